@@ -0,0 +1,230 @@
+use legion::prelude::*;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::Height;
+use crate::Building;
+use crate::Owned;
+use crate::Owns;
+use crate::Settlement;
+use crate::Sea;
+use crate::Lake;
+use crate::PixelGrid;
+use crate::path::step_cost;
+use crate::tags::TagEvents;
+use crate::tags::TagKind;
+use crate::validate::Breach;
+use crate::symbol::Symbol;
+
+const SCALE: f32 = 1000.;
+const UNREACHED: u32 = u32::MAX;
+
+pub struct Admin {
+    pub settlements: BTreeSet<Entity>,
+    pub owner: Vec<Option<Entity>>,
+    dist: Vec<u32>,
+    subscriber: Option<usize>,
+}
+
+impl Admin {
+    pub fn new() -> Self {
+        Admin {
+            settlements: BTreeSet::new(),
+            owner: Vec::new(),
+            dist: Vec::new(),
+            subscriber: None,
+        }
+    }
+}
+
+fn passable(world: &World, pixel: Entity) -> bool {
+    world.get_tag::<Sea>(pixel).is_none() && world.get_tag::<Lake>(pixel).is_none()
+}
+
+fn step(world: &World, from: Entity, to: Entity, road: usize) -> u32 {
+    let height = world.get_component::<Height>(from).map_or(0., |height| height.item);
+    let height_next = world.get_component::<Height>(to).map_or(0., |height| height.item);
+    let road = world.get_component::<Building>(from).map_or(0., |building| building.item[road]);
+
+    (SCALE * step_cost(height, height_next, road)) as u32
+}
+
+// Plain multi-source dijkstra; a pixel is only taken over when the new
+// settlement is strictly closer, so seeding a new settlement or the border
+// of a vacated area both converge to the same partition as a full rebuild.
+fn spread(world: &World, grid: &PixelGrid, admin: &mut Admin, mut heap: BinaryHeap<Reverse<(u32, usize)>>, road: usize, range: u32, changed: &mut Vec<usize>) {
+    while let Some(Reverse((d, index))) = heap.pop() {
+        if d > admin.dist[index] {
+            continue;
+        }
+
+        let pixel = grid.get(index);
+        let owner = admin.owner[index];
+        let neighb = grid.neighb(world, index);
+
+        for next in neighb.iter() {
+            let pixel_next = grid.get(next);
+
+            if !passable(world, pixel_next) {
+                continue;
+            }
+
+            let d = d.saturating_add(step(world, pixel, pixel_next, road));
+
+            if d <= range && d < admin.dist[next] {
+                admin.dist[next] = d;
+                admin.owner[next] = owner;
+                changed.push(next);
+                heap.push(Reverse((d, next)));
+            }
+        }
+    }
+}
+
+pub fn assign_ownership(world: &mut World, resources: &mut Resources) {
+    let defines = match resources.get::<Defines>() {
+        Some(defines) => defines,
+        None => return,
+    };
+    let grid = match resources.get::<PixelGrid>() {
+        Some(grid) => grid,
+        None => return,
+    };
+    let mut admin = match resources.get_mut::<Admin>() {
+        Some(admin) => admin,
+        None => return,
+    };
+
+    let settlements = match resources.get_mut::<TagEvents>() {
+        Some(mut events) if admin.subscriber.is_some() => {
+            let mut settlements = admin.settlements.clone();
+
+            for change in events.read(admin.subscriber.unwrap()).into_iter().filter(|change| change.kind == TagKind::Settlement) {
+                match change.added {
+                    true => settlements.insert(change.entity),
+                    false => settlements.remove(&change.entity),
+                };
+            }
+
+            settlements
+        }
+        events => {
+            if let Some(mut events) = events {
+                admin.subscriber = Some(events.subscribe());
+            }
+
+            <Read<Index>>::query()
+                .filter(tag::<Settlement>())
+                .iter_entities(&*world)
+                .map(|(settlement, _)| settlement)
+                .collect::<BTreeSet<Entity>>()
+        }
+    };
+
+    if settlements == admin.settlements {
+        return;
+    }
+
+    if admin.owner.len() != grid.item.len() {
+        admin.owner = vec![None; grid.item.len()];
+        admin.dist = vec![UNREACHED; grid.item.len()];
+    }
+
+    let road = defines.building_i[&Symbol::new("Road")];
+    let range = (SCALE * defines.admin_range) as u32;
+    let removed: BTreeSet<Entity> = admin.settlements.difference(&settlements).cloned().collect();
+    let added: Vec<Entity> = settlements.difference(&admin.settlements).cloned().collect();
+    let mut changed = Vec::new();
+    let mut heap = BinaryHeap::new();
+
+    if !removed.is_empty() {
+        let mut freed = Vec::new();
+
+        for index in 0..admin.owner.len() {
+            if let Some(owner) = admin.owner[index] {
+                if removed.contains(&owner) {
+                    admin.owner[index] = None;
+                    admin.dist[index] = UNREACHED;
+                    freed.push(index);
+                    changed.push(index);
+                }
+            }
+        }
+
+        for &index in freed.iter() {
+            for next in grid.neighb(world, index).iter() {
+                if admin.owner[next].is_some() {
+                    heap.push(Reverse((admin.dist[next], next)));
+                }
+            }
+        }
+    }
+
+    for &settlement in added.iter() {
+        if let Some(index) = world.get_component::<Index>(settlement).map(|index| index.item) {
+            admin.owner[index] = Some(settlement);
+            admin.dist[index] = 0;
+            changed.push(index);
+            heap.push(Reverse((0, index)));
+        }
+    }
+
+    spread(world, &grid, &mut admin, heap, road, range, &mut changed);
+
+    changed.sort();
+    changed.dedup();
+
+    let mut affected: BTreeSet<Entity> = removed.clone();
+
+    for &index in changed.iter() {
+        let pixel = grid.get(index);
+
+        if let Some(old) = world.get_component::<Owned>(pixel).map(|owned| owned.item) {
+            affected.insert(old);
+        }
+
+        match admin.owner[index] {
+            Some(owner) => {
+                affected.insert(owner);
+                world.add_component(pixel, Owned { item: owner }).unwrap();
+            },
+            None => {
+                let _ = world.remove_component::<Owned>(pixel);
+            },
+        }
+    }
+
+    let mut owns: HashMap<Entity, Vec<Entity>> = affected
+        .iter()
+        .filter(|settlement| settlements.contains(settlement))
+        .map(|&settlement| (settlement, Vec::new()))
+        .collect();
+
+    for (index, owner) in admin.owner.iter().enumerate() {
+        if let Some(pixels) = owner.and_then(|owner| owns.get_mut(&owner)) {
+            pixels.push(grid.get(index));
+        }
+    }
+
+    for settlement in removed.iter() {
+        let _ = world.remove_component::<Owns>(*settlement);
+    }
+    for (settlement, pixels) in owns.into_iter() {
+        world.add_component(settlement, Owns { item: pixels }).unwrap();
+    }
+
+    admin.settlements = settlements;
+}
+
+pub fn owners_alive(world: &World, _: &Resources, _: f32) -> Vec<Breach> {
+    Read::<Owned>::query()
+        .iter_entities(world)
+        .filter(|(_, owned)| !world.is_alive(owned.item))
+        .map(|(entity, owned)| Breach { entity: Some(entity), message: format!("owner {:?} is gone", owned.item) })
+        .collect()
+}
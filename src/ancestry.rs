@@ -0,0 +1,179 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::collections::HashMap;
+
+use crate::Index;
+use crate::Pop;
+use crate::Name;
+use crate::Settlement;
+use crate::Defines;
+use crate::symbol::Symbol;
+use crate::market::Market;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::random::WorldRng;
+
+#[derive(Clone, Deserialize)]
+pub struct AncestryDefines {
+    pub year: usize,
+    pub flow: f32,
+    pub drift: f32,
+    pub min_share: f32,
+}
+
+// Shares of a settlement's people by founding lineage, named after the
+// settlement the lineage started from, largest first and summing to one.
+pub struct Ancestry { pub item: Vec<(Symbol, f32)> }
+
+impl Ancestry {
+    pub fn share(&self, lineage: Symbol) -> f32 {
+        self.item.iter().find(|&&(l, _)| l == lineage).map_or(0., |&(_, share)| share)
+    }
+
+    pub fn dominant(&self) -> Option<Symbol> {
+        self.item.first().map(|&(lineage, _)| lineage)
+    }
+}
+
+pub struct Lineages {
+    pub period: usize,
+    tick: usize,
+}
+
+impl Lineages {
+    pub fn new(period: usize) -> Self {
+        Lineages {
+            period,
+            tick: 0,
+        }
+    }
+}
+
+fn normalize(shares: HashMap<Symbol, f32>, min_share: f32) -> Vec<(Symbol, f32)> {
+    let mut item: Vec<(Symbol, f32)> = shares.into_iter().filter(|&(_, share)| share >= min_share).collect();
+    let total: f32 = item.iter().map(|&(_, share)| share).sum();
+
+    for (_, share) in item.iter_mut() {
+        *share /= total.max(1e-9);
+    }
+
+    item.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.as_str().cmp(b.0.as_str())));
+    item
+}
+
+// Emigrants carry their home's ancestry to the place they found.
+pub fn found(world: &mut World, from: Entity, to: Entity) {
+    if let Some(item) = world.get_component::<Ancestry>(from).map(|ancestry| ancestry.item.clone()) {
+        world.add_component(to, Ancestry { item }).unwrap();
+    }
+}
+
+// Newcomers making up `share` of the people after they arrive.
+pub fn admix(world: &mut World, settlement: Entity, incoming: &Vec<(Symbol, f32)>, share: f32, min_share: f32) {
+    let mut shares: HashMap<Symbol, f32> = HashMap::new();
+
+    if let Some(ancestry) = world.get_component::<Ancestry>(settlement) {
+        for &(lineage, s) in ancestry.item.iter() {
+            *shares.entry(lineage).or_insert(0.) += s * (1. - share);
+        }
+    }
+    for &(lineage, s) in incoming.iter() {
+        *shares.entry(lineage).or_insert(0.) += s * share;
+    }
+
+    world.add_component(settlement, Ancestry { item: normalize(shares, min_share) }).unwrap();
+}
+
+// Roughly normal noise with unit variance.
+fn noise<R: Rng>(rng: &mut R) -> f32 {
+    (0..12).map(|_| rng.gen::<f32>()).sum::<f32>() - 6.
+}
+
+// Once a year settlements trading in one market zone exchange `flow` of their
+// people with the zone as a whole, and every lineage's share drifts by
+// chance, the more so the smaller the settlement. A settlement without
+// ancestry yet starts out as its own lineage.
+pub fn mix_ancestry(world: &mut World, resources: &mut Resources) {
+    let mut lineages = match resources.get_mut::<Lineages>() {
+        Some(lineages) => lineages,
+        None => return,
+    };
+
+    lineages.tick += 1;
+
+    if lineages.period == 0 || lineages.tick % lineages.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let a = &defines.ancestry;
+    let market = resources.get::<Market>();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+    let mut rng = resources.get::<WorldRng>().unwrap().get("ancestry");
+
+    let query = <(Read<Index>, Read<Pop>, TryRead<Ancestry>)>::query().filter(tag::<Settlement>());
+    let settlements: Vec<(Entity, usize, f32, Vec<(Symbol, f32)>)> = query
+        .iter_entities(&*world)
+        .map(|(settlement, (index, pop, ancestry))| {
+            let item = match ancestry {
+                Some(ancestry) => ancestry.item.clone(),
+                None => {
+                    let lineage = world.get_component::<Name>(settlement).map_or_else(|| Symbol::new(&format!("pixel {}", index.item)), |name| name.item);
+
+                    vec![(lineage, 1.)]
+                }
+            };
+
+            (settlement, index.item, pop.item, item)
+        })
+        .collect();
+
+    let zone = |settlement: &Entity| market.as_ref().and_then(|market| market.zone.get(settlement).cloned());
+    let mut pools: HashMap<usize, (HashMap<Symbol, f32>, f32)> = HashMap::new();
+
+    for (settlement, _, pop, item) in settlements.iter() {
+        if let Some(z) = zone(settlement) {
+            let pool = pools.entry(z).or_insert_with(|| (HashMap::new(), 0.));
+
+            for &(lineage, share) in item.iter() {
+                *pool.0.entry(lineage).or_insert(0.) += share * pop;
+            }
+
+            pool.1 += pop;
+        }
+    }
+
+    for (settlement, index, pop, item) in settlements.into_iter() {
+        let mut shares: HashMap<Symbol, f32> = item.iter().cloned().collect();
+
+        if let Some((pool, total)) = zone(&settlement).and_then(|z| pools.get(&z)) {
+            for share in shares.values_mut() {
+                *share *= 1. - a.flow;
+            }
+            for (&lineage, &people) in pool.iter() {
+                *shares.entry(lineage).or_insert(0.) += a.flow * people / total.max(1e-9);
+            }
+        }
+
+        for share in shares.values_mut() {
+            let spread = (*share * (1. - *share) / (2. * pop.max(1.))).sqrt();
+
+            *share = (*share + a.drift * spread * noise(&mut rng)).max(0.);
+        }
+
+        let before = item.first().map(|&(lineage, _)| lineage);
+        let ancestry = Ancestry { item: normalize(shares, a.min_share) };
+
+        if let (Some(chronicle), Some(before), Some(after)) = (chronicle.as_mut(), before, ancestry.dominant()) {
+            if before != after {
+                chronicle.record(index, Symbol::new("Lineage"), format!("most people of {} now descend from {}", site_name(world, settlement), after));
+            }
+        }
+
+        world.add_component(settlement, ancestry).unwrap();
+    }
+}
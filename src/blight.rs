@@ -0,0 +1,166 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::Index;
+use crate::Settlement;
+use crate::Defines;
+use crate::PixelGrid;
+use crate::admin::Admin;
+use crate::crops::Crop;
+use crate::symbol::Symbol;
+use crate::modifier::Modifier;
+use crate::modifier::Modifiers;
+use crate::modifier::add_modifier;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::random::WorldRng;
+
+// Each year's harvest of a settlement growing a crop is scaled by a draw
+// within `variability` of a normal year. `chance` is per settlement and year
+// of a blight breaking out, `spread` that of it reaching each neighbour
+// growing the same crop; a blighted harvest is scaled by `loss` for
+// `duration` ticks.
+#[derive(Clone, Deserialize)]
+pub struct BlightDefines {
+    pub year: usize,
+    pub variability: f32,
+    pub chance: f32,
+    pub spread: f32,
+    pub loss: f32,
+    pub duration: u32,
+}
+
+pub struct Blights {
+    pub period: usize,
+    pub struck: usize,
+    tick: usize,
+}
+
+impl Blights {
+    pub fn new(period: usize) -> Self {
+        Blights {
+            period,
+            struck: 0,
+            tick: 0,
+        }
+    }
+}
+
+// Settlements whose land borders each other's, by the administrative partition.
+fn neighbours(world: &World, grid: &PixelGrid, admin: &Admin) -> BTreeMap<Entity, BTreeSet<Entity>> {
+    let mut neighbours: BTreeMap<Entity, BTreeSet<Entity>> = BTreeMap::new();
+
+    for (index, owner) in admin.owner.iter().enumerate() {
+        let owner = match owner {
+            Some(owner) => *owner,
+            None => continue,
+        };
+
+        for next in grid.neighb(world, index).iter() {
+            if let Some(Some(other)) = admin.owner.get(next) {
+                if *other != owner {
+                    neighbours.entry(owner).or_default().insert(*other);
+                }
+            }
+        }
+    }
+
+    neighbours
+}
+
+// Once a year, ahead of the harvest, every settlement growing a crop draws
+// how good a year it has, blights already on the land may reach neighbours
+// growing the same crop, and new ones may break out anywhere.
+pub fn spread_blight(world: &mut World, resources: &mut Resources) {
+    let mut blights = match resources.get_mut::<Blights>() {
+        Some(blights) => blights,
+        None => return,
+    };
+
+    blights.tick += 1;
+
+    if blights.period == 0 || blights.tick % blights.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let b = &defines.blight;
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let admin = resources.get::<Admin>().unwrap();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+    let mut rng = resources.get::<WorldRng>().unwrap().get("blight");
+
+    let stat = Symbol::new("harvest");
+    let season = Symbol::new("Season");
+    let blight = Symbol::new("Blight");
+
+    let query = <(Read<Index>, Read<Crop>, TryRead<Modifiers>)>::query().filter(tag::<Settlement>());
+    let farming: Vec<(Entity, usize, usize, bool)> = query
+        .iter_entities(&*world)
+        .map(|(settlement, (index, crop, modifiers))| (
+            settlement, index.item, crop.item, modifiers.map_or(false, |modifiers| modifiers.from(blight)),
+        ))
+        .collect();
+    let site: HashMap<Entity, usize> = farming.iter().map(|&(settlement, index, _, _)| (settlement, index)).collect();
+    let crop: HashMap<Entity, usize> = farming.iter().map(|&(settlement, _, crop, _)| (settlement, crop)).collect();
+    let blighted: HashSet<Entity> = farming.iter().filter(|&&(_, _, _, blighted)| blighted).map(|&(settlement, _, _, _)| settlement).collect();
+    let neighbours = neighbours(&*world, &grid, &admin);
+    let mut struck = Vec::new();
+    let mut caught = HashSet::new();
+
+    for &(settlement, _, item, _) in farming.iter() {
+        let year = 1. + b.variability * rng.gen_range(-1., 1.);
+
+        if let Some(mut modifiers) = world.get_component_mut::<Modifiers>(settlement) {
+            modifiers.item.retain(|modifier| modifier.source != season);
+        }
+
+        add_modifier(world, settlement, Modifier { stat, factor: year, left: Some(b.year as u32), source: season });
+
+        if !blighted.contains(&settlement) {
+            continue;
+        }
+
+        for &next in neighbours.get(&settlement).into_iter().flatten() {
+            if crop.get(&next) != Some(&item) || blighted.contains(&next) || caught.contains(&next) {
+                continue;
+            }
+            if rng.gen::<f32>() < b.spread {
+                caught.insert(next);
+                struck.push((next, Some(settlement)));
+            }
+        }
+    }
+
+    for &(settlement, _, _, _) in farming.iter() {
+        if !blighted.contains(&settlement) && !caught.contains(&settlement) && rng.gen::<f32>() < b.chance {
+            caught.insert(settlement);
+            struck.push((settlement, None));
+        }
+    }
+
+    for (settlement, from) in struck.into_iter() {
+        let index = site[&settlement];
+
+        add_modifier(world, settlement, Modifier { stat, factor: b.loss, left: Some(b.duration), source: blight });
+
+        if let Some(chronicle) = chronicle.as_deref_mut() {
+            let text = match from {
+                Some(from) => format!("blight spread from {} to the fields of {}", site_name(world, from), site_name(world, settlement)),
+                None => format!("blight broke out in the fields of {}", site_name(world, settlement)),
+            };
+
+            chronicle.record(index, blight, text);
+        }
+
+        blights.struck += 1;
+    }
+}
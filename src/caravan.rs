@@ -0,0 +1,235 @@
+use legion::prelude::*;
+
+use rand::Rng;
+
+use crate::Defines;
+use crate::Location;
+use crate::Speed;
+use crate::Capacity;
+use crate::Height;
+use crate::Building;
+use crate::Navigable;
+use crate::Index;
+use crate::PixelGrid;
+use crate::garrison::Coverage;
+use crate::scratch::with_scratch;
+use crate::path::LAND;
+use crate::path::WATER;
+use crate::path::RIVER_FACTOR;
+use crate::path::step_cost;
+use crate::path::PathRequests;
+use crate::path::FlowFields;
+use crate::path::Target;
+use crate::trade::Trade;
+use crate::trade::Dispatch;
+use crate::guild::Stock;
+use crate::symbol::Symbol;
+use crate::random::WorldRng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Caravan;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ship;
+
+pub struct Cargo { pub item: Vec<f32> }
+pub struct Route { pub item: Vec<Entity> }
+pub struct Progress { pub item: f32 }
+
+// A load from `from` to `to` waiting on solve_paths for its route.
+pub struct Order {
+    pub from: Entity,
+    pub to: Entity,
+    pub by_sea: bool,
+}
+
+// Asks for a route for `agent`, which finds a PathResult on itself the next
+// tick. False when either end is not on the map.
+pub fn plan_route(world: &World, requests: &mut PathRequests, agent: Entity, from: Entity, to: Entity, by_sea: bool) -> bool {
+    let (from, to) = match (world.get_component::<Index>(from), world.get_component::<Index>(to)) {
+        (Some(from), Some(to)) => (from.item, to.item),
+        _ => return false,
+    };
+    let cost = match by_sea {
+        true => WATER,
+        false => LAND,
+    };
+
+    requests.submit(agent, from, to, cost);
+
+    true
+}
+
+pub fn spawn_caravan(world: &mut World, from: Entity, route: Vec<Entity>, by_sea: bool, speed: f32, capacity: f32, cargo: Vec<f32>) -> Entity {
+    let components = vec![(
+        Location { item: from },
+        Speed { item: speed },
+        Capacity { item: capacity },
+        Cargo { item: cargo },
+        Route { item: route },
+        Progress { item: 0. },
+    )];
+
+    match by_sea {
+        true => world.insert((Ship,), components)[0],
+        false => world.insert((Caravan,), components)[0],
+    }
+}
+
+// A caravan at the end of its route unloads into the stock of the settlement
+// it reached, and is gone.
+pub fn deliver_cargo(world: &mut World, _: &mut Resources) {
+    let arrived: Vec<(Entity, Entity, Vec<f32>)> = <(Read<Location>, Read<Route>, Read<Cargo>)>::query()
+        .iter_entities(&*world)
+        .filter(|(_, (_, route, _))| route.item.is_empty())
+        .map(|(caravan, (location, _, cargo))| (caravan, location.item, cargo.item.clone()))
+        .collect();
+
+    for (caravan, at, cargo) in arrived.into_iter() {
+        if let Some(mut stock) = world.get_component_mut::<Stock>(at) {
+            for (g, amount) in cargo.into_iter().enumerate() {
+                stock.item[g] += amount;
+            }
+        }
+
+        world.delete(caravan);
+    }
+}
+
+// Bandits keep to the wilds, so a caravan's chance of being raided each tick
+// grows with its distance from the nearest settlement up to `reach`, while a
+// ship at sea is always in reach of pirates. Garrisons covering the pixel
+// keep both off. A raid takes `loot` of the cargo.
+pub fn raid_caravans(world: &mut World, resources: &mut Resources) {
+    let (mut flows, grid) = match (resources.get_mut::<FlowFields>(), resources.get::<PixelGrid>()) {
+        (Some(flows), Some(grid)) => (flows, grid),
+        _ => return,
+    };
+    let defines = resources.get::<Defines>().unwrap();
+    let c = &defines.caravans;
+    let mut rng = resources.get::<WorldRng>().unwrap().get("raids");
+
+    let raided: Vec<Entity> = {
+        let world = &*world;
+        let field = flows.get(world, &grid, &LAND, Target::Settlement);
+
+        <(Read<Location>, Read<Cargo>)>::query()
+            .iter_entities(world)
+            .filter_map(|(caravan, (location, _))| {
+                let index = world.get_component::<Index>(location.item)?.item;
+                let cover = world.get_component::<Coverage>(location.item).map_or(0., |coverage| coverage.item);
+                let chance = match world.get_tag::<Ship>(caravan) {
+                    Some(_) => c.pirates,
+                    None => c.bandits * field.dist(index).map_or(1., |dist| (dist / c.reach.max(1e-3)).min(1.)),
+                };
+
+                if rng.gen::<f32>() < chance / (1. + cover.max(0.)) { Some(caravan) } else { None }
+            })
+            .collect()
+    };
+
+    for &caravan in raided.iter() {
+        if let Some(mut cargo) = world.get_component_mut::<Cargo>(caravan) {
+            for amount in cargo.item.iter_mut() {
+                *amount *= 1. - c.loot;
+            }
+        }
+    }
+
+    if let Some(mut dispatch) = resources.get_mut::<Dispatch>() {
+        dispatch.raided += raided.len();
+    }
+}
+
+// Fills up to `capacity` by weight with whatever earns the most per unit of
+// weight after spoiling for `ticks` on the way, leaving out goods that would
+// sell for less than they cost.
+pub fn pick_cargo(trade: &Trade, stock: &Vec<f32>, from: &Vec<f32>, to: &Vec<f32>, capacity: f32, ticks: f32) -> Vec<f32> {
+    let mut cargo = vec![0.; stock.len()];
+    let mut margins: Vec<(usize, f32)> = (0..stock.len())
+        .map(|g| (g, (to[g] * trade.kept(g, ticks) - from[g]) / trade.weight[g]))
+        .filter(|&(_, margin)| margin > 0.)
+        .collect();
+    let mut room = capacity;
+
+    margins.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    for (g, _) in margins.into_iter() {
+        let amount = stock[g].max(0.).min(room / trade.weight[g]);
+
+        cargo[g] = amount;
+        room -= amount * trade.weight[g];
+
+        if room <= 0. {
+            break;
+        }
+    }
+
+    cargo
+}
+
+// Routes are stored goal-first so the next pixel is always at the back.
+// Ships ignore roads and slope; land caravans are slowed by climbing and
+// sped up by the Road level of the pixel they are leaving, or by following a
+// navigable river.
+pub fn move_caravans() -> Box<dyn Schedulable> {
+    SystemBuilder::new("move_caravans")
+        .read_resource::<Defines>()
+        .read_component::<Height>()
+        .read_component::<Building>()
+        .with_query(<(Read<Location>, Read<Speed>, Read<Route>)>::query())
+        .with_query(<(Write<Location>, Write<Route>, Write<Progress>)>::query())
+        .with_query(<Write<Cargo>>::query())
+        .build(|_, world, defines, (plan_query, move_query, cargo_query)| {
+            let road = defines.building_i[&Symbol::new("Road")];
+            let mut steps = with_scratch(|scratch| scratch.entity_floats.take());
+
+            for (caravan, (location, speed, route)) in plan_query.iter_entities(&*world) {
+                let next = match route.item.last() {
+                    Some(&next) => next,
+                    None => continue,
+                };
+                let step = match world.get_tag::<Ship>(caravan) {
+                    Some(_) => speed.item,
+                    None => {
+                        let height = world.get_component::<Height>(location.item).map_or(0., |height| height.item);
+                        let height_next = world.get_component::<Height>(next).map_or(0., |height| height.item);
+                        let road = world.get_component::<Building>(location.item).map_or(0., |building| building.item[road]);
+
+                        let river = world.get_tag::<Navigable>(location.item).is_some() && world.get_tag::<Navigable>(next).is_some();
+
+                        match river {
+                            true => speed.item / (step_cost(height, height_next, road) * RIVER_FACTOR),
+                            false => speed.item / step_cost(height, height_next, road),
+                        }
+                    }
+                };
+
+                steps.insert(caravan, step);
+            }
+
+            for (caravan, (mut location, mut route, mut progress)) in move_query.iter_entities_mut(world) {
+                if let Some(step) = steps.get(&caravan) {
+                    progress.item += step;
+
+                    while progress.item >= 1. {
+                        match route.item.pop() {
+                            Some(next) => location.item = next,
+                            None => break,
+                        }
+
+                        progress.item -= 1.;
+                    }
+                }
+            }
+
+            with_scratch(|scratch| scratch.entity_floats.give(steps));
+
+            let trade = Trade::new(defines);
+
+            for mut cargo in cargo_query.iter_mut(world) {
+                for (g, amount) in cargo.item.iter_mut().enumerate() {
+                    *amount *= 1. - trade.spoilage[g];
+                }
+            }
+        })
+}
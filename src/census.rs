@@ -0,0 +1,90 @@
+use legion::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Pop;
+use crate::Settlement;
+use crate::guild::Stock;
+use crate::validate::Breach;
+
+pub struct SettlementCensus {
+    pub pop: f32,
+    pub stock: Vec<f32>,
+}
+
+pub struct Census {
+    pub settlements: HashMap<Entity, SettlementCensus>,
+    pub pop: f32,
+    pub stock: Vec<f32>,
+    pub period: usize,
+    tick: usize,
+}
+
+impl Census {
+    pub fn new(period: usize) -> Self {
+        Census {
+            settlements: HashMap::new(),
+            pop: 0.,
+            stock: Vec::new(),
+            period,
+            tick: 0,
+        }
+    }
+
+    pub fn get(&self, settlement: Entity) -> Option<&SettlementCensus> {
+        self.settlements.get(&settlement)
+    }
+
+    pub fn largest(&self, n: usize) -> Vec<(Entity, f32)> {
+        let mut out: Vec<(Entity, f32)> = self.settlements
+            .iter()
+            .map(|(&settlement, census)| (settlement, census.pop))
+            .collect();
+        out.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        out.truncate(n);
+
+        out
+    }
+}
+
+pub fn take_census() -> Box<dyn Schedulable> {
+    SystemBuilder::new("take_census")
+        .read_resource::<Defines>()
+        .write_resource::<Census>()
+        .with_query(<(TryRead<Pop>, TryRead<Stock>)>::query().filter(tag::<Settlement>()))
+        .build(|_, world, (defines, census), query| {
+            census.tick += 1;
+
+            if census.tick % census.period.max(1) != 0 {
+                return;
+            }
+
+            let goods = defines.good_i.len();
+
+            census.settlements.clear();
+            census.pop = 0.;
+            census.stock = vec![0.; goods];
+
+            for (settlement, (pop, stock)) in query.iter_entities(&*world) {
+                let pop = pop.map_or(0., |pop| pop.item);
+                let stock = stock.map_or(vec![0.; goods], |stock| stock.item.clone());
+
+                census.pop += pop;
+
+                for (total, amount) in census.stock.iter_mut().zip(stock.iter()) {
+                    *total += amount;
+                }
+
+                census.settlements.insert(settlement, SettlementCensus { pop, stock });
+            }
+        })
+}
+
+pub fn pop_non_negative(world: &World, _: &Resources, epsilon: f32) -> Vec<Breach> {
+    Read::<Pop>::query()
+        .iter_entities(world)
+        .filter(|(_, pop)| !pop.item.is_finite() || pop.item < -epsilon)
+        .map(|(entity, pop)| Breach { entity: Some(entity), message: format!("pop is {}", pop.item) })
+        .collect()
+}
@@ -0,0 +1,169 @@
+use legion::prelude::*;
+
+use prototype4::map::ProvBuilder;
+use prototype4::map::MONTHS;
+use prototype4::map::calc_veget;
+
+use num::clamp;
+
+use std::collections::HashSet;
+
+use crate::Height;
+use crate::Heat;
+use crate::HeatBase;
+use crate::Water;
+use crate::WaterBase;
+use crate::Rain;
+use crate::River;
+use crate::Veget;
+use crate::Neighb;
+use crate::Index;
+use crate::PixelGrid;
+use crate::tags::TagEvents;
+use crate::tags::TagKind;
+use crate::Sea;
+use crate::Lake;
+use crate::Ice;
+use crate::fixed::Real;
+use crate::fixed::real;
+use crate::fixed::to_f32;
+
+pub struct Climate {
+    pub temp: Real,
+    pub sea_level: Real,
+    pub temp_rate: Real,
+    pub sea_rate: Real,
+    pub sea_step: Real,
+    sea_applied: Real,
+}
+
+impl Climate {
+    pub fn new(temp_rate: f32, sea_rate: f32, sea_step: f32) -> Self {
+        Climate {
+            temp: real(0.),
+            sea_level: real(0.),
+            temp_rate: real(temp_rate),
+            sea_rate: real(sea_rate),
+            sea_step: real(sea_step),
+            sea_applied: real(0.),
+        }
+    }
+}
+
+// The heat and rain of every pixel by index for each month of a map made
+// with seasons. As each month begins the pixels take its values, and heat
+// then follows the global offset from there as before.
+pub struct Calendar {
+    pub year: usize,
+    pub month: usize,
+    heat: Vec<Vec<f32>>,
+    rain: Vec<Vec<f32>>,
+    tick: usize,
+}
+
+impl Calendar {
+    pub fn new(map: &ProvBuilder) -> Option<Self> {
+        let year = map.seasons.as_ref()?.year;
+        let pixels = map.size * map.size;
+
+        if map.monthly_temp.is_empty() {
+            return None;
+        }
+
+        Some(Calendar {
+            year,
+            month: 0,
+            heat: (0..MONTHS).map(|month| (0..pixels).map(|i| map.temp_at(i, month) as f32).collect()).collect(),
+            rain: (0..MONTHS).map(|month| (0..pixels).map(|i| map.cloud_at(i, month) as f32).collect()).collect(),
+            tick: 0,
+        })
+    }
+}
+
+pub fn turn_seasons(world: &mut World, resources: &mut Resources) {
+    let mut calendar = match resources.get_mut::<Calendar>() {
+        Some(calendar) => calendar,
+        None => return,
+    };
+    let length = (calendar.year / MONTHS).max(1);
+
+    calendar.tick += 1;
+
+    if calendar.tick % length != 0 {
+        return;
+    }
+
+    let month = (calendar.tick / length) % MONTHS;
+
+    calendar.month = month;
+
+    for (index, mut base, mut rain) in <(Read<Index>, Write<HeatBase>, Write<Rain>)>::query().iter_mut(world) {
+        base.item = calendar.heat[month][index.item];
+        rain.item = calendar.rain[month][index.item];
+    }
+}
+
+// Heat follows the global offset every tick, vegetation follows heat, and the
+// coastline is only reclassified once the sea has moved by at least `sea_step`.
+// Flooding spreads one ring of pixels per pass, inward from the existing sea,
+// and a falling sea gives up one ring per pass outward from the coast.
+pub fn climate_drift() -> Box<dyn Schedulable> {
+    SystemBuilder::new("climate_drift")
+        .write_resource::<Climate>()
+        .write_resource::<TagEvents>()
+        .read_resource::<PixelGrid>()
+        .with_query(<(Read<HeatBase>, Write<Heat>)>::query())
+        .with_query(<(Read<Heat>, Read<Water>, Write<Veget>)>::query().filter(!tag::<Sea>() & !tag::<Lake>() & !tag::<Ice>()))
+        .with_query(<(Read<Height>, Read<Index>, TryRead<Neighb>)>::query().filter(!tag::<Sea>()))
+        .with_query(<(Read<Height>, Read<Rain>, Read<Index>, TryRead<Neighb>)>::query().filter(tag::<Sea>()))
+        .build(|cmd, world, (climate, events, grid), (heat_query, veget_query, land_query, sea_query)| {
+            let (temp_rate, sea_rate) = (climate.temp_rate, climate.sea_rate);
+
+            climate.temp += temp_rate;
+            climate.sea_level += sea_rate;
+
+            for (base, mut heat) in heat_query.iter_mut(world) {
+                heat.item = to_f32(clamp(real(base.item) + climate.temp, real(0.), real(1.)));
+            }
+            for (heat, water, mut veget) in veget_query.iter_mut(world) {
+                veget.item = to_f32(calc_veget(real(heat.item), real(water.item)));
+            }
+
+            if (climate.sea_level - climate.sea_applied).abs() < climate.sea_step {
+                return;
+            }
+
+            climate.sea_applied = climate.sea_level;
+
+            let sea_level = to_f32(climate.sea_level);
+            let lands: HashSet<usize> = land_query.iter(&*world).map(|(_, index, _)| index.item).collect();
+            let mut seas = HashSet::new();
+
+            for (pixel, (height, rain, index, neighb)) in sea_query.iter_entities(&*world) {
+                if height.item > sea_level && grid.around(neighb.as_deref(), index.item).iter().any(|n| lands.contains(&n)) {
+                    cmd.remove_tag::<Sea>(pixel);
+                    events.push(pixel, TagKind::Sea, false);
+                    cmd.add_component(pixel, Water { item: rain.item });
+                    cmd.add_component(pixel, WaterBase { item: rain.item });
+                } else {
+                    seas.insert(index.item);
+                }
+            }
+
+            for (pixel, (height, index, neighb)) in land_query.iter_entities(&*world) {
+                if height.item <= sea_level && grid.around(neighb.as_deref(), index.item).iter().any(|n| seas.contains(&n)) {
+                    if world.get_tag::<Lake>(pixel).is_some() {
+                        cmd.remove_tag::<Lake>(pixel);
+                        events.push(pixel, TagKind::Lake, false);
+                    }
+
+                    cmd.add_tag(pixel, Sea);
+                    events.push(pixel, TagKind::Sea, true);
+                    cmd.add_component(pixel, Water { item: 0. });
+                    cmd.add_component(pixel, WaterBase { item: 0. });
+                    cmd.add_component(pixel, River { item: 0. });
+                    cmd.add_component(pixel, Veget { item: 0. });
+                }
+            }
+        })
+}
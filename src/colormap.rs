@@ -0,0 +1,216 @@
+use image::Rgb;
+use image::RgbImage;
+
+use serde::Deserialize;
+
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+const DIVERGING: [[u8; 3]; 5] = [
+    [59, 76, 192],
+    [141, 176, 254],
+    [221, 221, 221],
+    [244, 154, 123],
+    [180, 4, 38],
+];
+
+// Hypsometric tints for land, lowland green through tan and brown to snow.
+const TERRAIN: [[u8; 3]; 7] = [
+    [0, 97, 71],
+    [16, 122, 47],
+    [232, 215, 125],
+    [161, 67, 0],
+    [130, 30, 30],
+    [110, 110, 110],
+    [255, 255, 255],
+];
+
+// Three by five pixel glyphs, a row to a byte with the leftmost pixel in
+// the highest of its three bits.
+const DIGITS: [[u8; 5]; 10] = [
+    [7, 5, 5, 5, 7],
+    [2, 6, 2, 2, 7],
+    [7, 1, 7, 4, 7],
+    [7, 1, 7, 1, 7],
+    [5, 5, 7, 1, 1],
+    [7, 4, 7, 1, 7],
+    [7, 4, 7, 5, 7],
+    [7, 1, 1, 2, 2],
+    [7, 5, 7, 5, 7],
+    [7, 5, 7, 1, 7],
+];
+
+const LETTERS: [[u8; 5]; 26] = [
+    [2, 5, 7, 5, 5],
+    [6, 5, 6, 5, 6],
+    [3, 4, 4, 4, 3],
+    [6, 5, 5, 5, 6],
+    [7, 4, 6, 4, 7],
+    [7, 4, 6, 4, 4],
+    [3, 4, 5, 5, 3],
+    [5, 5, 7, 5, 5],
+    [7, 2, 2, 2, 7],
+    [1, 1, 1, 5, 2],
+    [5, 5, 6, 5, 5],
+    [4, 4, 4, 4, 7],
+    [5, 7, 7, 5, 5],
+    [6, 5, 5, 5, 5],
+    [2, 5, 5, 5, 2],
+    [6, 5, 6, 4, 4],
+    [2, 5, 5, 6, 3],
+    [6, 5, 6, 5, 5],
+    [3, 4, 2, 1, 6],
+    [7, 2, 2, 2, 2],
+    [5, 5, 5, 5, 7],
+    [5, 5, 5, 5, 2],
+    [5, 5, 7, 7, 5],
+    [5, 5, 2, 5, 5],
+    [5, 5, 2, 2, 2],
+    [7, 1, 2, 4, 7],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Colormap {
+    Grayscale,
+    Viridis,
+    Diverging,
+    Terrain,
+}
+
+// How a layer is exported: its colormap, a title above it and, with `bar`,
+// a colour bar labelled with the range below it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Style {
+    pub colormap: Colormap,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub bar: bool,
+}
+
+fn lerp_stops(stops: &[[u8; 3]], t: f64) -> Rgb<u8> {
+    let t = t.max(0.).min(1.) * (stops.len() - 1) as f64;
+    let i = (t.floor() as usize).min(stops.len() - 2);
+    let f = t - i as f64;
+
+    let mut out = [0; 3];
+
+    for c in 0..3 {
+        out[c] = (stops[i][c] as f64 * (1. - f) + stops[i + 1][c] as f64 * f) as u8;
+    }
+
+    Rgb(out)
+}
+
+impl Colormap {
+    pub fn color(&self, t: f64) -> Rgb<u8> {
+        match self {
+            Colormap::Grayscale => {
+                let val = (t.max(0.).min(1.) * 255.) as u8;
+
+                Rgb([val, val, val])
+            },
+            Colormap::Viridis => lerp_stops(&VIRIDIS, t),
+            Colormap::Diverging => lerp_stops(&DIVERGING, t),
+            Colormap::Terrain => lerp_stops(&TERRAIN, t),
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        c @ '0'..='9' => DIGITS[c as usize - '0' as usize],
+        c @ 'A'..='Z' => LETTERS[c as usize - 'A' as usize],
+        '-' => [0, 0, 7, 0, 0],
+        '.' => [0, 0, 0, 0, 2],
+        _ => [0; 5],
+    }
+}
+
+fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * 4 * scale
+}
+
+fn draw_text(img: &mut RgbImage, text: &str, x: u32, y: u32, scale: u32) {
+    for (n, c) in text.chars().enumerate() {
+        let left = x + n as u32 * 4 * scale;
+
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits >> (2 - col) & 1 == 0 {
+                    continue;
+                }
+
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (left + col * scale + dx, y + row as u32 * scale + dy);
+
+                        if px < img.width() && py < img.height() {
+                            img.put_pixel(px, py, Rgb([0, 0, 0]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn label(value: f64) -> String {
+    format!("{}", (value * 100.).round() / 100.)
+}
+
+// Pads `img` with white strips for the title and the colour bar `style`
+// asks for, lettered in a pixel font scaled to the image width.
+pub fn decorate(img: RgbImage, min: f64, max: f64, style: &Style) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let scale = (width / 256).max(1);
+    let top = if style.title.is_some() { 9 * scale } else { 0 };
+    let bottom = if style.bar { 19 * scale } else { 0 };
+
+    if top == 0 && bottom == 0 {
+        return img;
+    }
+
+    let mut out = RgbImage::from_pixel(width, height + top + bottom, Rgb([255, 255, 255]));
+
+    for (x, y, &pixel) in img.enumerate_pixels() {
+        out.put_pixel(x, y + top, pixel);
+    }
+
+    if let Some(title) = style.title.as_ref() {
+        let x = width.saturating_sub(text_width(title, scale)) / 2;
+
+        draw_text(&mut out, title, x, 2 * scale, scale);
+    }
+
+    if style.bar {
+        let y = height + top + 2 * scale;
+        let (left, right) = (4 * scale, width.saturating_sub(4 * scale));
+
+        for x in left..right {
+            let color = style.colormap.color((x - left) as f64 / (right - left).max(1) as f64);
+
+            for dy in 0..8 * scale {
+                out.put_pixel(x, y + dy, color);
+            }
+        }
+
+        let y = y + 10 * scale;
+        let (low, mid, high) = (label(min), label((min + max) / 2.), label(max));
+
+        draw_text(&mut out, &low, left, y, scale);
+        draw_text(&mut out, &mid, (width.saturating_sub(text_width(&mid, scale))) / 2, y, scale);
+        draw_text(&mut out, &high, right.saturating_sub(text_width(&high, scale)), y, scale);
+    }
+
+    out
+}
@@ -0,0 +1,325 @@
+use legion::prelude::*;
+use legion::systems::resource::Resources;
+
+use serde::Serialize;
+use serde::Deserialize;
+
+use ron::de::from_str;
+use ron::ser::to_string;
+
+use rayon::prelude::*;
+
+use std::mem::take;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::Defines;
+use crate::PixelGrid;
+use crate::Building;
+use crate::Name;
+use crate::symbol::Symbol;
+use crate::server::StateServer;
+use crate::path;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Command {
+    Build { pixel: usize, building: Symbol, level: f32 },
+    Demolish { pixel: usize, building: Symbol },
+    Rename { pixel: usize, name: Symbol },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stamped {
+    pub tick: u64,
+    pub client: u32,
+    pub seq: u32,
+    pub command: Command,
+}
+
+// Commands are scheduled `delay` ticks ahead so every participant holds them
+// before they are due, and run in (tick, client, seq) order so all of them
+// apply the same ones in the same order. Commands submitted here wait in
+// `outbox` to be sent to the other participants.
+pub struct Lockstep {
+    pub tick: u64,
+    pub delay: u64,
+    pub conflicts: Vec<Conflict>,
+    pending: BTreeMap<(u64, u32, u32), Command>,
+    seq: HashMap<u32, u32>,
+    outbox: Vec<Stamped>,
+}
+
+// Two clients wrote the same building slot or name of a pixel in one tick;
+// the write later in (client, seq) order is the one kept.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub tick: u64,
+    pub pixel: usize,
+    pub clients: Vec<u32>,
+}
+
+struct Change {
+    pixel: usize,
+    building: Option<Vec<f32>>,
+    name: Option<Symbol>,
+    conflict: Option<Conflict>,
+}
+
+impl Lockstep {
+    pub fn new(delay: u64) -> Self {
+        Lockstep {
+            tick: 0,
+            delay,
+            conflicts: Vec::new(),
+            pending: BTreeMap::new(),
+            seq: HashMap::new(),
+            outbox: Vec::new(),
+        }
+    }
+
+    pub fn submit(&mut self, client: u32, command: Command) -> Stamped {
+        let seq = self.seq.entry(client).or_insert(0);
+        let stamped = Stamped { tick: self.tick + self.delay.max(1), client, seq: *seq, command };
+
+        *seq += 1;
+
+        self.pending.insert((stamped.tick, stamped.client, stamped.seq), stamped.command.clone());
+        self.outbox.push(stamped.clone());
+
+        stamped
+    }
+
+    pub fn outgoing(&mut self) -> Vec<Stamped> {
+        take(&mut self.outbox)
+    }
+
+    // A command from another participant that arrives after its tick has run
+    // would desync the simulation, so it is refused.
+    pub fn receive(&mut self, stamped: Stamped) -> bool {
+        if stamped.tick <= self.tick {
+            return false;
+        }
+
+        self.pending.insert((stamped.tick, stamped.client, stamped.seq), stamped.command);
+
+        true
+    }
+
+    pub fn take(&mut self) -> Vec<Stamped> {
+        self.tick += 1;
+
+        let later = self.pending.split_off(&(self.tick + 1, 0, 0));
+        let due = std::mem::replace(&mut self.pending, later);
+
+        due
+            .into_iter()
+            .map(|((tick, client, seq), command)| Stamped { tick, client, seq, command })
+            .collect()
+    }
+}
+
+fn slot(defines: &Defines, command: &Command) -> Option<(usize, f32)> {
+    match command {
+        Command::Build { building, level, .. } => defines.building_i.get(building).map(|&i| (i, *level)),
+        Command::Demolish { building, .. } => defines.building_i.get(building).map(|&i| (i, 0.)),
+        Command::Rename { .. } => None,
+    }
+}
+
+fn pixel(command: &Command) -> usize {
+    match command {
+        Command::Build { pixel, .. } | Command::Demolish { pixel, .. } | Command::Rename { pixel, .. } => *pixel,
+    }
+}
+
+// Folds one pixel's commands, already in (tick, client, seq) order, into the
+// values to write, only reading the world so pixels can be done in parallel.
+fn change(world: &World, grid: &PixelGrid, defines: &Defines, pixel: usize, commands: &Vec<&Stamped>) -> Change {
+    let mut building = None;
+    let mut name = None;
+    let mut writers: HashMap<Option<usize>, u32> = HashMap::new();
+    let mut clients = HashSet::new();
+
+    for stamped in commands.iter() {
+        let key = match &stamped.command {
+            Command::Rename { name: new, .. } => {
+                name = Some(*new);
+                None
+            }
+            command => match slot(defines, command) {
+                Some((i, level)) => {
+                    let current = building.get_or_insert_with(|| world.get_component::<Building>(grid.get(pixel)).map(|b| b.item.clone()));
+
+                    if let Some(current) = current {
+                        current[i] = level;
+                    }
+
+                    Some(i)
+                }
+                None => continue,
+            }
+        };
+
+        if let Some(previous) = writers.insert(key, stamped.client) {
+            if previous != stamped.client {
+                clients.insert(previous);
+                clients.insert(stamped.client);
+            }
+        }
+    }
+
+    let conflict = if clients.is_empty() {
+        None
+    } else {
+        let mut clients: Vec<u32> = clients.into_iter().collect();
+
+        clients.sort();
+
+        Some(Conflict { tick: commands[0].tick, pixel, clients })
+    };
+
+    Change { pixel, building: building.flatten(), name, conflict }
+}
+
+// A frame from a client is either a command stamped by another participant,
+// or a bare command for this one to stamp under the client's id. Commands
+// submitted here are sent to everyone before the due ones are taken.
+fn exchange(lockstep: &mut Lockstep, server: &StateServer) {
+    for (client, text) in server.receive().into_iter() {
+        if let Ok(stamped) = from_str::<Stamped>(&text) {
+            lockstep.receive(stamped);
+        } else if let Ok(command) = from_str::<Command>(&text) {
+            lockstep.submit(client, command);
+        }
+    }
+
+    for stamped in lockstep.outgoing().into_iter() {
+        if let Ok(text) = to_string(&stamped) {
+            server.broadcast(&text);
+        }
+    }
+}
+
+pub fn apply_commands(world: &mut World, resources: &mut Resources) {
+    let commands = match resources.get_mut::<Lockstep>() {
+        Some(mut lockstep) => {
+            if let Some(server) = resources.get::<StateServer>() {
+                exchange(&mut lockstep, &server);
+            }
+
+            lockstep.conflicts.clear();
+            lockstep.take()
+        }
+        None => return,
+    };
+
+    if commands.is_empty() {
+        return;
+    }
+
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let defines = resources.get::<Defines>().unwrap();
+    let mut groups: BTreeMap<usize, Vec<&Stamped>> = BTreeMap::new();
+
+    for stamped in commands.iter() {
+        groups.entry(pixel(&stamped.command)).or_insert_with(Vec::new).push(stamped);
+    }
+
+    let road = defines.building_i[&Symbol::new("Road")];
+    let roads = commands.iter().any(|stamped| slot(&defines, &stamped.command).map_or(false, |(i, _)| i == road));
+    let groups: Vec<(usize, Vec<&Stamped>)> = groups.into_iter().collect();
+    let changes: Vec<Change> = {
+        let world = &*world;
+
+        groups
+            .par_iter()
+            .map(|(pixel, commands)| change(world, &grid, &defines, *pixel, commands))
+            .collect()
+    };
+
+    let mut conflicts = Vec::new();
+
+    for change in changes {
+        let entity = grid.get(change.pixel);
+
+        if let Some(item) = change.building {
+            if let Some(mut building) = world.get_component_mut::<Building>(entity) {
+                building.item = item;
+            }
+        }
+        if let Some(name) = change.name {
+            world.add_component(entity, Name { item: name }).ok();
+        }
+        if let Some(conflict) = change.conflict {
+            conflicts.push(conflict);
+        }
+    }
+
+    drop(grid);
+    drop(defines);
+
+    if roads {
+        path::roads_changed(resources);
+    }
+
+    resources.get_mut::<Lockstep>().unwrap().conflicts = conflicts;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(pixel: usize, name: &str) -> Command {
+        Command::Rename { pixel, name: Symbol::new(name) }
+    }
+
+    fn describe(commands: Vec<Stamped>) -> Vec<String> {
+        commands.into_iter().map(|stamped| format!("{} {} {} {:?}", stamped.tick, stamped.client, stamped.seq, stamped.command)).collect()
+    }
+
+    // Two participants on the same tick, each passing what it submits to the
+    // other, take the same commands on every tick and lose none of them.
+    #[test]
+    fn peers_converge() {
+        let mut a = Lockstep::new(2);
+        let mut b = Lockstep::new(2);
+        let mut taken = Vec::new();
+
+        for tick in 0..8 {
+            if tick < 6 {
+                a.submit(0, rename(tick, "North"));
+                b.submit(1, rename(tick, "South"));
+            }
+            if tick % 2 == 0 {
+                b.submit(1, rename(tick + 1, "East"));
+            }
+
+            for stamped in a.outgoing().into_iter() {
+                assert!(b.receive(stamped));
+            }
+            for stamped in b.outgoing().into_iter() {
+                assert!(a.receive(stamped));
+            }
+
+            let due = describe(a.take());
+
+            assert_eq!(due, describe(b.take()));
+            taken.extend(due);
+        }
+
+        assert_eq!(taken.len(), 6 * 2 + 4);
+    }
+
+    #[test]
+    fn late_commands_are_refused() {
+        let mut a = Lockstep::new(1);
+        let mut b = Lockstep::new(1);
+        let stamped = a.submit(0, rename(0, "North"));
+
+        b.take();
+        b.take();
+
+        assert!(!b.receive(stamped));
+    }
+}
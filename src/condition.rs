@@ -0,0 +1,416 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::convert::TryFrom;
+
+use crate::Defines;
+use crate::Owned;
+use crate::Location;
+use crate::Name;
+use crate::Water;
+use crate::Veget;
+use crate::Heat;
+use crate::Rain;
+use crate::River;
+use crate::Height;
+use crate::Pop;
+use crate::Aquifer;
+use crate::Building;
+use crate::Biome;
+use crate::Fertility;
+use crate::Pixel;
+use crate::Sea;
+use crate::Lake;
+use crate::Settlement;
+use crate::Colony;
+use crate::guild::Stock;
+use crate::garrison::Garrison;
+use crate::crops::Crop;
+use crate::ancestry::Ancestry;
+use crate::personality::Personality;
+use crate::symbol::Symbol;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    This,
+    Owner,
+    Location,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Num(f32),
+    Bool(bool),
+    Sym(Symbol),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Lit(Value),
+    Field(Role, Symbol, Option<Symbol>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Operand, Op, Operand),
+    Truthy(Operand),
+}
+
+// Conditions are written as plain strings in the data files, e.g.
+// "self.Pop > 1000 && owner.Name == \"Aleppo\"", and parsed on load.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    pub source: String,
+    pub expr: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f32),
+    Str(String),
+    Op(&'static str),
+}
+
+const OPS: [&str; 13] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", ".", "-"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+
+            let text: String = chars[start..i].iter().collect();
+
+            tokens.push(Token::Num(text.parse().map_err(|_| format!("bad number {}", text))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '"' {
+            let start = i + 1;
+
+            i += 1;
+
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err("unterminated string".to_string());
+            }
+
+            tokens.push(Token::Str(chars[start..i].iter().collect()));
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPS.iter().find(|op| rest.starts_with(*op)).ok_or_else(|| format!("unexpected '{}'", c))?;
+
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(o)) if *o == op => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name)
+            },
+            token => Err(format!("expected a name, found {:?}", token)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+
+            if !self.eat(")") {
+                return Err("expected ')'".to_string());
+            }
+
+            return Ok(expr);
+        }
+
+        let left = self.operand()?;
+        let op = [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)]
+            .iter()
+            .find(|(text, _)| self.eat(text))
+            .map(|&(_, op)| op);
+
+        match op {
+            Some(op) => Ok(Expr::Cmp(left, op, self.operand()?)),
+            None => Ok(Expr::Truthy(left)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let negative = self.eat("-");
+
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Num(num)) => {
+                self.pos += 1;
+                Ok(Operand::Lit(Value::Num(if negative { -num } else { num })))
+            },
+            Some(Token::Str(text)) => {
+                self.pos += 1;
+                Ok(Operand::Lit(Value::Sym(Symbol::new(&text))))
+            },
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+
+                let role = match name.as_str() {
+                    "self" | "settlement" => Role::This,
+                    "owner" => Role::Owner,
+                    "location" => Role::Location,
+                    "true" => return Ok(Operand::Lit(Value::Bool(true))),
+                    "false" => return Ok(Operand::Lit(Value::Bool(false))),
+                    _ => return Ok(Operand::Lit(Value::Sym(Symbol::new(&name)))),
+                };
+
+                if !self.eat(".") {
+                    return Err(format!("expected a field after '{}'", name));
+                }
+
+                let field = Symbol::new(&self.ident()?);
+                let key = match self.eat(".") {
+                    true => Some(Symbol::new(&self.ident()?)),
+                    false => None,
+                };
+
+                Ok(Operand::Field(role, field, key))
+            },
+            token => Err(format!("expected a value, found {:?}", token)),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+        let expr = parser.or()?;
+
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("unexpected {:?} in '{}'", parser.tokens[parser.pos], source));
+        }
+
+        Ok(Condition { source: source.to_string(), expr })
+    }
+
+    pub fn eval<W: Lookup>(&self, world: &W, defines: &Defines, entity: Entity) -> bool {
+        eval(&self.expr, world, defines, entity)
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Condition::parse(&source)
+    }
+}
+
+// Anything a condition can read, so systems that evaluate conditions can
+// declare it all up front.
+pub fn reads<Q, R>(builder: SystemBuilder<Q, R>) -> SystemBuilder<Q, R> {
+    builder
+        .read_component::<Owned>()
+        .read_component::<Location>()
+        .read_component::<Name>()
+        .read_component::<Water>()
+        .read_component::<Veget>()
+        .read_component::<Heat>()
+        .read_component::<Rain>()
+        .read_component::<River>()
+        .read_component::<Height>()
+        .read_component::<Pop>()
+        .read_component::<Aquifer>()
+        .read_component::<Building>()
+        .read_component::<Stock>()
+        .read_component::<Garrison>()
+        .read_component::<Crop>()
+        .read_component::<Ancestry>()
+        .read_component::<Personality>()
+        .read_component::<Biome>()
+        .read_component::<Fertility>()
+}
+
+pub trait Lookup {
+    fn follow(&self, entity: Entity, role: Role) -> Option<Entity>;
+    fn field(&self, entity: Entity, defines: &Defines, field: Symbol, key: Option<Symbol>) -> Option<Value>;
+}
+
+macro_rules! impl_lookup {
+    ($($w:ty),*) => {
+        $(
+            impl Lookup for $w {
+                fn follow(&self, entity: Entity, role: Role) -> Option<Entity> {
+                    match role {
+                        Role::This => Some(entity),
+                        Role::Owner => self.get_component::<Owned>(entity).map(|owned| owned.item),
+                        Role::Location => self.get_component::<Location>(entity).map(|location| location.item),
+                    }
+                }
+
+                fn field(&self, entity: Entity, defines: &Defines, field: Symbol, key: Option<Symbol>) -> Option<Value> {
+                    let num = |v: f32| Some(Value::Num(v));
+
+                    match (field.as_str(), key) {
+                        ("Water", None) => self.get_component::<Water>(entity).and_then(|c| num(c.item)),
+                        ("Veget", None) => self.get_component::<Veget>(entity).and_then(|c| num(c.item)),
+                        ("Heat", None) => self.get_component::<Heat>(entity).and_then(|c| num(c.item)),
+                        ("Rain", None) => self.get_component::<Rain>(entity).and_then(|c| num(c.item)),
+                        ("River", None) => self.get_component::<River>(entity).and_then(|c| num(c.item)),
+                        ("Height", None) => self.get_component::<Height>(entity).and_then(|c| num(c.item)),
+                        ("Pop", None) => self.get_component::<Pop>(entity).and_then(|c| num(c.item)),
+                        ("Aquifer", None) => self.get_component::<Aquifer>(entity).and_then(|c| num(c.item)),
+                        ("Fertility", None) => self.get_component::<Fertility>(entity).and_then(|c| num(c.item)),
+                        ("Garrison", None) => self.get_component::<Garrison>(entity).and_then(|c| num(c.item)),
+                        ("Personality", None) => self.get_component::<Personality>(entity).map(|c| Value::Sym(c.item)),
+                        ("Crop", None) => self.get_component::<Crop>(entity).and_then(|c| defines.crops.crops.get(c.item)).map(|c| Value::Sym(c.name)),
+                        ("Biome", None) => self.get_component::<Biome>(entity).map(|c| Value::Sym(Symbol::new(&format!("{:?}", c.item)))),
+                        ("Name", None) => self.get_component::<Name>(entity).map(|c| Value::Sym(c.item)),
+                        ("Pixel", None) => Some(Value::Bool(self.get_tag::<Pixel>(entity).is_some())),
+                        ("Sea", None) => Some(Value::Bool(self.get_tag::<Sea>(entity).is_some())),
+                        ("Lake", None) => Some(Value::Bool(self.get_tag::<Lake>(entity).is_some())),
+                        ("Settlement", None) => Some(Value::Bool(self.get_tag::<Settlement>(entity).is_some())),
+                        ("Colony", None) => Some(Value::Bool(self.get_tag::<Colony>(entity).is_some())),
+                        ("building", Some(key)) => {
+                            let i = *defines.building_i.get(&key)?;
+
+                            self.get_component::<Building>(entity).and_then(|c| c.item.get(i).cloned()).and_then(num)
+                        },
+                        ("ancestry", Some(key)) => self.get_component::<Ancestry>(entity).and_then(|c| num(c.share(key))),
+                        ("stock", Some(key)) => {
+                            let i = *defines.good_i.get(&key)?;
+
+                            self.get_component::<Stock>(entity).and_then(|c| c.item.get(i).cloned()).and_then(num)
+                        },
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_lookup!(World, SubWorld);
+
+fn resolve<W: Lookup>(operand: &Operand, world: &W, defines: &Defines, entity: Entity) -> Option<Value> {
+    match operand {
+        Operand::Lit(value) => Some(*value),
+        Operand::Field(role, field, key) => world.field(world.follow(entity, *role)?, defines, *field, *key),
+    }
+}
+
+// A missing field or entity makes the comparison false rather than an error,
+// so "owner.Pop > 10" simply fails for unowned pixels.
+fn eval<W: Lookup>(expr: &Expr, world: &W, defines: &Defines, entity: Entity) -> bool {
+    match expr {
+        Expr::Or(a, b) => eval(a, world, defines, entity) || eval(b, world, defines, entity),
+        Expr::And(a, b) => eval(a, world, defines, entity) && eval(b, world, defines, entity),
+        Expr::Not(a) => !eval(a, world, defines, entity),
+        Expr::Truthy(a) => match resolve(a, world, defines, entity) {
+            Some(Value::Bool(b)) => b,
+            Some(Value::Num(n)) => n != 0.,
+            _ => false,
+        },
+        Expr::Cmp(a, op, b) => {
+            let (a, b) = match (resolve(a, world, defines, entity), resolve(b, world, defines, entity)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return false,
+            };
+
+            match (a, b) {
+                (Value::Num(a), Value::Num(b)) => match op {
+                    Op::Eq => a == b,
+                    Op::Ne => a != b,
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    Op::Ge => a >= b,
+                },
+                (a, b) => match op {
+                    Op::Eq => a == b,
+                    Op::Ne => a != b,
+                    _ => false,
+                },
+            }
+        },
+    }
+}
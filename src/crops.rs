@@ -0,0 +1,106 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use crate::Defines;
+use crate::Heat;
+use crate::Water;
+use crate::Owns;
+use crate::Settlement;
+use crate::symbol::Symbol;
+use crate::condition::Condition;
+
+#[derive(Clone, Deserialize)]
+pub struct CropDefines {
+    pub name: Symbol,
+    pub heat: (f32, f32),
+    pub water: (f32, f32),
+    pub output: f32,
+    pub season: f32,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+}
+
+// A pixel's growing season is the share of the year it is warmer than
+// `warm` would make it, so cold pixels cannot ripen slow crops.
+#[derive(Clone, Deserialize)]
+pub struct CropsDefines {
+    pub year: usize,
+    pub warm: f32,
+    pub crops: Vec<CropDefines>,
+}
+
+pub struct Crop { pub item: usize }
+
+pub struct Crops {
+    pub period: usize,
+    tick: usize,
+}
+
+impl Crops {
+    pub fn new(period: usize) -> Self {
+        Crops {
+            period,
+            tick: 0,
+        }
+    }
+}
+
+fn fit(value: f32, (min, max): (f32, f32)) -> f32 {
+    let width = (max - min).max(1e-3);
+
+    match () {
+        _ if value < min => (1. - (min - value) / width).max(0.),
+        _ if value > max => (1. - (value - max) / width).max(0.),
+        _ => 1.,
+    }
+}
+
+// Harvest per farm level of the crop on a pixel of this heat and water.
+pub fn crop_yield(defines: &CropsDefines, crop: usize, heat: f32, water: f32) -> f32 {
+    let c = &defines.crops[crop];
+    let season = defines.year as f32 * (heat / defines.warm).max(0.).min(1.);
+
+    c.output * fit(heat, c.heat) * fit(water, c.water) * (season / c.season.max(1.)).min(1.)
+}
+
+// Once a year each settlement sows whichever unlocked crop would bring in
+// the most over the land it administers.
+pub fn adopt_crops(world: &mut World, resources: &mut Resources) {
+    let mut crops = match resources.get_mut::<Crops>() {
+        Some(crops) => crops,
+        None => return,
+    };
+
+    crops.tick += 1;
+
+    if crops.period == 0 || crops.tick % crops.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let c = &defines.crops;
+    let settlements: Vec<(Entity, Vec<Entity>)> = <Read<Owns>>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(&*world)
+        .map(|(settlement, owns)| (settlement, owns.item.clone()))
+        .collect();
+
+    for (settlement, pixels) in settlements.into_iter() {
+        let land: Vec<(f32, f32)> = pixels
+            .iter()
+            .filter_map(|&pixel| Some((world.get_component::<Heat>(pixel)?.item, world.get_component::<Water>(pixel)?.item)))
+            .collect();
+        let best = (0..c.crops.len())
+            .filter(|&crop| c.crops[crop].requires.as_ref().map_or(true, |r| r.eval(&*world, &defines, settlement)))
+            .map(|crop| (crop, land.iter().map(|&(heat, water)| crop_yield(c, crop, heat, water)).sum::<f32>()))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        match best {
+            Some((crop, _)) => world.add_component(settlement, Crop { item: crop }).unwrap(),
+            None => {
+                let _ = world.remove_component::<Crop>(settlement);
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::Building;
+use crate::Owned;
+use crate::Pop;
+use crate::Location;
+use crate::Ruin;
+use crate::guild::Stock;
+use crate::symbol::Symbol;
+use crate::ruins::Chronicle;
+use crate::path;
+
+#[derive(Clone, Deserialize)]
+pub struct DecayDefines {
+    pub year: usize,
+    pub rate: f32,
+    pub collapse: f32,
+    pub upkeep: HashMap<Symbol, HashMap<Symbol, f32>>,
+    pub labour: f32,
+}
+
+pub struct Decay {
+    pub period: usize,
+    pub collapsed: usize,
+    tick: usize,
+}
+
+impl Decay {
+    pub fn new(period: usize) -> Self {
+        Decay {
+            period,
+            collapsed: 0,
+            tick: 0,
+        }
+    }
+}
+
+pub fn spawn_ruin(world: &mut World, pixel: Entity, buildings: Vec<f32>) -> Entity {
+    world.insert(
+        (Ruin,),
+        vec![(
+            Location { item: pixel },
+            Building { item: buildings },
+        )]
+    )[0]
+}
+
+// Pays a year's upkeep of one pixel's buildings per level out of `stock`,
+// or failing that out of `labour`, the levels its owner's people can still
+// keep up by hand, and wears down by `rate` what neither covers. Returns
+// the levels of the buildings that fell below `collapse`.
+fn upkeep_year(levels: &mut Vec<f32>, upkeep: &Vec<Vec<(usize, f32)>>, mut stock: Option<&mut Vec<f32>>, labour: &mut f32, d: &DecayDefines) -> Vec<f32> {
+    let mut ruined = vec![0.; levels.len()];
+
+    for b in 0..levels.len() {
+        if levels[b] <= 0. || upkeep[b].is_empty() {
+            continue;
+        }
+
+        match stock.as_deref_mut() {
+            Some(stock) if upkeep[b].iter().all(|&(g, amount)| stock[g] >= amount * levels[b]) => {
+                for &(g, amount) in upkeep[b].iter() {
+                    stock[g] -= amount * levels[b];
+                }
+
+                continue;
+            }
+            _ => (),
+        }
+
+        if *labour >= levels[b] {
+            *labour -= levels[b];
+            continue;
+        }
+
+        let worn = levels[b] - d.rate;
+
+        if worn < d.collapse {
+            ruined[b] = levels[b];
+            levels[b] = 0.;
+        } else {
+            levels[b] = worn;
+        }
+    }
+
+    ruined
+}
+
+// Once a year every building with an upkeep is paid for or worn down. Building
+// levels are what production, irrigation and movement read, so neglect
+// degrades all of them. A building worn below `collapse` is gone and leaves a
+// ruin behind.
+pub fn decay_buildings(world: &mut World, resources: &mut Resources) {
+    let mut decay = match resources.get_mut::<Decay>() {
+        Some(decay) => decay,
+        None => return,
+    };
+
+    decay.tick += 1;
+
+    if decay.period == 0 || decay.tick % decay.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let d = &defines.decay;
+    let upkeep: Vec<Vec<(usize, f32)>> = {
+        let mut upkeep = vec![Vec::new(); defines.building_i.len()];
+
+        for (building, goods) in d.upkeep.iter() {
+            if let Some(&b) = defines.building_i.get(building) {
+                upkeep[b] = goods.iter().map(|(good, &amount)| (defines.good_i[good], amount)).collect();
+            }
+        }
+
+        upkeep
+    };
+
+    let query = <(Read<Index>, Read<Building>, TryRead<Owned>)>::query();
+    let built: Vec<(Entity, usize, Vec<f32>, Option<Entity>)> = query
+        .iter_entities(&*world)
+        .filter(|(_, (_, building, _))| building.item.iter().any(|&level| level > 0.))
+        .map(|(pixel, (index, building, owned))| (pixel, index.item, building.item.clone(), owned.map(|owned| owned.item)))
+        .collect();
+    let mut labour: HashMap<Entity, f32> = <Read<Pop>>::query()
+        .iter_entities(&*world)
+        .map(|(settlement, pop)| (settlement, pop.item.max(0.) * d.labour))
+        .collect();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+    let names: HashMap<usize, Symbol> = defines.building_i.iter().map(|(&name, &b)| (b, name)).collect();
+    let road = defines.building_i[&Symbol::new("Road")];
+    let mut roads = false;
+
+    for (pixel, index, mut levels, owner) in built.into_iter() {
+        let before = levels[road];
+        let ruined = {
+            let mut stock = owner.and_then(|owner| world.get_component_mut::<Stock>(owner));
+            let mut none = 0.;
+            let labour = match owner.and_then(|owner| labour.get_mut(&owner)) {
+                Some(labour) => labour,
+                None => &mut none,
+            };
+
+            upkeep_year(&mut levels, &upkeep, stock.as_deref_mut().map(|stock| &mut stock.item), labour, d)
+        };
+
+        roads |= levels[road] != before;
+
+        if let Some(mut building) = world.get_component_mut::<Building>(pixel) {
+            building.item = levels;
+        }
+
+        if ruined.iter().any(|&level| level > 0.) {
+            if let Some(chronicle) = chronicle.as_mut() {
+                let fallen: Vec<String> = (0..ruined.len()).filter(|&b| ruined[b] > 0.).map(|b| names[&b].to_string()).collect();
+
+                chronicle.record(index, Symbol::new("Ruined"), format!("{} fell into ruin", fallen.join(", ")));
+            }
+
+            spawn_ruin(world, pixel, ruined);
+            decay.collapsed += 1;
+        }
+    }
+
+    if roads {
+        path::roads_changed(resources);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defines() -> DecayDefines {
+        DecayDefines { year: 360, rate: 0.1, collapse: 0.05, upkeep: HashMap::new(), labour: 0. }
+    }
+
+    #[test]
+    fn paid_buildings_do_not_decay() {
+        let upkeep = vec![vec![(0, 1.)]];
+        let mut levels = vec![2.];
+        let mut stock = vec![5.];
+        let mut labour = 0.;
+
+        let ruined = upkeep_year(&mut levels, &upkeep, Some(&mut stock), &mut labour, &defines());
+
+        assert_eq!(levels, vec![2.]);
+        assert_eq!(stock, vec![3.]);
+        assert_eq!(ruined, vec![0.]);
+    }
+
+    #[test]
+    fn labour_keeps_up_what_goods_cannot() {
+        let upkeep = vec![vec![(0, 1.)]];
+        let mut levels = vec![2.];
+        let mut stock = vec![1.];
+        let mut labour = 3.;
+
+        upkeep_year(&mut levels, &upkeep, Some(&mut stock), &mut labour, &defines());
+
+        assert_eq!(levels, vec![2.]);
+        assert_eq!(stock, vec![1.]);
+        assert_eq!(labour, 1.);
+    }
+
+    #[test]
+    fn neglected_buildings_wear_and_collapse() {
+        let upkeep = vec![vec![(0, 1.)], vec![(0, 1.)]];
+        let mut levels = vec![2., 0.1];
+        let mut labour = 0.;
+
+        let ruined = upkeep_year(&mut levels, &upkeep, None, &mut labour, &defines());
+
+        assert!((levels[0] - 1.9).abs() < 1e-6);
+        assert_eq!(levels[1], 0.);
+        assert_eq!(ruined, vec![0., 0.1]);
+    }
+}
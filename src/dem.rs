@@ -0,0 +1,108 @@
+use tiff::decoder::Decoder;
+use tiff::decoder::DecodingResult;
+
+use std::io;
+use std::io::Read;
+use std::io::BufReader;
+use std::fs::File;
+use std::path::Path;
+
+const VOID: i16 = -32768;
+
+pub struct Dem {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Dem {
+    // SRTM tiles are square grids of big-endian i16 metres, 1201 or 3601 on
+    // a side; voids are read as sea level.
+    pub fn read_hgt<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let side = ((bytes.len() / 2) as f64).sqrt() as usize;
+
+        if side * side * 2 != bytes.len() {
+            return Err(invalid("hgt file is not a square grid of i16"));
+        }
+
+        let data = bytes
+            .chunks(2)
+            .map(|b| match i16::from_be_bytes([b[0], b[1]]) {
+                VOID => 0.,
+                h => h as f32,
+            })
+            .collect();
+
+        Ok(Dem { width: side, height: side, data })
+    }
+
+    pub fn read_geotiff<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(invalid)?;
+        let (width, height) = decoder.dimensions().map_err(invalid)?;
+        let data: Vec<f32> = match decoder.read_image().map_err(invalid)? {
+            DecodingResult::U8(data) => data.into_iter().map(|h| h as f32).collect(),
+            DecodingResult::U16(data) => data.into_iter().map(|h| h as f32).collect(),
+            DecodingResult::U32(data) => data.into_iter().map(|h| h as f32).collect(),
+            DecodingResult::F32(data) => data,
+            DecodingResult::F64(data) => data.into_iter().map(|h| h as f32).collect(),
+            _ => return Err(invalid("unsupported GeoTIFF sample format")),
+        };
+
+        if data.len() != width as usize * height as usize {
+            return Err(invalid("GeoTIFF must have a single band"));
+        }
+
+        Ok(Dem { width: width as usize, height: height as usize, data })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase()) {
+            Some(ref ext) if ext == "hgt" => Dem::read_hgt(path),
+            Some(ref ext) if ext == "tif" || ext == "tiff" => Dem::read_geotiff(path),
+            _ => Err(invalid("unknown DEM format")),
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> f32 {
+        let v = self.data[y.min(self.height - 1) * self.width + x.min(self.width - 1)];
+
+        if v.is_finite() { v } else { 0. }
+    }
+
+    pub fn sample(&self, x: f64, y: f64) -> f32 {
+        let x = x.max(0.).min((self.width - 1) as f64);
+        let y = y.max(0.).min((self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+
+        let top = self.at(x0, y0) * (1. - fx) + self.at(x0 + 1, y0) * fx;
+        let bottom = self.at(x0, y0 + 1) * (1. - fx) + self.at(x0 + 1, y0 + 1) * fx;
+
+        top * (1. - fy) + bottom * fy
+    }
+
+    // Bilinear resampling onto a size * size grid, stretching the DEM to fit.
+    pub fn resample(&self, size: usize) -> Vec<f32> {
+        let sx = (self.width - 1) as f64 / (size - 1).max(1) as f64;
+        let sy = (self.height - 1) as f64 / (size - 1).max(1) as f64;
+        let mut out = Vec::with_capacity(size * size);
+
+        for y in 0..size {
+            for x in 0..size {
+                out.push(self.sample(x as f64 * sx, y as f64 * sy));
+            }
+        }
+
+        out
+    }
+}
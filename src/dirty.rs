@@ -0,0 +1,113 @@
+use legion::prelude::*;
+use legion::storage::Component;
+
+use serde::Deserialize;
+
+use std::collections::BTreeSet;
+
+use crate::Index;
+use crate::Water;
+use crate::Veget;
+use crate::Heat;
+use crate::Rain;
+use crate::River;
+use crate::Height;
+use crate::Pop;
+use crate::Aquifer;
+use crate::overlay::Value;
+use crate::colormap::Colormap;
+use crate::symbol::Symbol;
+
+pub const TILE: usize = 64;
+
+#[derive(Clone, Deserialize)]
+pub struct DisplayDefines {
+    pub layer: Symbol,
+    pub min: f32,
+    pub max: f32,
+    pub colormap: Colormap,
+}
+
+// Colors are what the display currently shows; dirty pixels and tiles pile
+// up until the viewer uploads them and calls clear.
+pub struct DirtyMap {
+    pub display: DisplayDefines,
+    pub size: usize,
+    pub colors: Vec<[u8; 3]>,
+    pub pixels: Vec<usize>,
+    pub tiles: BTreeSet<(usize, usize)>,
+    marked: Vec<bool>,
+}
+
+impl DirtyMap {
+    pub fn new(display: DisplayDefines, size: usize) -> Self {
+        DirtyMap {
+            display,
+            size,
+            colors: vec![[0; 3]; size * size],
+            pixels: Vec::new(),
+            tiles: BTreeSet::new(),
+            marked: vec![false; size * size],
+        }
+    }
+
+    pub fn update(&mut self, index: usize, value: f32) {
+        let t = (value - self.display.min) / (self.display.max - self.display.min);
+        let color = self.display.colormap.color(t as f64).0;
+
+        if self.colors[index] == color {
+            return;
+        }
+
+        self.colors[index] = color;
+
+        if !self.marked[index] {
+            self.marked[index] = true;
+            self.pixels.push(index);
+            self.tiles.insert(((index % self.size) / TILE, (index / self.size) / TILE));
+        }
+    }
+
+    pub fn tile_bounds(&self, (x, y): (usize, usize)) -> (usize, usize, usize, usize) {
+        let x0 = x * TILE;
+        let y0 = y * TILE;
+
+        (x0, y0, TILE.min(self.size - x0), TILE.min(self.size - y0))
+    }
+
+    pub fn clear(&mut self) {
+        for &index in self.pixels.iter() {
+            self.marked[index] = false;
+        }
+
+        self.pixels.clear();
+        self.tiles.clear();
+    }
+}
+
+fn track<T: Component + Value>(world: &World, dirty: &mut DirtyMap) {
+    let query = <(Read<Index>, Read<T>)>::query();
+
+    for (index, value) in query.iter(world) {
+        dirty.update(index.item, value.value());
+    }
+}
+
+pub fn track_dirty(world: &mut World, resources: &mut Resources) {
+    let mut dirty = match resources.get_mut::<DirtyMap>() {
+        Some(dirty) => dirty,
+        None => return,
+    };
+
+    match dirty.display.layer.as_str() {
+        "Water" => track::<Water>(world, &mut dirty),
+        "Veget" => track::<Veget>(world, &mut dirty),
+        "Heat" => track::<Heat>(world, &mut dirty),
+        "Rain" => track::<Rain>(world, &mut dirty),
+        "River" => track::<River>(world, &mut dirty),
+        "Height" => track::<Height>(world, &mut dirty),
+        "Pop" => track::<Pop>(world, &mut dirty),
+        "Aquifer" => track::<Aquifer>(world, &mut dirty),
+        _ => {},
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::metadata::escape;
+
+// Every run writes under its own `root/seed{seed}_{timestamp}` directory;
+// handing out a path records the artifact and rewrites manifest.json, so the
+// manifest is complete even when a run is cut short.
+pub struct ExportManager {
+    pub root: PathBuf,
+    pub dir: PathBuf,
+    pub seed: u64,
+    pub created: u64,
+    artifacts: Vec<(String, String)>,
+}
+
+impl ExportManager {
+    pub fn new<T: Into<PathBuf>>(root: T, seed: u64) -> Self {
+        let root = root.into();
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let dir = root.join(format!("seed{}_{}", seed, created));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        ExportManager {
+            root,
+            dir,
+            seed,
+            created,
+            artifacts: Vec::new(),
+        }
+    }
+
+    pub fn path(&mut self, kind: &str, name: &str) -> PathBuf {
+        let path = self.dir.join(name);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        if !self.artifacts.iter().any(|(_, artifact)| artifact == name) {
+            self.artifacts.push((kind.to_string(), name.to_string()));
+            self.write_manifest();
+        }
+
+        path
+    }
+
+    // The seed a world was actually generated from, which can differ from the
+    // configured one when seeds are rejected.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.write_manifest();
+    }
+
+    pub fn dir(&mut self, kind: &str, name: &str) -> PathBuf {
+        let path = self.path(kind, name);
+
+        fs::create_dir_all(&path).unwrap();
+
+        path
+    }
+
+    fn write_manifest(&self) {
+        let artifacts: Vec<String> = self.artifacts
+            .iter()
+            .map(|(kind, path)| format!("{{\"kind\":\"{}\",\"path\":\"{}\"}}", escape(kind), escape(path)))
+            .collect();
+
+        fs::write(self.dir.join("manifest.json"), format!(
+            "{{\"seed\":{},\"created\":{},\"artifacts\":[{}]}}\n",
+            self.seed, self.created, artifacts.join(","),
+        )).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use std::ptr;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+
+use crate::map::ProvBuilder;
+use crate::map::WorldGenConfig;
+use crate::map::WrapMode;
+use crate::map::NoiseKind;
+
+pub const P4_HEIGHT: u32 = 0;
+pub const P4_INSOLATION: u32 = 1;
+pub const P4_CLOUD: u32 = 2;
+pub const P4_TEMP: u32 = 3;
+pub const P4_RIVER: u32 = 4;
+pub const P4_WATER: u32 = 5;
+pub const P4_VEGET: u32 = 6;
+
+pub const P4_WRAP_NONE: u32 = 0;
+pub const P4_WRAP_X: u32 = 1;
+pub const P4_WRAP_TORUS: u32 = 2;
+
+pub const P4_NOISE_PERLIN: u32 = 0;
+pub const P4_NOISE_OPENSIMPLEX: u32 = 1;
+pub const P4_NOISE_RIDGED: u32 = 2;
+pub const P4_NOISE_BILLOW: u32 = 3;
+pub const P4_NOISE_WARPED: u32 = 4;
+
+#[repr(C)]
+pub struct P4Config {
+    pub size: usize,
+    pub freq: f64,
+    pub pers: f64,
+    pub lac: f64,
+    pub min: f64,
+    pub max: f64,
+    pub water_level: f64,
+    pub water_taper: f64,
+    pub lat_start: f64,
+    pub lat_end: f64,
+    pub seed: u64,
+    pub wrap: u32,
+    pub noise: u32,
+}
+
+// Nothing may unwind across the C boundary, so every entry point catches
+// panics and reports them the way it reports a null pointer.
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// Returns null when `config` is null or fails WorldGenConfig::validate.
+///
+/// # Safety
+///
+/// `config` must be null or point to a valid, initialised P4Config for the
+/// length of the call; it is only read. A returned builder is owned by the
+/// caller and must be released with exactly one call to p4_builder_free.
+#[no_mangle]
+pub unsafe extern "C" fn p4_builder_new(config: *const P4Config) -> *mut ProvBuilder {
+    guard(ptr::null_mut(), || {
+        let config = match config.as_ref() {
+            Some(config) => config,
+            None => return ptr::null_mut(),
+        };
+        let wrap = match config.wrap {
+            P4_WRAP_X => WrapMode::WrapX,
+            P4_WRAP_TORUS => WrapMode::Torus,
+            _ => WrapMode::None,
+        };
+        let noise = match config.noise {
+            P4_NOISE_OPENSIMPLEX => NoiseKind::OpenSimplex,
+            P4_NOISE_RIDGED => NoiseKind::Ridged,
+            P4_NOISE_BILLOW => NoiseKind::Billow,
+            P4_NOISE_WARPED => NoiseKind::Warped,
+            _ => NoiseKind::Perlin,
+        };
+        let config = WorldGenConfig {
+            size: config.size,
+            freq: config.freq,
+            pers: config.pers,
+            lac: config.lac,
+            min: config.min,
+            max: config.max,
+            water_level: config.water_level,
+            water_taper: config.water_taper,
+            lat_start: config.lat_start,
+            lat_end: config.lat_end,
+            seed: config.seed,
+            wrap,
+            noise,
+        };
+
+        match ProvBuilder::with_config(&config) {
+            Ok(builder) => Box::into_raw(Box::new(builder)),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns 0 once the builder is generated, or -1 on a null builder or a
+/// failed generation.
+///
+/// # Safety
+///
+/// `builder` must be null or a pointer returned by p4_builder_new that has
+/// not been freed, and no other call may use it at the same time. Pointers
+/// earlier returned by p4_layer and p4_settlements dangle afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn p4_generate(builder: *mut ProvBuilder) -> i32 {
+    guard(-1, || match builder.as_mut() {
+        Some(builder) => {
+            builder.generate();
+            0
+        },
+        None => -1,
+    })
+}
+
+/// # Safety
+///
+/// `builder` must be null or a pointer returned by p4_builder_new that has
+/// not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn p4_size(builder: *const ProvBuilder) -> usize {
+    guard(0, || builder.as_ref().map_or(0, |builder| builder.size))
+}
+
+/// Returns the layer's values and writes their count through `len`, or
+/// null for a null builder or an unknown layer.
+///
+/// # Safety
+///
+/// `builder` must be null or a pointer returned by p4_builder_new that has
+/// not been freed, and `len` null or valid for a write of one usize. The
+/// values are borrowed from the builder: the caller must not write through
+/// or free them, and may read only `len` of them, until the builder is
+/// generated again or freed.
+#[no_mangle]
+pub unsafe extern "C" fn p4_layer(builder: *const ProvBuilder, layer: u32, len: *mut usize) -> *const f64 {
+    guard(ptr::null(), || {
+        let builder = match builder.as_ref() {
+            Some(builder) => builder,
+            None => return ptr::null(),
+        };
+        let values = match layer {
+            P4_HEIGHT => &builder.heightmap,
+            P4_INSOLATION => &builder.insolation,
+            P4_CLOUD => &builder.cloudmap,
+            P4_TEMP => &builder.tempmap,
+            P4_RIVER => &builder.rivermap,
+            P4_WATER => &builder.watermap,
+            P4_VEGET => &builder.vegetmap,
+            _ => return ptr::null(),
+        };
+
+        if let Some(len) = len.as_mut() {
+            *len = values.len();
+        }
+
+        values.as_ptr()
+    })
+}
+
+/// Returns one flag per pixel and writes their count through `len`, or null
+/// for a null builder.
+///
+/// # Safety
+///
+/// `builder` must be null or a pointer returned by p4_builder_new that has
+/// not been freed, and `len` null or valid for a write of one usize. The
+/// flags are borrowed from the builder under the same terms as p4_layer.
+#[no_mangle]
+pub unsafe extern "C" fn p4_settlements(builder: *const ProvBuilder, len: *mut usize) -> *const bool {
+    guard(ptr::null(), || {
+        let builder = match builder.as_ref() {
+            Some(builder) => builder,
+            None => return ptr::null(),
+        };
+
+        if let Some(len) = len.as_mut() {
+            *len = builder.settlements.len();
+        }
+
+        builder.settlements.as_ptr()
+    })
+}
+
+/// # Safety
+///
+/// `builder` must be null or a pointer returned by p4_builder_new that has
+/// not already been freed. It, and every pointer p4_layer or p4_settlements
+/// returned from it, must not be used after the call.
+#[no_mangle]
+pub unsafe extern "C" fn p4_builder_free(builder: *mut ProvBuilder) {
+    guard((), || {
+        if !builder.is_null() {
+            drop(Box::from_raw(builder));
+        }
+    })
+}
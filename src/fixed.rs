@@ -0,0 +1,154 @@
+use std::ops::Add;
+use std::ops::AddAssign;
+use std::ops::Sub;
+use std::ops::SubAssign;
+use std::ops::Mul;
+use std::ops::Div;
+use std::ops::Neg;
+
+const FRAC: u32 = 32;
+const ONE: i64 = 1 << FRAC;
+
+// Q32.32 fixed point. Every operation is integer arithmetic, so results are
+// bit-identical on every platform; only the conversions touch floats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+
+    x
+}
+
+impl Fixed {
+    pub fn from_f32(x: f32) -> Self {
+        Fixed((x as f64 * ONE as f64).round() as i64)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        (self.0 as f64 / ONE as f64) as f32
+    }
+
+    pub fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Ord::max(self, other)
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Ord::min(self, other)
+    }
+
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed(0);
+        }
+
+        Fixed(isqrt((self.0 as u128) << FRAC) as i64)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0.wrapping_add(other.0))
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0.wrapping_sub(other.0))
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        *self = *self - other;
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * other.0 as i128) >> FRAC) as i64)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+
+    // Dividing by zero or past the range saturates instead of panicking, so a
+    // bad ratio stays a huge number on every peer alike.
+    fn div(self, other: Fixed) -> Fixed {
+        if other.0 == 0 {
+            return match self.0 {
+                0 => Fixed(0),
+                x if x > 0 => Fixed(i64::MAX),
+                _ => Fixed(i64::MIN),
+            };
+        }
+
+        let quotient = ((self.0 as i128) << FRAC) / other.0 as i128;
+
+        Fixed(quotient.max(i64::MIN as i128).min(i64::MAX as i128) as i64)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+// The number type of the systems lockstep peers must agree on: climate
+// drift, vegetation, irrigation and guild production. Components keep
+// storing f32 and are converted at the boundary, where rounding is the same
+// on every platform.
+#[cfg(feature = "fixed")]
+pub type Real = Fixed;
+#[cfg(not(feature = "fixed"))]
+pub type Real = f32;
+
+#[cfg(feature = "fixed")]
+pub fn real(x: f32) -> Real {
+    Fixed::from_f32(x)
+}
+
+#[cfg(not(feature = "fixed"))]
+pub fn real(x: f32) -> Real {
+    x
+}
+
+#[cfg(feature = "fixed")]
+pub fn to_f32(x: Real) -> f32 {
+    x.to_f32()
+}
+
+#[cfg(not(feature = "fixed"))]
+pub fn to_f32(x: Real) -> f32 {
+    x
+}
@@ -0,0 +1,174 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Veget;
+use crate::Owns;
+use crate::Settlement;
+use crate::Colony;
+use crate::Sea;
+use crate::Lake;
+use crate::Ice;
+use crate::PixelGrid;
+use crate::admin::Admin;
+use crate::guild;
+use crate::guild::Stock;
+use crate::garrison::Garrison;
+use crate::names;
+use crate::names::Names;
+use crate::ruins;
+use crate::ruins::Chronicle;
+use crate::tags;
+use crate::tags::TagEvents;
+use crate::ancestry;
+use crate::personality;
+use crate::symbol::Symbol;
+use crate::modifier::Modifiers;
+use crate::modifier::factor;
+use crate::random::WorldRng;
+
+#[derive(Clone, Deserialize)]
+pub struct FrontierDefines {
+    pub period: usize,
+    pub density: f32,
+    pub crowding: f32,
+    pub share: f32,
+    pub range: usize,
+    pub min_veget: f32,
+}
+
+pub struct Frontier {
+    pub period: usize,
+    pub founded: usize,
+    tick: usize,
+}
+
+impl Frontier {
+    pub fn new(period: usize) -> Self {
+        Frontier {
+            period,
+            founded: 0,
+            tick: 0,
+        }
+    }
+}
+
+fn habitable(world: &World, grid: &PixelGrid, admin: &Admin, defines: &FrontierDefines, index: usize) -> bool {
+    let pixel = grid.get(index);
+
+    admin.owner.get(index).map_or(true, |owner| owner.is_none())
+        && world.get_tag::<Sea>(pixel).is_none()
+        && world.get_tag::<Lake>(pixel).is_none()
+        && world.get_tag::<Ice>(pixel).is_none()
+        && world.get_tag::<Settlement>(pixel).is_none()
+        && world.get_component::<Veget>(pixel).map_or(false, |veget| veget.item >= defines.min_veget)
+}
+
+// Nearest habitable pixel outside every administrative area, searched
+// breadth-first up to `range` steps away.
+fn find_frontier(world: &World, grid: &PixelGrid, admin: &Admin, defines: &FrontierDefines, from: usize, taken: &HashSet<usize>) -> Option<usize> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    seen.insert(from);
+    queue.push_back((from, 0));
+
+    while let Some((index, depth)) = queue.pop_front() {
+        if index != from && !taken.contains(&index) && habitable(world, grid, admin, defines, index) {
+            return Some(index);
+        }
+        if depth >= defines.range {
+            continue;
+        }
+
+        for next in grid.neighb(world, index).iter() {
+            if seen.insert(next) {
+                queue.push_back((next, depth + 1));
+            }
+        }
+    }
+
+    None
+}
+
+// A settlement holding more people than `crowding` times what its land feeds
+// sends a share of the excess out to found a hamlet on the frontier. Hamlets
+// are ordinary settlements tagged Colony, so administration picks them up.
+// The share sent is scaled by the settlement's "expansion" stat.
+pub fn emigrate(world: &mut World, resources: &mut Resources) {
+    let mut frontier = match resources.get_mut::<Frontier>() {
+        Some(frontier) => frontier,
+        None => return,
+    };
+
+    frontier.tick += 1;
+
+    if frontier.period == 0 || frontier.tick % frontier.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let admin = resources.get::<Admin>().unwrap();
+    let mut names = resources.get_mut::<Names>().unwrap();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+    let mut events = resources.get_mut::<TagEvents>();
+    let f = &defines.frontier;
+    let goods = defines.good_i.len();
+
+    let stat = Symbol::new("expansion");
+
+    let query = <(Read<Index>, Read<Pop>, Read<Owns>, TryRead<Modifiers>)>::query().filter(tag::<Settlement>());
+    let crowded: Vec<(Entity, usize, f32)> = query
+        .iter_entities(&*world)
+        .filter_map(|(settlement, (index, pop, owns, modifiers))| {
+            let capacity = owns.item
+                .iter()
+                .filter_map(|&pixel| world.get_component::<Veget>(pixel).map(|veget| veget.item))
+                .sum::<f32>() * f.density;
+            let excess = pop.item - capacity * f.crowding;
+
+            if excess > 0. { Some((settlement, index.item, excess * f.share * factor(modifiers.as_deref(), stat))) } else { None }
+        })
+        .collect();
+
+    let mut taken = HashSet::new();
+    let mut rng = resources.get::<WorldRng>().unwrap().get("frontier");
+
+    for (settlement, from, emigrants) in crowded.into_iter() {
+        let target = match find_frontier(world, &grid, &admin, f, from, &taken) {
+            Some(target) => target,
+            None => continue,
+        };
+        let hamlet = grid.get(target);
+
+        taken.insert(target);
+
+        if let Some(mut pop) = world.get_component_mut::<Pop>(settlement) {
+            pop.item -= emigrants;
+        }
+
+        tags::add_tag(world, events.as_deref_mut(), hamlet, Settlement);
+        tags::add_tag(world, events.as_deref_mut(), hamlet, Colony);
+        world.add_component(hamlet, Pop { item: emigrants }).unwrap();
+        world.add_component(hamlet, Stock { item: vec![0.; goods] }).unwrap();
+        world.add_component(hamlet, Garrison { item: 0. }).unwrap();
+        names::rename(world, &mut names, hamlet, defines.culture, names::Kind::Settlement, &mut rng);
+        ancestry::found(world, settlement, hamlet);
+        personality::assign(world, &defines, hamlet, defines.culture, &mut rng);
+        guild::found_guilds(world, &defines, hamlet);
+
+        if let Some(chronicle) = chronicle.as_mut() {
+            ruins::resettle(world, chronicle, &defines, hamlet);
+        }
+
+        frontier.founded += 1;
+    }
+}
@@ -0,0 +1,108 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Building;
+use crate::Owns;
+use crate::Settlement;
+use crate::PixelGrid;
+use crate::isochrone::Isochrones;
+use crate::symbol::Symbol;
+use crate::modifier::Modifiers;
+use crate::modifier::factor;
+
+pub struct Garrison { pub item: f32 }
+pub struct Coverage { pub item: f32 }
+
+#[derive(Clone, Deserialize)]
+pub struct GarrisonDefines {
+    pub share: f32,
+    pub rate: f32,
+    pub walls: f32,
+    pub fort: f32,
+    pub range: f32,
+}
+
+// Multiplier on the defender's strength from Walls and Fort levels.
+pub fn fortification(building: &Building, defines: &Defines) -> f32 {
+    1. + building.item[defines.building_i[&Symbol::new("Walls")]] * defines.garrison.walls
+        + building.item[defines.building_i[&Symbol::new("Fort")]] * defines.garrison.fort
+}
+
+pub fn siege_duration(base: f32, fortification: f32) -> f32 {
+    base * fortification
+}
+
+// Chance that the defenders hold against an assault.
+pub fn defense_odds(attackers: f32, garrison: f32, fortification: f32) -> f32 {
+    let defense = garrison * fortification;
+
+    defense / (defense + attackers).max(1e-6)
+}
+
+// Garrisons drift toward a fixed share of their settlement's population.
+pub fn raise_garrisons() -> Box<dyn Schedulable> {
+    SystemBuilder::new("raise_garrisons")
+        .read_resource::<Defines>()
+        .with_query(<(TryRead<Pop>, TryRead<Modifiers>, Write<Garrison>)>::query().filter(tag::<Settlement>()))
+        .build(|_, world, defines, query| {
+            let stat = Symbol::new("garrison");
+
+            for (pop, modifiers, mut garrison) in query.iter_mut(world) {
+                let target = pop.map_or(0., |pop| pop.item) * defines.garrison.share * factor(modifiers.as_deref(), stat);
+
+                garrison.item += (target - garrison.item) * defines.garrison.rate;
+            }
+        })
+}
+
+// Every administered pixel is covered by its settlement's fortified garrison,
+// falling off with the days it takes to march there, or with distance when
+// no isochrones are kept; banditry and unrest are meant to read Coverage.
+pub fn cover_garrisons(world: &mut World, resources: &mut Resources) {
+    let defines = resources.get::<Defines>().unwrap();
+    let grid = resources.get::<PixelGrid>();
+    let mut isochrones = resources.get_mut::<Isochrones>();
+    let size = defines.size;
+    let query = <(Read<Index>, Read<Garrison>, Read<Building>, Read<Owns>)>::query().filter(tag::<Settlement>());
+    let mut cover = Vec::new();
+
+    for (index, garrison, building, owns) in query.iter(world) {
+        let strength = garrison.item * fortification(&building, &defines);
+
+        for &pixel in owns.item.iter() {
+            cover.push((pixel, index.item, strength));
+        }
+    }
+
+    for mut coverage in <Write<Coverage>>::query().iter_mut(world) {
+        coverage.item = 0.;
+    }
+
+    for (pixel, center, strength) in cover.into_iter() {
+        let target = match world.get_component::<Index>(pixel) {
+            Some(index) => index.item,
+            None => continue,
+        };
+        let days = match (isochrones.as_deref_mut(), grid.as_deref()) {
+            (Some(isochrones), Some(grid)) => match isochrones.days(world, grid, center, target) {
+                Some(days) => days,
+                None => continue,
+            },
+            _ => {
+                let dx = (target % size) as f32 - (center % size) as f32;
+                let dy = (target / size) as f32 - (center / size) as f32;
+
+                (dx * dx + dy * dy).sqrt()
+            }
+        };
+        let falloff = 1. + days / defines.garrison.range;
+
+        if let Some(mut coverage) = world.get_component_mut::<Coverage>(pixel) {
+            coverage.item = strength / falloff;
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::time::Instant;
+
+pub const ESSENTIAL: u8 = u8::MAX;
+
+#[derive(Clone, Deserialize)]
+pub struct GovernorDefines {
+    pub budget: f64,
+    pub max_every: usize,
+}
+
+pub struct Step {
+    pub name: &'static str,
+    pub func: fn(&mut World, &mut Resources),
+    pub priority: u8,
+    pub every: usize,
+    pub cost: f64,
+}
+
+// Runs the thread-local steps it owns, timing each one. While a tick's steps
+// go over `budget` seconds, the costliest step of the lowest priority is run
+// half as often; once they are well under it, the most important throttled
+// step is given back its frequency. ESSENTIAL steps run every tick. Time
+// spent differs between machines, so a world kept in lockstep is never
+// throttled and runs every step every tick.
+pub struct Governor {
+    pub budget: f64,
+    pub max_every: usize,
+    pub adaptive: bool,
+    pub steps: Vec<Step>,
+    pub spent: f64,
+    tick: usize,
+}
+
+impl Governor {
+    pub fn new(defines: &GovernorDefines, lockstep: bool) -> Self {
+        Governor {
+            budget: defines.budget,
+            max_every: defines.max_every.max(1),
+            adaptive: !lockstep,
+            steps: Vec::new(),
+            spent: 0.,
+            tick: 0,
+        }
+    }
+
+    pub fn add(mut self, name: &'static str, func: fn(&mut World, &mut Resources), priority: u8) -> Self {
+        self.steps.push(Step { name, func, priority, every: 1, cost: 0. });
+        self
+    }
+
+    pub fn throttled(&self) -> Vec<&Step> {
+        self.steps.iter().filter(|step| step.every > 1).collect()
+    }
+
+    pub fn report(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| format!("{}: every {} tick(s), {:.3} ms", step.name, step.every, step.cost * 1000.))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn adjust(&mut self) {
+        let max_every = self.max_every;
+
+        if self.spent > self.budget {
+            let slowest = self.steps
+                .iter_mut()
+                .filter(|step| step.priority != ESSENTIAL && step.every < max_every)
+                .min_by(|a, b| a.priority.cmp(&b.priority).then(b.cost.partial_cmp(&a.cost).unwrap()));
+
+            if let Some(step) = slowest {
+                step.every = (step.every * 2).min(max_every);
+            }
+        } else if self.spent < self.budget / 2. {
+            let throttled = self.steps
+                .iter_mut()
+                .filter(|step| step.every > 1)
+                .max_by(|a, b| a.priority.cmp(&b.priority).then(b.cost.partial_cmp(&a.cost).unwrap()));
+
+            if let Some(step) = throttled {
+                step.every /= 2;
+            }
+        }
+    }
+}
+
+// The governor is taken out of the resources while its steps run, since they
+// need the resources mutably themselves.
+pub fn govern(world: &mut World, resources: &mut Resources) {
+    let mut governor = match resources.remove::<Governor>() {
+        Some(governor) => governor,
+        None => return,
+    };
+
+    governor.tick += 1;
+    governor.spent = 0.;
+
+    let tick = governor.tick;
+    let mut spent = 0.;
+
+    for step in governor.steps.iter_mut() {
+        if tick % step.every != 0 {
+            continue;
+        }
+
+        let start = Instant::now();
+
+        (step.func)(world, resources);
+
+        let cost = start.elapsed().as_secs_f64();
+
+        step.cost = 0.8 * step.cost + 0.2 * cost;
+        spent += cost;
+    }
+
+    governor.spent = spent;
+
+    if governor.adaptive {
+        governor.adjust();
+    }
+
+    resources.insert(governor);
+}
@@ -0,0 +1,179 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Veget;
+use crate::Heat;
+use crate::Water;
+use crate::Fertility;
+use crate::Building;
+use crate::Owned;
+use crate::Settlement;
+use crate::guild::Stock;
+use crate::garrison::Garrison;
+use crate::symbol::Symbol;
+use crate::condition::Condition;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::crops::Crop;
+use crate::crops::crop_yield;
+use crate::modifier::Modifiers;
+use crate::modifier::factor;
+
+// A policy applies in a year the store cannot feed everyone: each person
+// gets `ration` of a full share, and with `garrison_first` the garrison is
+// fed in full before the people get what is left.
+#[derive(Clone, Deserialize)]
+pub struct RationDefines {
+    pub name: Symbol,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+    pub ration: f32,
+    pub garrison_first: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GranaryDefines {
+    pub year: usize,
+    pub food: Symbol,
+    pub harvest: f32,
+    pub eat: f32,
+    pub storage: f32,
+    pub granary: f32,
+    pub rot: f32,
+    pub starvation: f32,
+    pub famine: f32,
+    pub policies: Vec<RationDefines>,
+}
+
+pub struct Granaries {
+    pub period: usize,
+    pub starved: f32,
+    pub policy: HashMap<Entity, Symbol>,
+    tick: usize,
+}
+
+impl Granaries {
+    pub fn new(period: usize) -> Self {
+        Granaries {
+            period,
+            starved: 0.,
+            policy: HashMap::new(),
+            tick: 0,
+        }
+    }
+}
+
+fn fed(food: f32, need: f32) -> f32 {
+    if need > 0. { (food / need).min(1.) } else { 1. }
+}
+
+// Once a year farms on a settlement's land bring in the harvest of its crop, everyone
+// eats, and what is left is carried over up to the storage the granaries
+// give, losing `rot` of it by the next year. Anything above that is lost.
+pub fn feed_settlements(world: &mut World, resources: &mut Resources) {
+    let mut granaries = match resources.get_mut::<Granaries>() {
+        Some(granaries) => granaries,
+        None => return,
+    };
+
+    granaries.tick += 1;
+
+    if granaries.period == 0 || granaries.tick % granaries.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let g = &defines.granary;
+    let food = defines.good_i[&g.food];
+    let farm = defines.building_i[&Symbol::new("Farm")];
+    let granary = defines.building_i[&Symbol::new("Granary")];
+    let mut harvest: HashMap<Entity, f32> = HashMap::new();
+
+    // Land of a settlement that has not picked a crop yet yields by vegetation;
+    // either way land of middling soil yields as much as it would without one.
+    for (building, veget, heat, water, owned, soil) in <(Read<Building>, Read<Veget>, Read<Heat>, Read<Water>, Read<Owned>, TryRead<Fertility>)>::query().iter(&*world) {
+        let fertility = match world.get_component::<Crop>(owned.item) {
+            Some(crop) => crop_yield(&defines.crops, crop.item, heat.item, water.item),
+            None => veget.item,
+        };
+        let soil = 0.5 + soil.map_or(0.5, |soil| soil.item);
+
+        *harvest.entry(owned.item).or_insert(0.) += building.item[farm] * fertility * soil * g.harvest;
+    }
+
+    let stat = Symbol::new("harvest");
+
+    for (settlement, amount) in harvest.iter_mut() {
+        *amount *= factor(world.get_component::<Modifiers>(*settlement).as_deref(), stat);
+    }
+
+    let query = <(Read<Index>, Read<Pop>, Read<Building>, Read<Stock>, TryRead<Garrison>)>::query().filter(tag::<Settlement>());
+    let settlements: Vec<(Entity, usize, f32, f32, f32, f32)> = query
+        .iter_entities(&*world)
+        .map(|(settlement, (index, pop, building, stock, garrison))| (
+            settlement, index.item, pop.item, garrison.map_or(0., |garrison| garrison.item),
+            stock.item[food], g.storage + building.item[granary] * g.granary,
+        ))
+        .collect();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+
+    granaries.starved = 0.;
+
+    for (settlement, index, pop, soldiers, stored, capacity) in settlements.into_iter() {
+        let store = stored + harvest.get(&settlement).cloned().unwrap_or(0.);
+        let full = (pop + soldiers) * g.eat;
+        let policy = g.policies.iter().find(|policy| policy.requires.as_ref().map_or(true, |c| c.eval(&*world, &defines, settlement)));
+
+        let (eaten, pop_fed, soldiers_fed) = match policy {
+            _ if store >= full => (full, 1., 1.),
+            None => (store, fed(store, full), fed(store, full)),
+            Some(policy) => {
+                let soldiers_need = soldiers * g.eat * policy.ration;
+                let pop_need = pop * g.eat * policy.ration;
+
+                if policy.garrison_first {
+                    let soldiers_food = store.min(soldiers_need);
+
+                    (store.min(soldiers_need + pop_need), fed(store - soldiers_food, pop_need), fed(soldiers_food, soldiers_need))
+                } else {
+                    (store.min(soldiers_need + pop_need), fed(store, soldiers_need + pop_need), fed(store, soldiers_need + pop_need))
+                }
+            }
+        };
+
+        let pop_lost = pop * (1. - pop_fed) * g.starvation;
+        let soldiers_lost = soldiers * (1. - soldiers_fed) * g.starvation;
+        let left = ((store - eaten).min(capacity) * (1. - g.rot)).max(0.);
+
+        if let Some(mut stock) = world.get_component_mut::<Stock>(settlement) {
+            stock.item[food] = left;
+        }
+        if let Some(mut p) = world.get_component_mut::<Pop>(settlement) {
+            p.item -= pop_lost;
+        }
+        if let Some(mut garrison) = world.get_component_mut::<Garrison>(settlement) {
+            garrison.item -= soldiers_lost;
+        }
+
+        match policy {
+            Some(policy) if store < full => granaries.policy.insert(settlement, policy.name),
+            _ => granaries.policy.remove(&settlement),
+        };
+
+        granaries.starved += pop_lost + soldiers_lost;
+
+        if let Some(chronicle) = chronicle.as_mut() {
+            if pop > 0. && pop_lost / pop >= g.famine {
+                let name = site_name(world, settlement);
+
+                chronicle.record(index, Symbol::new("Famine"), format!("famine struck {}, and {} people starved", name, pop_lost as usize));
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::io;
+use std::fs;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::Index;
+use crate::Name;
+use crate::Owns;
+use crate::Owned;
+use crate::Location;
+use crate::Pixel;
+use crate::Settlement;
+use crate::Ruin;
+use crate::Province;
+use crate::guild::Guild;
+use crate::caravan::Caravan;
+use crate::caravan::Ship;
+use crate::export::ExportManager;
+use crate::writer::Writer;
+
+#[derive(Clone, Deserialize)]
+pub struct GraphDefines {
+    pub tick: usize,
+    pub path: String,
+}
+
+pub struct GraphExport {
+    pub at: usize,
+    pub path: PathBuf,
+    tick: usize,
+}
+
+impl GraphExport {
+    pub fn new(defines: &GraphDefines, exports: &mut ExportManager) -> Self {
+        GraphExport {
+            at: defines.tick,
+            path: exports.path("graph", &defines.path),
+            tick: 0,
+        }
+    }
+}
+
+struct Node {
+    kind: &'static str,
+    label: String,
+}
+
+struct Graph {
+    nodes: BTreeMap<u32, Node>,
+    edges: Vec<(u32, u32, &'static str)>,
+}
+
+fn kind(world: &World, entity: Entity) -> &'static str {
+    match () {
+        _ if world.get_tag::<Settlement>(entity).is_some() => "Settlement",
+        _ if world.get_tag::<Ruin>(entity).is_some() => "Ruin",
+        _ if world.get_tag::<Province>(entity).is_some() => "Province",
+        _ if world.get_tag::<Guild>(entity).is_some() => "Guild",
+        _ if world.get_tag::<Caravan>(entity).is_some() => "Caravan",
+        _ if world.get_tag::<Ship>(entity).is_some() => "Ship",
+        _ if world.get_tag::<Pixel>(entity).is_some() => "Pixel",
+        _ => "Entity",
+    }
+}
+
+fn label(world: &World, entity: Entity) -> String {
+    let name = world.get_component::<Name>(entity).map(|name| name.item.as_str().to_string());
+    let index = world.get_component::<Index>(entity).map(|index| index.item);
+
+    match (name, index) {
+        (Some(name), _) => name,
+        (None, Some(index)) => format!("pixel {}", index),
+        (None, None) => format!("{}", entity),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Graph {
+    fn node(&mut self, world: &World, entity: Entity) -> u32 {
+        let id = entity.index();
+
+        self.nodes.entry(id).or_insert_with(|| Node { kind: kind(world, entity), label: label(world, entity) });
+
+        id
+    }
+
+    // Owned is the inverse of Owns, so only the back links that Owns does not
+    // already cover are drawn, which is where the two have drifted apart.
+    fn collect(world: &World) -> Self {
+        let mut graph = Graph { nodes: BTreeMap::new(), edges: Vec::new() };
+        let mut owns = HashSet::new();
+
+        for (entity, item) in Read::<Owns>::query().iter_entities(world) {
+            let from = graph.node(world, entity);
+
+            for &pixel in item.item.iter() {
+                let to = graph.node(world, pixel);
+
+                owns.insert((from, to));
+                graph.edges.push((from, to, "owns"));
+            }
+        }
+
+        for (entity, owned) in Read::<Owned>::query().iter_entities(world) {
+            if !owns.contains(&(owned.item.index(), entity.index())) {
+                let from = graph.node(world, entity);
+                let to = graph.node(world, owned.item);
+
+                graph.edges.push((from, to, "owned"));
+            }
+        }
+
+        for (entity, location) in Read::<Location>::query().iter_entities(world) {
+            let from = graph.node(world, entity);
+            let to = graph.node(world, location.item);
+
+            graph.edges.push((from, to, "location"));
+        }
+
+        graph
+    }
+
+    fn dot(&self) -> String {
+        let mut out = String::from("digraph world {\n");
+
+        for (id, node) in self.nodes.iter() {
+            out += &format!("  n{} [label=\"{}\", kind=\"{}\"];\n", id, node.label.replace('"', "\\\""), node.kind);
+        }
+        for &(from, to, relation) in self.edges.iter() {
+            out += &format!("  n{} -> n{} [label=\"{}\"];\n", from, to, relation);
+        }
+
+        out + "}\n"
+    }
+
+    fn graphml(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n \
+             <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n \
+             <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n \
+             <key id=\"relation\" for=\"edge\" attr.name=\"relation\" attr.type=\"string\"/>\n \
+             <graph id=\"world\" edgedefault=\"directed\">\n",
+        );
+
+        for (id, node) in self.nodes.iter() {
+            out += &format!(
+                "  <node id=\"n{}\"><data key=\"label\">{}</data><data key=\"kind\">{}</data></node>\n",
+                id, escape(&node.label), node.kind,
+            );
+        }
+        for &(from, to, relation) in self.edges.iter() {
+            out += &format!("  <edge source=\"n{}\" target=\"n{}\"><data key=\"relation\">{}</data></edge>\n", from, to, relation);
+        }
+
+        out + " </graph>\n</graphml>\n"
+    }
+}
+
+// GraphML for a .graphml path and DOT otherwise.
+fn render(world: &World, path: &Path) -> String {
+    let graph = Graph::collect(world);
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("graphml") => graph.graphml(),
+        _ => graph.dot(),
+    }
+}
+
+pub fn export_graph<P: AsRef<Path>>(world: &World, path: P) -> io::Result<()> {
+    fs::write(path.as_ref(), render(world, path.as_ref()))
+}
+
+pub fn export_graph_at(world: &mut World, resources: &mut Resources) {
+    let mut export = match resources.get_mut::<GraphExport>() {
+        Some(export) => export,
+        None => return,
+    };
+
+    export.tick += 1;
+
+    if export.tick != export.at {
+        return;
+    }
+
+    let path = export.path.clone();
+    let text = render(world, &path);
+
+    match resources.get::<Writer>() {
+        Some(writer) => writer.submit(move || fs::write(path, text)),
+        None => fs::write(path, text).unwrap(),
+    }
+}
@@ -0,0 +1,106 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::collections::HashMap;
+
+use crate::Index;
+use crate::Settlement;
+use crate::Defines;
+use crate::symbol::Symbol;
+use crate::condition::Condition;
+use crate::modifier::Modifier;
+use crate::modifier::Modifiers;
+use crate::modifier::add_modifier;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::random::WorldRng;
+
+// `chance` is per settlement and year; `modifiers` maps a stat to the factor
+// it is multiplied by for `duration` ticks, or for good without one.
+#[derive(Clone, Deserialize)]
+pub struct GreatDefines {
+    pub name: Symbol,
+    pub title: String,
+    pub chance: f32,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+    pub modifiers: HashMap<Symbol, f32>,
+    #[serde(default)]
+    pub duration: Option<u32>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GreatsDefines {
+    pub year: usize,
+    pub greats: Vec<GreatDefines>,
+}
+
+pub struct Greats {
+    pub period: usize,
+    pub risen: usize,
+    tick: usize,
+}
+
+impl Greats {
+    pub fn new(period: usize) -> Self {
+        Greats {
+            period,
+            risen: 0,
+            tick: 0,
+        }
+    }
+}
+
+// Once a year each settlement may see a great person rise, at most one of
+// each kind at a time, whose deeds are written into the chronicle.
+pub fn raise_greats(world: &mut World, resources: &mut Resources) {
+    let mut greats = match resources.get_mut::<Greats>() {
+        Some(greats) => greats,
+        None => return,
+    };
+
+    greats.tick += 1;
+
+    if greats.period == 0 || greats.tick % greats.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let mut chronicle = resources.get_mut::<Chronicle>();
+    let mut rng = resources.get::<WorldRng>().unwrap().get("greats");
+
+    let query = <(Read<Index>, TryRead<Modifiers>)>::query().filter(tag::<Settlement>());
+    let mut risen = Vec::new();
+
+    for (settlement, (index, modifiers)) in query.iter_entities(&*world) {
+        for (g, great) in defines.greats.greats.iter().enumerate() {
+            if modifiers.as_ref().map_or(false, |modifiers| modifiers.from(great.name)) {
+                continue;
+            }
+            if rng.gen::<f32>() >= great.chance {
+                continue;
+            }
+            if great.requires.as_ref().map_or(true, |requires| requires.eval(&*world, &defines, settlement)) {
+                risen.push((settlement, index.item, g));
+                break;
+            }
+        }
+    }
+
+    for (settlement, index, g) in risen.into_iter() {
+        let great = &defines.greats.greats[g];
+
+        for (&stat, &factor) in great.modifiers.iter() {
+            add_modifier(world, settlement, Modifier { stat, factor, left: great.duration, source: great.name });
+        }
+
+        if let Some(chronicle) = chronicle.as_deref_mut() {
+            chronicle.record(index, great.name, format!("{} rose in {}", great.title, site_name(world, settlement)));
+        }
+
+        greats.risen += 1;
+    }
+}
@@ -0,0 +1,253 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Location;
+use crate::Skill;
+use crate::Building;
+use crate::Owned;
+use crate::Settlement;
+use crate::symbol::Symbol;
+use crate::condition::reads;
+use crate::condition::Condition;
+use crate::caravan::Cargo;
+use crate::validate::Breach;
+use crate::modifier::Modifiers;
+use crate::modifier::factor;
+use crate::fixed::Real;
+use crate::fixed::real;
+use crate::fixed::to_f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Guild;
+
+pub struct Stock { pub item: Vec<f32> }
+
+// A settlement that meets `founded` when it is founded starts out with a
+// guild working the recipe.
+#[derive(Clone, Deserialize)]
+pub struct RecipeDefines {
+    pub name: Symbol,
+    pub building: Symbol,
+    pub inputs: HashMap<Symbol, f32>,
+    pub outputs: HashMap<Symbol, f32>,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+    #[serde(default)]
+    pub founded: Option<Condition>,
+}
+
+// Every `year` each pixel a settlement administers that meets `requires`
+// yields `amount` of `good`, and `amount` more per level of `building` on it.
+#[derive(Clone, Deserialize)]
+pub struct ExtractDefines {
+    pub good: Symbol,
+    pub amount: f32,
+    #[serde(default)]
+    pub building: Option<Symbol>,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ExtractionDefines {
+    pub year: usize,
+    pub goods: Vec<ExtractDefines>,
+}
+
+pub struct Extraction {
+    pub period: usize,
+    tick: usize,
+}
+
+impl Extraction {
+    pub fn new(period: usize) -> Self {
+        Extraction {
+            period,
+            tick: 0,
+        }
+    }
+}
+
+// A new guild sets up a level of the building its recipe is worked in.
+pub fn found_guild(world: &mut World, defines: &Defines, settlement: Entity, recipe: usize) -> Entity {
+    let mut skill = vec![0.; defines.recipes.len()];
+    skill[recipe] = 1.;
+
+    if let Some(mut building) = world.get_component_mut::<Building>(settlement) {
+        building.item[defines.building_i[&defines.recipes[recipe].building]] += 1.;
+    }
+
+    world.insert(
+        (Guild,),
+        vec![(
+            Location { item: settlement },
+            Skill { item: skill },
+        )]
+    )[0]
+}
+
+pub fn found_guilds(world: &mut World, defines: &Defines, settlement: Entity) {
+    let recipes: Vec<usize> = defines.recipes
+        .iter()
+        .enumerate()
+        .filter(|(_, recipe)| recipe.founded.as_ref().map_or(false, |founded| founded.eval(&*world, defines, settlement)))
+        .map(|(r, _)| r)
+        .collect();
+
+    for recipe in recipes.into_iter() {
+        found_guild(world, defines, settlement, recipe);
+    }
+}
+
+// Raw goods gathered from the land go into the stock of the settlement
+// administering it, scaled by its production modifiers.
+pub fn extract_goods(world: &mut World, resources: &mut Resources) {
+    let mut extraction = match resources.get_mut::<Extraction>() {
+        Some(extraction) => extraction,
+        None => return,
+    };
+
+    extraction.tick += 1;
+
+    if extraction.period == 0 || extraction.tick % extraction.period != 0 {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let goods = defines.good_i.len();
+    let mut gathered: HashMap<Entity, Vec<f32>> = HashMap::new();
+
+    for (pixel, (owned, building)) in <(Read<Owned>, Read<Building>)>::query().iter_entities(&*world) {
+        for extract in defines.extraction.goods.iter() {
+            if !extract.requires.as_ref().map_or(true, |requires| requires.eval(&*world, &defines, pixel)) {
+                continue;
+            }
+
+            let level = extract.building.map_or(0., |b| building.item[defines.building_i[&b]]);
+
+            gathered.entry(owned.item).or_insert_with(|| vec![0.; goods])[defines.good_i[&extract.good]] += extract.amount * (1. + level);
+        }
+    }
+
+    let stat = Symbol::new("production");
+
+    for (settlement, amounts) in gathered.into_iter() {
+        let boost = factor(world.get_component::<Modifiers>(settlement).as_deref(), stat);
+
+        if let Some(mut stock) = world.get_component_mut::<Stock>(settlement) {
+            for (g, amount) in amounts.into_iter().enumerate() {
+                stock.item[g] += amount * boost;
+            }
+        }
+    }
+}
+
+// Each recipe runs at most `skill * building level` batches a tick at a
+// settlement, limited by the scarcest input, its outputs scaled by the
+// settlement's production modifiers. Guild skill grows with use.
+pub fn produce() -> Box<dyn Schedulable> {
+    reads(SystemBuilder::new("produce"))
+        .read_resource::<Defines>()
+        .with_query(<(Read<Location>, Read<Skill>)>::query().filter(tag::<Guild>()))
+        .with_query(<(Read<Building>, TryRead<Modifiers>, Write<Stock>)>::query().filter(tag::<Settlement>()))
+        .with_query(<(Read<Location>, Write<Skill>)>::query().filter(tag::<Guild>()))
+        .build(|_, world, defines, (guild_query, stock_query, skill_query)| {
+            let mut rates: HashMap<Entity, Vec<Real>> = HashMap::new();
+
+            for (location, skill) in guild_query.iter(&*world) {
+                let rate = rates.entry(location.item).or_insert_with(|| vec![real(0.); defines.recipes.len()]);
+
+                for (r, &s) in skill.item.iter().enumerate() {
+                    rate[r] += real(s);
+                }
+            }
+
+            let allowed: HashMap<Entity, Vec<bool>> = rates
+                .keys()
+                .map(|&settlement| {
+                    let allowed = defines.recipes
+                        .iter()
+                        .map(|recipe| recipe.requires.as_ref().map_or(true, |requires| requires.eval(&*world, defines, settlement)))
+                        .collect();
+
+                    (settlement, allowed)
+                })
+                .collect();
+            let mut used: HashMap<Entity, Vec<Real>> = HashMap::new();
+            let stat = Symbol::new("production");
+
+            for (settlement, (building, modifiers, mut stock)) in stock_query.iter_entities_mut(world) {
+                let rate = match rates.get(&settlement) {
+                    Some(rate) => rate,
+                    None => continue,
+                };
+                let boost = real(factor(modifiers.as_deref(), stat));
+                let mut batches = vec![real(0.); defines.recipes.len()];
+
+                for (r, recipe) in defines.recipes.iter().enumerate() {
+                    if !allowed[&settlement][r] {
+                        continue;
+                    }
+
+                    let mut batch = rate[r] * real(building.item[defines.building_i[&recipe.building]]);
+
+                    for (good, &amount) in recipe.inputs.iter() {
+                        batch = batch.min(real(stock.item[defines.good_i[good]]) / real(amount));
+                    }
+                    if batch <= real(0.) {
+                        continue;
+                    }
+
+                    for (good, &amount) in recipe.inputs.iter() {
+                        let held = &mut stock.item[defines.good_i[good]];
+
+                        *held = to_f32(real(*held) - batch * real(amount));
+                    }
+                    for (good, &amount) in recipe.outputs.iter() {
+                        let held = &mut stock.item[defines.good_i[good]];
+
+                        *held = to_f32(real(*held) + batch * real(amount) * boost);
+                    }
+
+                    batches[r] = batch / rate[r];
+                }
+
+                used.insert(settlement, batches);
+            }
+
+            for (location, mut skill) in skill_query.iter_mut(world) {
+                if let Some(batches) = used.get(&location.item) {
+                    for (r, s) in skill.item.iter_mut().enumerate() {
+                        if *s > 0. {
+                            let room = (real(1.) - real(*s) / real(defines.guild_skill_max)).max(real(0.));
+
+                            *s = to_f32(real(*s) + real(defines.guild_learning) * batches[r] * room);
+                        }
+                    }
+                }
+            }
+        })
+}
+
+fn goods_breach(entity: Entity, what: &str, goods: &Vec<f32>, epsilon: f32) -> Option<Breach> {
+    goods
+        .iter()
+        .position(|&amount| !amount.is_finite() || amount < -epsilon)
+        .map(|good| Breach { entity: Some(entity), message: format!("{} of good {} is {}", what, good, goods[good]) })
+}
+
+// Goods may dip below zero by rounding, but never by more than `epsilon`.
+pub fn goods_non_negative(world: &World, _: &Resources, epsilon: f32) -> Vec<Breach> {
+    let stocks = Read::<Stock>::query()
+        .iter_entities(world)
+        .filter_map(|(entity, stock)| goods_breach(entity, "stock", &stock.item, epsilon));
+    let cargoes = Read::<Cargo>::query()
+        .iter_entities(world)
+        .filter_map(|(entity, cargo)| goods_breach(entity, "cargo", &cargo.item, epsilon));
+
+    stocks.chain(cargoes).collect()
+}
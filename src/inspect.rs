@@ -0,0 +1,76 @@
+use legion::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Building;
+use crate::Land;
+use crate::Skill;
+use crate::guild::Stock;
+use crate::caravan::Cargo;
+use crate::registry::Registry;
+use crate::registry::EntityIds;
+use crate::symbol::Symbol;
+
+fn labels(index: &HashMap<Symbol, usize>) -> Vec<Symbol> {
+    let mut labels: Vec<(usize, Symbol)> = index.iter().map(|(&name, &i)| (i, name)).collect();
+
+    labels.sort_by_key(|&(i, _)| i);
+    labels.into_iter().map(|(_, name)| name).collect()
+}
+
+fn labelled(values: &Vec<f32>, labels: &Vec<Symbol>) -> String {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match labels.get(i) {
+            Some(label) => format!("{}={}", label, value),
+            None => format!("{}={}", i, value),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// One line per component the entity has, in registry order. Value vectors
+// indexed by a defines table are printed with their names, and entity
+// references by their index.
+pub fn dump_entity(world: &World, registry: &Registry, defines: &Defines, entity: Entity) -> String {
+    if !world.is_alive(entity) {
+        return format!("{:?} does not exist", entity);
+    }
+
+    let mut ids = EntityIds::new();
+
+    for other in world.iter_entities() {
+        ids.insert(other, other.index() as u64);
+    }
+
+    let buildings = labels(&defines.building_i);
+    let lands = labels(&defines.land_i);
+    let goods = labels(&defines.good_i);
+    let recipes: Vec<Symbol> = defines.recipes.iter().map(|recipe| recipe.name).collect();
+    let mut out = format!("{:?}", entity);
+
+    for entry in registry.entries.iter() {
+        let text = match (entry.serialize)(world, entity, &ids) {
+            Some(text) => text,
+            None => continue,
+        };
+        let text = match entry.name {
+            "Building" => world.get_component::<Building>(entity).map(|c| labelled(&c.item, &buildings)),
+            "Land" => world.get_component::<Land>(entity).map(|c| labelled(&c.item, &lands)),
+            "Skill" => world.get_component::<Skill>(entity).map(|c| labelled(&c.item, &recipes)),
+            "Stock" => world.get_component::<Stock>(entity).map(|c| labelled(&c.item, &goods)),
+            "Cargo" => world.get_component::<Cargo>(entity).map(|c| labelled(&c.item, &goods)),
+            _ => None,
+        }.unwrap_or(text);
+
+        if text.is_empty() {
+            out += &format!("\n  {}", entry.name);
+        } else {
+            out += &format!("\n  {}: {}", entry.name, text);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,74 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use crate::Defines;
+use crate::Building;
+use crate::Neighb;
+use crate::Index;
+use crate::PixelGrid;
+use crate::Aquifer;
+use crate::River;
+use crate::Water;
+use crate::WaterBase;
+use crate::Sea;
+use crate::Lake;
+use crate::scratch::with_scratch;
+use crate::fixed::real;
+use crate::fixed::to_f32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum WaterSource {
+    River,
+    Aquifer,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IrrigationDefines {
+    pub bonus: f32,
+    pub source: WaterSource,
+}
+
+// A pixel's Water is rebuilt from WaterBase every tick, adding the bonus of
+// each completed irrigation building whose water source the pixel can reach.
+pub fn irrigation() -> Box<dyn Schedulable> {
+    SystemBuilder::new("irrigation")
+        .read_resource::<Defines>()
+        .read_resource::<PixelGrid>()
+        .with_query(<(Read<River>, Read<Index>)>::query().filter(!tag::<Sea>()))
+        .with_query(<(Read<Index>, Read<Building>, TryRead<Neighb>, Read<Aquifer>, Read<WaterBase>, Write<Water>)>::query().filter(!tag::<Sea>() & !tag::<Lake>()))
+        .build(|_, world, (defines, grid), (river_query, water_query)| {
+            let mut rivers = with_scratch(|scratch| scratch.index_sets.take());
+
+            rivers.extend(river_query
+                .iter(&*world)
+                .filter(|(river, _)| river.item >= defines.river_access)
+                .map(|(_, index)| index.item));
+
+            for (index, building, neighb, aquifer, base, mut water) in water_query.iter_mut(world) {
+                let neighb = grid.around(neighb.as_deref(), index.item);
+                let river = rivers.contains(&index.item) || neighb.iter().any(|n| rivers.contains(&n));
+                let mut bonus = real(0.);
+
+                for (name, irrigation) in defines.irrigation.iter() {
+                    match building.item.get(defines.building_i[name]) {
+                        Some(&level) if level >= 1. => {},
+                        _ => continue,
+                    }
+
+                    let access = match irrigation.source {
+                        WaterSource::River => river,
+                        WaterSource::Aquifer => aquifer.item >= defines.aquifer_access,
+                    };
+
+                    if access {
+                        bonus += real(irrigation.bonus);
+                    }
+                }
+
+                water.item = to_f32((real(base.item) + bonus).min(real(1.)));
+            }
+
+            with_scratch(|scratch| scratch.index_sets.give(rivers));
+        })
+}
@@ -0,0 +1,130 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Height;
+use crate::Building;
+use crate::Sea;
+use crate::Navigable;
+use crate::PixelGrid;
+use crate::path::FlowField;
+use crate::path::RIVER_FACTOR;
+use crate::path::step_cost;
+use crate::tags::TagEvents;
+use crate::tags::TagKind;
+
+// `day` is how much travel cost is covered in a day. Sailing a sea pixel
+// costs `sea`, stepping between land and sea costs `embark`, and nothing
+// past `horizon` days is computed.
+#[derive(Clone, Deserialize)]
+pub struct IsochroneDefines {
+    pub day: f32,
+    pub sea: f32,
+    pub embark: f32,
+    pub horizon: f32,
+    pub period: usize,
+    pub capacity: usize,
+}
+
+fn height(world: &World, pixel: Entity) -> f32 {
+    world.get_component::<Height>(pixel).map_or(0., |height| height.item)
+}
+
+// Overland steps are sped up by roads and navigable rivers the way caravans
+// are, and ships may go anywhere at sea.
+fn travel_step(world: &World, defines: &IsochroneDefines, road: usize, from: Entity, to: Entity) -> f32 {
+    match (world.get_tag::<Sea>(from).is_some(), world.get_tag::<Sea>(to).is_some()) {
+        (true, true) => defines.sea,
+        (false, false) => {
+            let road = world.get_component::<Building>(to).map_or(0., |building| building.item[road]);
+            let cost = step_cost(height(world, from), height(world, to), road);
+
+            match world.get_tag::<Navigable>(from).is_some() && world.get_tag::<Navigable>(to).is_some() {
+                true => cost * RIVER_FACTOR,
+                false => cost,
+            }
+        }
+        _ => defines.embark,
+    }
+}
+
+// Travel days outward from each source pixel asked for, built on demand and
+// shared by everything asking. Fields are dropped when water or navigable
+// rivers change, every `period` ticks so new roads are picked up, and all at
+// once when more than `capacity` are held.
+pub struct Isochrones {
+    pub defines: IsochroneDefines,
+    pub road: usize,
+    fields: HashMap<usize, FlowField>,
+    subscriber: Option<usize>,
+    tick: usize,
+}
+
+impl Isochrones {
+    pub fn new(defines: &IsochroneDefines, road: usize) -> Self {
+        Isochrones {
+            defines: defines.clone(),
+            road,
+            fields: HashMap::new(),
+            subscriber: None,
+            tick: 0,
+        }
+    }
+
+    pub fn refresh(&mut self, events: &mut TagEvents) {
+        let subscriber = *self.subscriber.get_or_insert_with(|| events.subscribe());
+        let stale = events
+            .read(subscriber)
+            .iter()
+            .any(|change| match change.kind {
+                TagKind::Sea | TagKind::Lake | TagKind::Navigable => true,
+                _ => false,
+            });
+
+        self.tick += 1;
+
+        if stale || (self.defines.period > 0 && self.tick % self.defines.period == 0) {
+            self.fields.clear();
+        }
+    }
+
+    pub fn field(&mut self, world: &World, grid: &PixelGrid, from: usize) -> &FlowField {
+        if self.fields.len() >= self.defines.capacity && !self.fields.contains_key(&from) {
+            self.fields.clear();
+        }
+
+        let defines = &self.defines;
+        let road = self.road;
+
+        self.fields.entry(from).or_insert_with(|| {
+            let step = |a: Entity, b: Entity| Some(travel_step(world, defines, road, b, a));
+
+            FlowField::build_by(world, grid, &vec![from], step, defines.horizon * defines.day)
+        })
+    }
+
+    pub fn days(&mut self, world: &World, grid: &PixelGrid, from: usize, to: usize) -> Option<f32> {
+        let day = self.defines.day;
+
+        self.field(world, grid, from).dist(to).map(|dist| dist / day)
+    }
+
+    pub fn within(&mut self, world: &World, grid: &PixelGrid, from: usize, days: f32) -> Vec<usize> {
+        let day = self.defines.day;
+        let field = self.field(world, grid, from);
+
+        (0..grid.item.len()).filter(|&index| field.dist(index).map_or(false, |dist| dist / day <= days)).collect()
+    }
+
+    // Days for every pixel, those past the horizon at the horizon, ready for
+    // overlay::render.
+    pub fn overlay(&mut self, world: &World, grid: &PixelGrid, from: usize) -> Vec<f64> {
+        let day = self.defines.day;
+        let horizon = self.defines.horizon;
+        let field = self.field(world, grid, from);
+
+        (0..grid.item.len()).map(|index| field.dist(index).map_or(horizon, |dist| dist / day) as f64).collect()
+    }
+}
@@ -0,0 +1,12 @@
+pub mod map;
+pub mod colormap;
+pub mod npy;
+pub mod dem;
+pub mod pyramid;
+pub mod fixed;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
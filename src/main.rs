@@ -3,7 +3,64 @@
 
 extern crate num_cpus;
 
-mod map;
+mod climate;
+mod irrigation;
+mod caravan;
+mod guild;
+mod census;
+mod timeline;
+mod overlay;
+mod server;
+mod registry;
+mod save;
+mod replica;
+mod memory;
+mod scratch;
+mod path;
+mod dirty;
+mod admin;
+mod minimap;
+mod symbol;
+mod condition;
+mod mods;
+mod names;
+mod tiled;
+mod metadata;
+mod sweep;
+mod command;
+mod recorder;
+mod inspect;
+mod garrison;
+mod frontier;
+mod decay;
+mod ruins;
+mod storm;
+mod market;
+mod governor;
+mod graph;
+mod export;
+mod writer;
+mod zoom;
+mod tags;
+mod trade;
+mod granary;
+mod crops;
+mod ancestry;
+mod validate;
+mod modifier;
+mod great;
+mod blight;
+mod isochrone;
+mod trigger;
+mod personality;
+mod report;
+mod random;
+
+use prototype4::map;
+use prototype4::colormap;
+use prototype4::npy;
+use prototype4::dem::Dem;
+use prototype4::fixed;
 
 use legion::prelude::*;
 use legion::entity::Entity;
@@ -18,7 +75,8 @@ use half::f16;
 use serde::Deserialize;
 use ron::de::from_reader;
 
-use rand::thread_rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use rand::Rng;
 
 use std::sync::Arc;
@@ -31,11 +89,14 @@ use std::sync::mpsc::Receiver;
 use std::sync::mpsc::channel;
 use std::collections::HashMap;
 use std::any::Any;
+use std::env;
 use std::path::PathBuf;
 use std::fs::File;
 use std::cmp::max_by;
 use std::cmp::min_by;
 
+use symbol::Symbol;
+
 struct Wrapper<T> {
     item: T,
 }
@@ -62,14 +123,96 @@ enum BuildingAction {
 
 #[derive(Clone, Deserialize)]
 struct Defines {
+    #[serde(skip)]
     size: usize,
-    building_i: HashMap<String, usize>,
-    land_i: HashMap<String, usize>,
+    #[serde(skip)]
+    seed: u64,
+    seed_tries: u64,
+    worldgen: String,
+    export_root: String,
+    building_i: HashMap<Symbol, usize>,
+    land_i: HashMap<Symbol, usize>,
+    temp_drift: f32,
+    sea_drift: f32,
+    sea_step: f32,
+    irrigation: HashMap<Symbol, irrigation::IrrigationDefines>,
+    river_access: f32,
+    aquifer_access: f32,
+    good_i: HashMap<Symbol, usize>,
+    trade: HashMap<Symbol, trade::TradeDefines>,
+    caravans: trade::CaravanDefines,
+    recipes: Vec<guild::RecipeDefines>,
+    extraction: guild::ExtractionDefines,
+    guild_learning: f32,
+    guild_skill_max: f32,
+    census_period: usize,
+    timeline_period: usize,
+    timeline_capacity: usize,
+    server_addr: Option<String>,
+    server_period: usize,
+    replica: Option<replica::ReplicaDefines>,
+    memory_period: usize,
+    memory: memory::MemoryDefines,
+    display: dirty::DisplayDefines,
+    admin_range: f32,
+    settlement_density: f32,
+    minimap_scale: usize,
+    minimap_period: usize,
+    minimap_capacity: usize,
+    culture: Symbol,
+    naming: names::NamingDefines,
+    dem: Option<DemDefines>,
+    lockstep_delay: u64,
+    recording: Option<recorder::RecordingDefines>,
+    graph: Option<graph::GraphDefines>,
+    report: Option<report::ReportDefines>,
+    autosave: Option<save::AutosaveDefines>,
+    garrison: garrison::GarrisonDefines,
+    frontier: frontier::FrontierDefines,
+    decay: decay::DecayDefines,
+    ruins: ruins::RuinsDefines,
+    synthetic: Option<map::Synthetic>,
+    #[serde(skip)]
+    wrap: map::WrapMode,
+    terrain: map::Terrain,
+    thermal: Option<map::Thermal>,
+    noise: map::NoiseDefines,
+    styles: HashMap<String, colormap::Style>,
+    seasons: Option<map::Seasons>,
+    currents: Option<map::Currents>,
+    glaciers: Option<map::Glaciers>,
+    orography: Option<map::Orography>,
+    basins: Option<map::Basins>,
+    rivers: Option<map::Rivers>,
+    provinces: Option<map::Provinces>,
+    borders: Option<map::BorderStyle>,
+    groundwater: Option<map::Groundwater>,
+    navigation: path::NavigationDefines,
+    storm: storm::StormDefines,
+    market: market::MarketDefines,
+    granary: granary::GranaryDefines,
+    crops: crops::CropsDefines,
+    ancestry: ancestry::AncestryDefines,
+    governor: governor::GovernorDefines,
+    validate: validate::ValidateDefines,
+    greats: great::GreatsDefines,
+    blight: blight::BlightDefines,
+    isochrone: isochrone::IsochroneDefines,
+    events: Vec<trigger::EventDefines>,
+    personalities: Vec<personality::PersonalityDefines>,
+    #[serde(default)]
+    mods: Vec<Symbol>,
+}
+
+#[derive(Clone, Deserialize)]
+struct DemDefines {
+    path: String,
+    sea_level: f64,
 }
 
 #[derive(Clone, Deserialize)]
 struct BuildingDefines {
-    name: String,
+    name: Symbol,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -82,20 +225,32 @@ struct Sea;
 struct Settlement;
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Colony;
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ruin;
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Navigable;
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ice;
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Province;
 
 struct Owned { item: Entity }
 struct Owns { item: Vec<Entity> }
 struct Location { item: Entity }
-struct Name { item: String }
+struct Name { item: Symbol }
 struct Water { item: f32 }
+struct WaterBase { item: f32 }
+struct Aquifer { item: f32 }
 struct River { item: f32 }
 struct Rain { item: f32 }
 struct Heat { item: f32 }
 struct Height { item: f32 }
 struct Veget { item: f32 }
-struct Neighb { item: Vec<Entity> }
+#[derive(Clone, Copy)]
+struct Neighb { item: [u32; 8] }
 struct RiverBase { item: f32 }
 struct VegetBase { item: f32 }
+struct HeatBase { item: f32 }
 struct Speed { item: f32 }
 struct Capacity { item: f32 }
 struct Index { item: usize }
@@ -103,12 +258,202 @@ struct Pop { item: f32 }
 struct Skill { item: Vec<f32> }
 struct Building { item: Vec<f32> }
 struct Land { item: Vec<f32> }
+struct Biome { item: map::Biome }
+struct Fertility { item: f32 }
+
+const NO_NEIGHB: u32 = u32::MAX;
+
+impl Neighb {
+    fn new(neighbs: &Vec<(usize, f64)>) -> Self {
+        let mut item = [NO_NEIGHB; 8];
+
+        for (slot, &(i, _)) in item.iter_mut().zip(neighbs.iter()) {
+            *slot = i as u32;
+        }
+
+        Neighb { item }
+    }
+
+    fn at(index: usize, size: usize, wrap: map::WrapMode) -> Self {
+        let mut item = [NO_NEIGHB; 8];
+        let near = map::OFFSETS.iter().filter_map(|&(dx, dy)| map::neighbor_at(index, dx, dy, size, wrap));
+
+        for (slot, i) in item.iter_mut().zip(near) {
+            *slot = i as u32;
+        }
+
+        Neighb { item }
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        self.item.iter().filter(|&&i| i != NO_NEIGHB).map(|&i| i as usize)
+    }
+}
+
+struct PixelGrid {
+    item: Vec<Entity>,
+    size: usize,
+    wrap: map::WrapMode,
+}
+
+impl PixelGrid {
+    fn from_world(world: &World, size: usize, wrap: map::WrapMode) -> Self {
+        let query = <Read<Index>>::query().filter(tag::<Pixel>());
+        let mut pixels: Vec<(usize, Entity)> = query
+            .iter_entities(world)
+            .map(|(pixel, index)| (index.item, pixel))
+            .collect();
+
+        pixels.sort_by_key(|&(index, _)| index);
+
+        PixelGrid {
+            item: pixels.into_iter().map(|(_, pixel)| pixel).collect(),
+            size,
+            wrap,
+        }
+    }
+
+    fn get(&self, index: usize) -> Entity {
+        self.item[index]
+    }
+
+    // The pixel's own neighbour table, or the same worked out from its index
+    // when the lean memory profile left the tables out.
+    fn around(&self, neighb: Option<&Neighb>, index: usize) -> Neighb {
+        match neighb {
+            Some(&neighb) => neighb,
+            None => Neighb::at(index, self.size, self.wrap),
+        }
+    }
+
+    fn neighb(&self, world: &World, index: usize) -> Neighb {
+        self.around(world.get_component::<Neighb>(self.item[index]).as_deref(), index)
+    }
+
+    fn neighbs<'a>(&'a self, neighb: &'a Neighb) -> impl Iterator<Item = Entity> + 'a {
+        neighb.iter().map(move |i| self.item[i])
+    }
+}
+
+// A layer named in `styles` is exported in its colormap with its title and
+// colour bar, any other in grayscale. Without a range the layer's own
+// lowest and highest values are used.
+fn export_layer(map: &map::ProvBuilder, exports: &mut export::ExportManager, styles: &HashMap<String, colormap::Style>, name: &str, values: &Vec<f64>, range: Option<(f64, f64)>) {
+    let path = exports.path(name, &format!("{}.png", name));
+    let (min, max) = range.unwrap_or_else(|| {
+        values.iter().fold((f64::MAX, f64::MIN), |(min, max), &value| (min.min(value), max.max(value)))
+    });
+
+    match (styles.get(name), range) {
+        (Some(style), _) => map.export_styled(values, path, min, max, style),
+        (None, Some(_)) => map.export_minmax(values, path, min, max),
+        (None, None) => map.export(values, path),
+    }
+}
+
+fn spawn_pixels(world: &mut World, names: &mut names::Names, map: &map::ProvBuilder, defines: &Defines) -> Vec<Entity> {
+    let buildings = defines.building_i.len();
+    let goods = defines.good_i.len();
+    let mut rng = StdRng::seed_from_u64(map.seed);
+
+    let pixels = world.insert(
+        (Pixel,),
+        (0..map.size * map.size).map(|i| {
+            (
+                Height { item: map.heightmap[i] as f32 },
+                Heat { item: map.tempmap[i] as f32 },
+                River { item: map.rivermap[i] as f32 },
+                Rain { item: map.cloudmap[i] as f32 },
+                Veget { item: map.vegetmap[i] as f32 },
+                Water { item: map.watermap[i] as f32 },
+                WaterBase { item: map.watermap[i] as f32 },
+                Aquifer { item: if map.groundwater.is_some() { map.groundwater_at(i) } else { map.cloudmap[i] } as f32 },
+                RiverBase { item: map.rivermap[i] as f32 },
+                VegetBase { item: map.vegetmap[i] as f32 },
+                HeatBase { item: map.tempmap[i] as f32 },
+                Building { item: vec![0.; buildings] },
+                Land { item: Vec::new() },
+                Index { item: i },
+                Biome { item: map.biomes[i] },
+                garrison::Coverage { item: 0. },
+            )
+        })
+    ).to_vec();
+
+    for (i, &pixel) in pixels.iter().enumerate() {
+        if defines.memory.profile == memory::Profile::Full {
+            world.add_component(pixel, Neighb::new(&map.neighbs[i])).unwrap();
+        }
+        world.add_component(pixel, Fertility { item: map.soilmap[i] as f32 }).unwrap();
+
+        if let Some(water) = map.waters.get(&i) {
+            match water {
+                map::Water::Sea => world.add_tag(pixel, Sea).unwrap(),
+                map::Water::Lake | map::Water::SaltLake => world.add_tag(pixel, Lake).unwrap(),
+            };
+        }
+        if map.frozen(i) {
+            world.add_tag(pixel, Ice).unwrap();
+        }
+        if map.settlements[i] {
+            world.add_tag(pixel, Settlement).unwrap();
+            world.add_component(pixel, Pop { item: map.vegetmap[i] as f32 * defines.settlement_density }).unwrap();
+            world.add_component(pixel, guild::Stock { item: vec![0.; goods] }).unwrap();
+            world.add_component(pixel, garrison::Garrison { item: 0. }).unwrap();
+            names::rename(world, names, pixel, defines.culture, names::Kind::Settlement, &mut rng);
+            personality::assign(world, defines, pixel, defines.culture, &mut rng);
+            guild::found_guilds(world, defines, pixel);
+        }
+    }
+
+    pixels
+}
+
+// One entity per province, owning its pixels the way a settlement owns its
+// administered area.
+fn spawn_provinces(world: &mut World, grid: &PixelGrid, map: &map::ProvBuilder) -> Vec<Entity> {
+    let mut owns = vec![Vec::new(); map.province_adjacency.len()];
+
+    for (i, &province) in map.provincemap.iter().enumerate() {
+        if province > 0 {
+            owns[province as usize].push(grid.get(i));
+        }
+    }
+
+    world.insert((Province,), owns.into_iter().skip(1).map(|item| (Owns { item },))).to_vec()
+}
+
+// Settlements are named as they are spawned; provinces, seas and the larger
+// rivers are named here, from the same culture.
+fn name_features(world: &mut World, names: &mut names::Names, grid: &PixelGrid, map: &map::ProvBuilder, defines: &Defines, provinces: &Vec<Entity>) {
+    let mut rng = StdRng::seed_from_u64(map.seed.wrapping_add(1));
+    let culture = defines.culture;
+
+    for &province in provinces.iter() {
+        names::rename(world, names, province, culture, names::Kind::Province, &mut rng);
+    }
+    for sea in map.sea_bodies(defines.naming.sea).into_iter() {
+        let pixels: Vec<Entity> = sea.into_iter().map(|i| grid.get(i)).collect();
+
+        names::name_all(world, names, &pixels, culture, names::Kind::Sea, &mut rng);
+    }
+    for river in map.river_systems(defines.naming.river).into_iter() {
+        let pixels: Vec<Entity> = river.into_iter().map(|i| grid.get(i)).collect();
+
+        names::name_all(world, names, &pixels, culture, names::Kind::River, &mut rng);
+    }
+}
 
 fn handle_event(world: &mut World, resources: &mut Resources, events: &Receiver<LoopEvent>) {
+    let mut count = 0;
+    let mut removed = Vec::new();
+
     for event in events.try_iter() {
+        count += 1;
+
         match event {
             LoopEvent::RemoveEntity(entity) => {
-                world.delete(entity);
+                removed.push(entity);
             },
             LoopEvent::ChangeComponent(entity, wrapper, func) => {
                 func(world, &entity, wrapper.item);
@@ -118,6 +463,20 @@ fn handle_event(world: &mut World, resources: &mut Resources, events: &Receiver<
             }
         }
     }
+
+    // Deletes wait until the drain is done, so a change sent for an entity in
+    // the same tick it is removed still finds it, whatever order they came in.
+    for entity in removed {
+        world.delete(entity);
+    }
+
+    if let Some(mut stats) = resources.get_mut::<memory::MemoryStats>() {
+        stats.events = count;
+    }
+}
+
+fn run_sys(_: &mut World, resources: &mut Resources, _: &Receiver<LoopEvent>) {
+    resources.get::<Arc<AtomicBool>>().unwrap().store(true, Ordering::Relaxed);
 }
 
 struct AppLoop {
@@ -205,23 +564,28 @@ impl SysLoop {
     }
 }
 
-struct Core {
-    universe: Universe,
+// One simulated world with its own app and sys loops, resources and defines.
+struct Instance {
     app: Arc<AppLoop>,
     sys: Arc<SysLoop>,
-    pools: Vec<ThreadPool>,
     barrier: Arc<Barrier>,
     defines: Defines,
 }
 
-impl Core {
-    fn new() -> Self {
-        let universe = Universe::new();
+// Hosts any number of independent worlds. Their loops share two pools: the
+// first has a thread per app loop, and the second a thread per sys loop plus
+// the rest of the machine for the work they run in parallel.
+struct Core {
+    universe: Universe,
+    pools: Vec<ThreadPool>,
+    worlds: Vec<Instance>,
+}
+
+impl Instance {
+    fn new(universe: &Universe, defines: Defines) -> Self {
         let barrier = Arc::new(Barrier::new(3));
         let mtx = Arc::new(Mutex::new(false));
         let run = Arc::new(AtomicBool::new(false));
-        let pools = vec![ThreadPoolBuilder::new().num_threads(1).build().unwrap(), ThreadPoolBuilder::new().num_threads(num_cpus::get() - 1).build().unwrap()];
-        let defines: Defines = from_reader(File::open("defines.ron").unwrap()).unwrap();
 
         let (producer_app, consumer_app) = channel::<LoopEvent>();
         let (producer_sys, consumer_sys) = channel::<LoopEvent>();
@@ -237,6 +601,115 @@ impl Core {
         resources_app.insert(defines.clone());
         resources_sys.insert(defines.clone());
 
+        resources_sys.insert(climate::Climate::new(defines.temp_drift, defines.sea_drift, defines.sea_step));
+        resources_sys.insert(census::Census::new(defines.census_period));
+        resources_sys.insert(timeline::Timeline::new(defines.timeline_period, defines.memory.capacity(defines.timeline_capacity)));
+        resources_sys.insert(registry::Registry::new());
+        resources_sys.insert(memory::MemoryStats::new(defines.memory_period, &defines.memory));
+        resources_sys.insert(path::FlowFields::new(defines.building_i[&Symbol::new("Road")]));
+        resources_sys.insert(isochrone::Isochrones::new(&defines.isochrone, defines.building_i[&Symbol::new("Road")]));
+        resources_sys.insert(tags::TagEvents::new());
+        resources_sys.insert(path::PathRequests::new(defines.building_i[&Symbol::new("Road")]));
+        resources_sys.insert(dirty::DirtyMap::new(defines.display.clone(), defines.size));
+        resources_sys.insert(admin::Admin::new());
+        resources_sys.insert(names::Names::load("names.ron").unwrap());
+        resources_sys.insert(minimap::Minimap::new(defines.size, defines.minimap_scale, defines.minimap_period, defines.memory.capacity(defines.minimap_capacity)));
+        resources_sys.insert(zoom::Pyramids::new(defines.size));
+        resources_sys.insert(command::Lockstep::new(defines.lockstep_delay));
+        resources_sys.insert(frontier::Frontier::new(defines.frontier.period));
+        resources_sys.insert(decay::Decay::new(defines.decay.year));
+        resources_sys.insert(crops::Crops::new(defines.crops.year));
+        resources_sys.insert(ancestry::Lineages::new(defines.ancestry.year));
+        resources_sys.insert(granary::Granaries::new(defines.granary.year));
+        resources_sys.insert(guild::Extraction::new(defines.extraction.year));
+        resources_sys.insert(ruins::Chronicle::new());
+        resources_sys.insert(storm::Storms::new());
+        resources_sys.insert(great::Greats::new(defines.greats.year));
+        resources_sys.insert(blight::Blights::new(defines.blight.year));
+        resources_sys.insert(trigger::Triggers::new(&defines.events));
+        resources_sys.insert(market::Market::new(defines.market.period));
+        resources_sys.insert(trade::Dispatch::new(defines.caravans.period));
+        resources_sys.insert(
+            governor::Governor::new(&defines.governor, defines.server_addr.is_some())
+                .add("brew_storms", storm::brew_storms, 1)
+                .add("abandon_settlements", ruins::abandon_settlements, 3)
+                .add("emigrate", frontier::emigrate, 2)
+                .add("mix_ancestry", ancestry::mix_ancestry, 3)
+                .add("raise_greats", great::raise_greats, 3)
+                .add("decay_buildings", decay::decay_buildings, 3)
+                .add("adopt_crops", crops::adopt_crops, 3)
+                .add("spread_blight", blight::spread_blight, 2)
+                .add("feed_settlements", granary::feed_settlements, 2)
+                .add("extract_goods", guild::extract_goods, 2)
+                .add("assign_ownership", admin::assign_ownership, governor::ESSENTIAL)
+                .add("update_markets", market::update_markets, 2)
+                .add("dispatch_caravans", trade::dispatch_caravans, 2)
+                .add("cover_garrisons", garrison::cover_garrisons, 1)
+        );
+
+        let mut exports = export::ExportManager::new(&defines.export_root, defines.seed);
+
+        if let Some(recording) = defines.recording.as_ref() {
+            resources_sys.insert(recorder::Recorder::new(recording, defines.size, &mut exports));
+        }
+        if let Some(graph) = defines.graph.as_ref() {
+            resources_sys.insert(graph::GraphExport::new(graph, &mut exports));
+        }
+        if let Some(report) = defines.report.as_ref() {
+            resources_sys.insert(report::WorldReport::new(report, &mut exports));
+        }
+
+        if let Some(autosave) = defines.autosave.as_ref() {
+            resources_sys.insert(save::Autosave::new(autosave, &mut exports));
+        }
+
+        resources_sys.insert(
+            validate::Validator::new(&defines.validate, &mut exports)
+                .register("pop_non_negative", census::pop_non_negative)
+                .register("owners_alive", admin::owners_alive)
+                .register("goods_non_negative", guild::goods_non_negative)
+        );
+        resources_sys.insert(exports);
+        resources_sys.insert(writer::Writer::new());
+
+        let mut schedule_sys = Schedule::builder()
+            .add_thread_local_fn(random::advance_rng)
+            .add_thread_local_fn(command::apply_commands)
+            .add_thread_local_fn(climate::turn_seasons)
+            .add_system(irrigation::irrigation())
+            .add_system(climate::climate_drift())
+            .add_system(caravan::move_caravans())
+            .add_system(guild::produce())
+            .add_system(census::take_census())
+            .add_system(timeline::record_timeline())
+            .add_system(memory::measure_memory())
+            .add_system(minimap::update_minimap())
+            .add_system(zoom::update_pyramids())
+            .add_system(garrison::raise_garrisons())
+            .add_system(modifier::expire_modifiers())
+            .add_thread_local_fn(caravan::raid_caravans)
+            .add_thread_local_fn(caravan::deliver_cargo)
+            .add_thread_local_fn(trade::depart_caravans)
+            .add_thread_local_fn(governor::govern)
+            .add_thread_local_fn(trigger::fire_events)
+            .add_thread_local_fn(path::solve_paths)
+            .add_thread_local_fn(dirty::track_dirty)
+            .add_thread_local_fn(recorder::record_frames)
+            .add_thread_local_fn(graph::export_graph_at)
+            .add_thread_local_fn(report::export_report_at)
+            .add_thread_local_fn(save::autosave)
+            .add_thread_local_fn(validate::validate);
+
+        if let Some(addr) = defines.server_addr.as_ref() {
+            resources_sys.insert(server::StateServer::bind(addr, defines.server_period).unwrap());
+            schedule_sys = schedule_sys.add_system(server::publish_state());
+
+            if let Some(replica) = defines.replica.as_ref() {
+                resources_sys.insert(replica::Replica::new(replica));
+                schedule_sys = schedule_sys.add_thread_local_fn(replica::replicate);
+            }
+        }
+
         let app = AppLoop {
             world: universe.create_world(),
             resources: resources_app,
@@ -244,18 +717,18 @@ impl Core {
             events: Wrapper { item: consumer_app },
             mtx: mtx.clone(),
             barrier: barrier.clone(),
-            on_schedule_start: Vec::new(),
+            on_schedule_start: vec![handle_event],
             on_schedule_end: Vec::new(),
         };
         let sys = SysLoop {
             world: universe.create_world(),
             resources: resources_sys,
-            schedule: Wrapper { item: Schedule::builder().build() },
+            schedule: Wrapper { item: schedule_sys.build() },
             events: Wrapper { item: consumer_sys },
             mtx: mtx.clone(),
             barrier: barrier.clone(),
-            on_schedule_start: Vec::new(),
-            on_schedule_end: Vec::new(),
+            on_schedule_start: vec![handle_event],
+            on_schedule_end: vec![scratch::reset_scratch],
             on_schedule_wait: Vec::new(),
             update: |_, _| {},
             run: run.clone(),
@@ -264,86 +737,230 @@ impl Core {
         let app = Arc::new(app);
         let sys = Arc::new(sys);
 
-        Core {
-            universe,
+        Instance {
             app,
             sys,
-            pools,
             barrier,
             defines,
         }
     }
 
     fn load_pixels(&mut self) {
-        let mut map = map::ProvBuilder::new(self.defines.size, 0.1, 0.6, 2., 0., 1., 0.1, 0.9, -20., -10.);
-
-        map.gen_heightmap();
-        map.gen_insolation();
-        map.gen_waters();
-        map.gen_cloud();
-        map.gen_temp();
-        map.gen_rivermap();
-        map.gen_watermap();
-        map.gen_vegetmap();
-        map.gen_settlements();
-
-        map.export(&map.heightmap, "heightmap.png");
-        map.export_minmax(&map.insolation, "insolation.png", 0., 1.);
-        map.export_waters("waters.png");
-        map.export_minmax(&map.cloudmap, "cloudmap.png", 0., 1.);
-        map.export_minmax(&map.tempmap, "tempmap.png", 0., 1.);
-        map.export_minmax(&map.rivermap, "rivermap.png", 0., 1.);
-        map.export_minmax(&map.watermap, "watermap.png", 0., 1.);
-        map.export_minmax(&map.vegetmap, "vegetmap.png", 0., 1.);
-        map.export_settlements("settlements.png");
-
-        let world = unsafe { &mut Arc::get_mut_unchecked(&mut self.sys).world };
-
-        let pixels = world.insert(
-            (Pixel,),
-            (0..map.size * map.size).map(|i| {
-                (
-                    Height { item: map.heightmap[i] as f32 },
-                    Heat { item: map.tempmap[i] as f32 },
-                    River { item: map.rivermap[i] as f32 },
-                    Rain { item: map.cloudmap[i] as f32 },
-                    Veget { item: map.vegetmap[i] as f32 },
-                    Water { item: map.watermap[i] as f32 },
-                    RiverBase { item: map.rivermap[i] as f32 },
-                    VegetBase { item: map.vegetmap[i] as f32 },
-                    Building { item: Vec::new() },
-                    Land { item: Vec::new() },
-                )
-            })
-        ).to_vec();
+        let config = map::WorldGenConfig::load(&self.defines.worldgen).unwrap();
+        let mut map = map::ProvBuilder::with_config(&config).unwrap();
+
+        map.seasons = self.defines.seasons.clone();
+        map.currents = self.defines.currents.clone();
+        map.glaciers = self.defines.glaciers.clone();
+        map.orography = self.defines.orography.clone();
+        map.basins = self.defines.basins.clone();
+        map.rivers = self.defines.rivers.clone();
+        map.provinces = self.defines.provinces.clone();
+        map.groundwater = self.defines.groundwater.clone();
+        map.set_noise(&self.defines.noise);
+
+        match (&self.defines.synthetic, &self.defines.dem) {
+            (Some(synthetic), _) => map.gen_synthetic(synthetic),
+            (None, Some(dem)) => {
+                map.import_dem(&Dem::read(&dem.path).unwrap(), dem.sea_level);
+                map.gen_from_heightmap();
+            }
+            (None, None) => {
+                map.terrain = self.defines.terrain.clone();
+                map.thermal = self.defines.thermal.clone();
+                map.generate_valid(self.defines.seed, self.defines.seed_tries).unwrap();
+            }
+        }
+
+        map.gen_navigation(self.defines.navigation.flow as f64, self.defines.navigation.slope as f64);
+
+        let sys = unsafe { Arc::get_mut_unchecked(&mut self.sys) };
+        let world = &mut sys.world;
+        let road = self.defines.building_i[&Symbol::new("Road")];
+
+        {
+            let mut exports = sys.resources.get_mut::<export::ExportManager>().unwrap();
 
-        for (i, &pixel) in pixels.iter().enumerate() {
-            let neighb = map.neighbs[i].iter().map(|&(i, _)| pixels[i]).collect();
+            let styles = &self.defines.styles;
 
-            world.add_component(pixel, Neighb { item: neighb }).unwrap();
+            exports.set_seed(map.seed);
+            export_layer(&map, &mut exports, styles, "heightmap", &map.heightmap, None);
+            export_layer(&map, &mut exports, styles, "insolation", &map.insolation, Some((0., 1.)));
+            map.export_waters(exports.path("waters", "waters.png"));
+            map.export_snow(exports.path("snow", "snow.png"));
+            export_layer(&map, &mut exports, styles, "cloudmap", &map.cloudmap, Some((0., 1.)));
+            export_layer(&map, &mut exports, styles, "tempmap", &map.tempmap, Some((0., 1.)));
 
-            if let Some(water) = map.waters.get(&i) {
-                match water {
-                    map::Water::Sea => world.add_tag(pixel, Sea).unwrap(),
-                    map::Water::Lake => world.add_tag(pixel, Lake).unwrap(),
-                };
+            if let Some(currents) = map.currents.as_ref() {
+                export_layer(&map, &mut exports, styles, "currentmap", &map.currentmap, Some((-currents.strength, currents.strength)));
             }
-            if map.settlements[i] {
-                world.add_tag(pixel, Settlement).unwrap();
+
+            export_layer(&map, &mut exports, styles, "rivermap", &map.rivermap, Some((0., 1.)));
+
+            if let Some(rivers) = map.rivers.as_ref() {
+                map.export_rivers(rivers.scale, exports.path("rivers", "rivers.png"));
+            }
+
+            export_layer(&map, &mut exports, styles, "watermap", &map.watermap, Some((0., 1.)));
+
+            if map.groundwater.is_some() {
+                export_layer(&map, &mut exports, styles, "groundwatermap", &map.groundwatermap, Some((0., 1.)));
+            }
+
+            export_layer(&map, &mut exports, styles, "vegetmap", &map.vegetmap, Some((0., 1.)));
+            export_layer(&map, &mut exports, styles, "soilmap", &map.soilmap, Some((0., 1.)));
+            map.export_biomes(exports.path("biomes", "biomes.png"));
+            map.export_settlements(exports.path("settlements", "settlements.png"));
+
+            if map.provinces.is_some() {
+                map.export_provinces(exports.path("provinces", "provinces.png"));
+            }
+        }
+
+        let pixels = spawn_pixels(world, &mut sys.resources.get_mut::<names::Names>().unwrap(), &map, &self.defines);
+
+        let grid = PixelGrid { item: pixels, size: map.size, wrap: map.wrap };
+
+        let provinces = spawn_provinces(world, &grid, &map);
+
+        name_features(world, &mut sys.resources.get_mut::<names::Names>().unwrap(), &grid, &map, &self.defines, &provinces);
+        path::mark_navigable(world, &grid, &map.navigable);
+
+        if let Some(calendar) = climate::Calendar::new(&map) {
+            sys.resources.insert(calendar);
+        }
+
+        {
+            let mut exports = sys.resources.get_mut::<export::ExportManager>().unwrap();
+
+            tiled::export_tmx(world, map.size, road, exports.dir("tiled", "tiled")).unwrap();
+            metadata::export_metadata(world, &grid, road, exports.path("metadata", "metadata.json")).unwrap();
+        }
+
+        sys.resources.insert(random::WorldRng::new(map.seed));
+        sys.resources.insert(grid);
+
+        // The realms as first claimed, before the simulation moves them.
+        admin::assign_ownership(world, &mut sys.resources);
+
+        let owners: Vec<Option<usize>> = sys.resources
+            .get::<admin::Admin>()
+            .unwrap()
+            .owner
+            .iter()
+            .map(|owner| owner.and_then(|owner| world.get_component::<Index>(owner)).map(|owner| owner.item))
+            .collect();
+        let mut exports = sys.resources.get_mut::<export::ExportManager>().unwrap();
+
+        map.export_political(&owners, self.defines.borders.as_ref(), exports.path("political", "political.png"));
+    }
+
+}
+
+impl Core {
+    // defines.ron with the mods from each of `dirs` applied in order. The
+    // grid's size, seed and wrap come from the worldgen config alone.
+    fn defines(dirs: &[&str]) -> Defines {
+        let mut defines: Defines = from_reader(File::open("defines.ron").unwrap()).unwrap();
+
+        for dir in dirs.iter() {
+            let applied = mods::load_mods(&mut defines, dir).unwrap();
+
+            defines.mods.extend(applied);
+        }
+
+        let config = map::WorldGenConfig::load(&defines.worldgen).unwrap();
+
+        config.validate().unwrap();
+        defines.size = config.size;
+        defines.seed = config.seed;
+        defines.wrap = config.wrap;
+
+        defines
+    }
+
+    fn new(defines: Vec<Defines>) -> Self {
+        let universe = Universe::new();
+        let count = defines.len().max(1);
+        let pools = vec![
+            ThreadPoolBuilder::new().num_threads(count).build().unwrap(),
+            ThreadPoolBuilder::new().num_threads((num_cpus::get() - 1).max(count + 1)).build().unwrap(),
+        ];
+        let worlds = defines.into_iter().map(|defines| Instance::new(&universe, defines)).collect();
+
+        Core {
+            universe,
+            pools,
+            worlds,
+        }
+    }
+
+    fn load_pixels(&mut self) {
+        for world in self.worlds.iter_mut() {
+            world.load_pixels();
+        }
+    }
+
+    // Ticks every world as fast as its sys loop goes, for as long as the
+    // process runs.
+    fn serve(&mut self) {
+        for world in self.worlds.iter_mut() {
+            if let Some(app) = Arc::get_mut(&mut world.app) {
+                app.on_schedule_start.push(run_sys);
             }
         }
+
+        self.start();
     }
 
     fn start(&mut self) {
-        AppLoop::start(self.app.clone(), &self.pools[0]);
-        SysLoop::start(self.sys.clone(), self.app.clone(), &self.pools[1]);
-
-        self.barrier.wait();
+        for world in self.worlds.iter() {
+            AppLoop::start(world.app.clone(), &self.pools[0]);
+            SysLoop::start(world.sys.clone(), world.app.clone(), &self.pools[1]);
+        }
+        for world in self.worlds.iter() {
+            world.barrier.wait();
+        }
     }
 }
 
+// `worlds <dir>...` runs one world per mod directory side by side, each on
+// top of the usual mods and exporting under its own root; only the first
+// serves state, as they would all want the same address. `sweep <spec>`
+// runs its own worlds and never starts the main one. `serve` keeps the main
+// world ticking after it is generated, for clients of the state server.
 fn main() {
-    let mut core = Core::new();
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() > 2 && args[1] == "sweep" {
+        sweep::sweep(&args[2], &Core::defines(&["mods"])).unwrap();
+
+        return;
+    }
+
+    let defines = match args.get(1).map(|arg| arg.as_str()) {
+        Some("worlds") => args[2..]
+            .iter()
+            .enumerate()
+            .map(|(i, dir)| {
+                let mut defines = Core::defines(&["mods", dir]);
+
+                defines.export_root = format!("{}/world_{}", defines.export_root, i);
+
+                if i > 0 {
+                    defines.server_addr = None;
+                }
+
+                defines
+            })
+            .collect(),
+        _ => vec![Core::defines(&["mods"])],
+    };
+    let mut core = Core::new(defines);
 
     core.load_pixels();
+
+    if args.get(1).map(|arg| arg.as_str()) == Some("serve") {
+        core.serve();
+    }
 }
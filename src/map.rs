@@ -1,19 +1,79 @@
 use noise::Perlin;
+use noise::OpenSimplex;
 use noise::NoiseFn;
+use noise::Seedable;
 use image::RgbImage;
 use image::Rgb;
 use num::clamp;
 
+use serde::Serialize;
+use serde::Deserialize;
+
 use pathfinding::directed::dijkstra::dijkstra;
+use pathfinding::directed::astar::astar;
+
+use ron::de::from_reader;
 
 use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 use std::mem::swap;
+use std::borrow::Cow;
+use std::mem::take;
+use std::f64::consts::PI;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::fs::File;
+use std::path::Path;
 use std::path::PathBuf;
 
+use crate::colormap::Colormap;
+use crate::colormap::Style;
+use crate::colormap::decorate;
+use crate::npy::write_npy;
+use crate::npy::write_layers;
+use crate::dem::Dem;
+use crate::fixed::Real;
+use crate::fixed::real;
+use crate::fixed::to_f32;
+
+// WrapX joins the east and west edges, as on a cylinder; Torus also joins
+// the north and south ones.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum WrapMode {
+    None,
+    WrapX,
+    Torus,
+}
+
+impl Default for WrapMode {
+    fn default() -> Self {
+        WrapMode::None
+    }
+}
+
+// What each octave is built from. Ridged folds Perlin noise into sharp
+// crests, each octave weighted by the one before so ridgelines branch
+// rather than wash out; Billow folds it the other way into rounded hills,
+// and Warped displaces the point sampled by a coarser noise first.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum NoiseKind {
+    Perlin,
+    OpenSimplex,
+    Ridged,
+    Billow,
+    Warped,
+}
+
 struct PerlinOctave {
+    kind: NoiseKind,
     noise: Perlin,
+    simplex: OpenSimplex,
+    wrap: WrapMode,
     size: usize,
     octave: usize,
     freq: f64,
@@ -21,12 +81,28 @@ struct PerlinOctave {
     lac: f64,
     min: f64,
     max: f64,
+    threshold: f64,
+    octaves: Vec<(f64, f64)>,
+    peak: f64,
+    max_total: f64,
+}
+
+// Octaves weighted below `threshold` of the first are left out. With `cache`
+// the noise of every pixel is kept until the seed changes, so generating
+// again from the same seed does not evaluate it a second time.
+#[derive(Clone, Deserialize)]
+pub struct NoiseDefines {
+    pub threshold: f64,
+    pub cache: bool,
 }
 
 impl PerlinOctave {
     fn new(size: usize, freq: f64, pers: f64, lac: f64, min: f64, max: f64) -> Self {
-        PerlinOctave {
+        let mut noise = PerlinOctave {
+            kind: NoiseKind::Perlin,
             noise: Perlin::new(),
+            simplex: OpenSimplex::new(),
+            wrap: WrapMode::None,
             size,
             octave: (size as f64).log2() as usize,
             freq,
@@ -34,26 +110,102 @@ impl PerlinOctave {
             lac,
             min,
             max,
-        }
+            threshold: 0.,
+            octaves: Vec::new(),
+            peak: 0.,
+            max_total: 0.,
+        };
+
+        noise.tabulate();
+        noise
     }
 
-    fn get(&self, x: f64, y: f64) -> f64 {
-        let max = (2f64).sqrt() / 2.;
+    // The scale and weight of every octave kept, and the largest total they
+    // can reach, which only change with the settings.
+    fn tabulate(&mut self) {
+        let dims: f64 = match self.wrap {
+            WrapMode::None => 2.,
+            WrapMode::WrapX => 3.,
+            WrapMode::Torus => 4.,
+        };
+        let max = dims.sqrt() / 2.;
 
-        let mut out = 0.;
-        let mut max_total = 0.;
         let mut amp = 1.;
+        let mut scale = self.freq / (self.size as f64).sqrt();
 
-        let mut x = x * self.freq / (self.size as f64).sqrt();
-        let mut y = y * self.freq / (self.size as f64).sqrt();
+        self.octaves.clear();
+        self.peak = max;
+        self.max_total = 0.;
 
         for _ in 0..self.octave {
-            out += self.noise.get([x, y]) * amp;
-            max_total += max * amp;
+            if amp < self.threshold {
+                break;
+            }
+
+            self.octaves.push((scale, amp));
+            self.max_total += max * amp;
 
             amp *= self.pers;
-            x *= self.lac;
-            y *= self.lac;
+            scale *= self.lac;
+        }
+    }
+
+    fn base<P>(&self, point: P) -> f64
+    where
+        Perlin: NoiseFn<P>,
+        OpenSimplex: NoiseFn<P>,
+    {
+        match self.kind {
+            NoiseKind::OpenSimplex => self.simplex.get(point),
+            _ => self.noise.get(point),
+        }
+    }
+
+    // A wrapping edge is sampled around a circle of the same circumference,
+    // one more noise dimension per wrapping axis, so no seam shows.
+    fn sample(&self, x: f64, y: f64, scale: f64) -> f64 {
+        let r = self.size as f64 / (2. * PI) * scale;
+        let u = 2. * PI * x / self.size as f64;
+        let v = 2. * PI * y / self.size as f64;
+
+        match self.wrap {
+            WrapMode::None => self.base([x * scale, y * scale]),
+            WrapMode::WrapX => self.base([r * u.cos(), r * u.sin(), y * scale]),
+            WrapMode::Torus => self.base([r * u.cos(), r * u.sin(), r * v.cos(), r * v.sin()]),
+        }
+    }
+
+    fn get(&self, x: f64, y: f64) -> f64 {
+        let max_total = self.max_total;
+        let max = self.peak;
+        let (mut x, mut y) = (x, y);
+
+        // The displacement is read a third of the map away, where it is
+        // unrelated to the height itself, at the coarsest octave.
+        if let (NoiseKind::Warped, Some(&(scale, _))) = (self.kind, self.octaves.first()) {
+            let warp = self.size as f64 / 16.;
+            let (dx, dy) = (self.sample(x + self.size as f64 / 3., y, scale), self.sample(x, y + self.size as f64 / 3., scale));
+
+            x += dx / max * warp;
+            y += dy / max * warp;
+        }
+
+        let mut out = 0.;
+        let mut weight = 1.;
+
+        for &(scale, amp) in self.octaves.iter() {
+            let n = self.sample(x, y, scale);
+
+            out += match self.kind {
+                NoiseKind::Ridged => {
+                    let ridge = (1. - (n / max).abs()).max(0.).powi(2) * weight;
+
+                    weight = clamp(ridge * 2., 0., 1.);
+                    (2. * ridge - 1.) * max * amp
+                },
+                NoiseKind::Billow => (2. * (n / max).abs() - 1.) * max * amp,
+                _ => n * amp,
+            };
         }
 
         out += max_total;
@@ -66,9 +218,96 @@ impl PerlinOctave {
     }
 }
 
+pub const ALPINE: f64 = 0.6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Ocean,
+    Lake,
+    Ice,
+    Tundra,
+    Taiga,
+    Steppe,
+    Grassland,
+    Desert,
+    Savanna,
+    TemperateForest,
+    TemperateRainforest,
+    TropicalForest,
+    Rainforest,
+    Alpine,
+}
+
+impl Biome {
+    // Whittaker-style: temperature picks the band, moisture the biome in it,
+    // and high ground is alpine whatever the climate.
+    pub fn classify(temp: f64, moisture: f64, height: f64) -> Self {
+        match () {
+            _ if height > ALPINE => if temp < 0.15 { Biome::Ice } else { Biome::Alpine },
+            _ if temp < 0.15 => Biome::Ice,
+            _ if temp < 0.3 => Biome::Tundra,
+            _ if temp < 0.45 => if moisture < 0.25 { Biome::Steppe } else { Biome::Taiga },
+            _ if temp < 0.7 => match () {
+                _ if moisture < 0.15 => Biome::Desert,
+                _ if moisture < 0.35 => Biome::Grassland,
+                _ if moisture < 0.7 => Biome::TemperateForest,
+                _ => Biome::TemperateRainforest,
+            },
+            _ => match () {
+                _ if moisture < 0.2 => Biome::Desert,
+                _ if moisture < 0.45 => Biome::Savanna,
+                _ if moisture < 0.7 => Biome::TropicalForest,
+                _ => Biome::Rainforest,
+            },
+        }
+    }
+
+    pub fn color(&self) -> Rgb<u8> {
+        match self {
+            Biome::Ocean => Rgb([30, 60, 140]),
+            Biome::Lake => Rgb([60, 110, 180]),
+            Biome::Ice => Rgb([240, 240, 245]),
+            Biome::Tundra => Rgb([150, 160, 140]),
+            Biome::Taiga => Rgb([60, 100, 80]),
+            Biome::Steppe => Rgb([190, 185, 110]),
+            Biome::Grassland => Rgb([120, 175, 80]),
+            Biome::Desert => Rgb([225, 200, 140]),
+            Biome::Savanna => Rgb([190, 170, 80]),
+            Biome::TemperateForest => Rgb([45, 115, 50]),
+            Biome::TemperateRainforest => Rgb([30, 95, 70]),
+            Biome::TropicalForest => Rgb([60, 130, 40]),
+            Biome::Rainforest => Rgb([20, 80, 35]),
+            Biome::Alpine => Rgb([120, 105, 95]),
+        }
+    }
+}
+
+// Settlements are placed at most one per row band, bands being at least this
+// many rows apart.
+pub const SETTLEMENT_SPACING: usize = 5;
+
+// What `drainage` holds for a pixel whose water goes nowhere, being sea, lake
+// or off the rivers; pixel 0 is a real outlet like any other.
+pub const NO_OUTLET: usize = usize::MAX;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    RiverSink(usize),
+    Negative(&'static str, usize),
+    WaterHeight(usize),
+    SettlementWater(usize),
+    SettlementSpacing(usize, usize),
+}
+
 pub enum Water {
     Sea,
-    Lake
+    Lake,
+    SaltLake,
+}
+
+// Perlin only takes 32 bits of seed, so both halves are folded into them.
+fn perlin_seed(seed: u64) -> u32 {
+    (seed ^ (seed >> 32)) as u32
 }
 
 pub fn is_neighbor(i: usize, ii: usize, size: usize) -> bool {
@@ -90,6 +329,157 @@ pub fn is_neighbor(i: usize, ii: usize, size: usize) -> bool {
     return x_diff <= 1 && y_diff <= 1 && y_ii < size;
 }
 
+// Steps to the eight neighbours, in the order `neighbs` lists them.
+pub const OFFSETS: [(isize, isize); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+// The pixel `dx` columns and `dy` rows from `i`, if it is on the map.
+pub fn neighbor_at(i: usize, dx: isize, dy: isize, size: usize, wrap: WrapMode) -> Option<usize> {
+    let n = size as isize;
+    let mut x = (i % size) as isize + dx;
+    let mut y = (i / size) as isize + dy;
+
+    if wrap != WrapMode::None {
+        x = x.rem_euclid(n);
+    }
+    if wrap == WrapMode::Torus {
+        y = y.rem_euclid(n);
+    }
+
+    match x >= 0 && x < n && y >= 0 && y < n {
+        true => Some((x + y * n) as usize),
+        false => None,
+    }
+}
+
+// The step from `i` to `ii`, taking the short way round whichever edges wrap.
+pub fn offset_between(i: usize, ii: usize, size: usize, wrap: WrapMode) -> (f64, f64) {
+    let n = size as isize;
+    let wrapped = |d: isize| if d > n / 2 { d - n } else if d < -n / 2 { d + n } else { d };
+    let dx = (ii % size) as isize - (i % size) as isize;
+    let dy = (ii / size) as isize - (i / size) as isize;
+    let dx = if wrap != WrapMode::None { wrapped(dx) } else { dx };
+    let dy = if wrap == WrapMode::Torus { wrapped(dy) } else { dy };
+
+    (dx as f64, dy as f64)
+}
+
+// Vegetation from a pixel's heat and its wettest source of water, shared by
+// worldgen and the climate drift so both agree on the same pixel.
+pub fn calc_veget(heat: Real, water: Real) -> Real {
+    let water = clamp(real(1.5) * water - heat / real(2.), real(0.), real(1.));
+    let offset = heat - real(0.75);
+
+    (water * (real(1.) - offset * offset)).max(real(0.)).sqrt()
+}
+
+pub fn owner_color(owner: usize) -> Rgb<u8> {
+    let hash = (owner as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+
+    Rgb([
+        64 + (hash >> 56) as u8 % 192,
+        64 + (hash >> 48) as u8 % 192,
+        64 + (hash >> 40) as u8 % 192,
+    ])
+}
+
+// Display only: pixels within `band` of a border between two owners are
+// handed back out by a cheapest-path fill from the rest of each province.
+// Entering a river, climbing or descending, or stepping along the coast
+// costs extra, so the drawn line settles on the nearest such feature
+// instead of cutting across it. Ownership itself is never changed.
+#[derive(Clone, Deserialize)]
+pub struct BorderStyle {
+    pub band: usize,
+    pub river: f64,
+    pub slope: f64,
+    pub coast: f64,
+}
+
+fn around(i: usize, size: usize, wrap: WrapMode) -> impl Iterator<Item = usize> {
+    OFFSETS.iter().filter_map(move |&(dx, dy)| neighbor_at(i, dx, dy, size, wrap))
+}
+
+pub fn snap_borders(size: usize, wrap: WrapMode, heightmap: &Vec<f64>, rivermap: &Vec<f64>, water: &Vec<bool>, owners: &Vec<Option<usize>>, style: &BorderStyle) -> Vec<Option<usize>> {
+    let mut dist = vec![usize::MAX; size * size];
+    let mut queue = VecDeque::new();
+
+    for i in 0..size * size {
+        if owners[i].is_some() && around(i, size, wrap).any(|ii| owners[ii].is_some() && owners[ii] != owners[i]) {
+            dist[i] = 0;
+            queue.push_back(i);
+        }
+    }
+
+    while let Some(i) = queue.pop_front() {
+        for ii in around(i, size, wrap) {
+            if dist[ii] == usize::MAX && dist[i] + 1 < style.band && owners[ii].is_some() {
+                dist[ii] = dist[i] + 1;
+                queue.push_back(ii);
+            }
+        }
+    }
+
+    let mut open = vec![false; size * size];
+    let mut kept = HashMap::new();
+
+    for i in 0..size * size {
+        if let Some(owner) = owners[i] {
+            open[i] = dist[i] < style.band && !water[i];
+
+            if !open[i] {
+                *kept.entry(owner).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // An owner lying wholly inside the band would have nothing to fill from,
+    // so it keeps its pixels exactly as they are.
+    for i in 0..size * size {
+        if let Some(owner) = owners[i] {
+            if !kept.contains_key(&owner) {
+                open[i] = false;
+            }
+        }
+    }
+
+    let mut out = owners.clone();
+    let mut heap = BinaryHeap::new();
+
+    for i in 0..size * size {
+        if let Some(owner) = owners[i] {
+            if !open[i] && around(i, size, wrap).any(|ii| open[ii]) {
+                heap.push(Reverse((0u32, i, owner)));
+            }
+        }
+    }
+
+    let mut cost = vec![u32::MAX; size * size];
+
+    while let Some(Reverse((d, i, owner))) = heap.pop() {
+        if open[i] && d > cost[i] {
+            continue;
+        }
+
+        for ii in around(i, size, wrap) {
+            if !open[ii] {
+                continue;
+            }
+
+            let coast = if around(ii, size, wrap).any(|n| water[n]) { style.coast } else { 0. };
+            let step = 1. + style.river * rivermap[ii] + style.slope * (heightmap[ii] - heightmap[i]).abs() * size as f64 + coast;
+            let next = d + (step * 100.) as u32;
+
+            if next < cost[ii] {
+                cost[ii] = next;
+                out[ii] = Some(owner);
+                heap.push(Reverse((next, ii, owner)));
+            }
+        }
+    }
+
+    out
+}
+
 fn find_lat(lats: &Vec<f64>, targ: f64, size: usize) -> usize {
     let mut prev = 0;
     let mut prev_diff = f64::MAX;
@@ -109,10 +499,92 @@ fn find_lat(lats: &Vec<f64>, targ: f64, size: usize) -> usize {
     return prev;
 } 
 
+// Moisture carried cell by cell along the wind. Over the sea the air takes
+// up `pickup` times the belt's gain and over land `land` times it; it rains
+// out `base` of what it holds, more by `lift` per unit of height climbed and
+// less by `sink` per unit descended, so the lee of a range stays dry.
+#[derive(Clone, Deserialize)]
+pub struct Orography {
+    pub pickup: f64,
+    pub land: f64,
+    pub base: f64,
+    pub lift: f64,
+    pub sink: f64,
+}
+
+// Land is split into `count` provinces grown from settlements, with more
+// seeds put as far as possible from the others while there are too few.
+// Each grows by cheapest path, entering a river costing `river` times its
+// flow more and climbing or descending `slope` times the height difference
+// scaled by the map size, so borders tend to follow rivers and ridges.
+#[derive(Clone, Deserialize)]
+pub struct Provinces {
+    pub count: usize,
+    pub river: f64,
+    pub slope: f64,
+}
+
+// Pixels carrying at least `threshold` of the largest river are channels,
+// `width` pixels wide at the largest. Mouths at least `delta` wide fan out
+// into up to `branches` more outlets, and channels falling less than
+// `gradient` per pixel wander by up to `meander` pixels to either side.
+// `scale` is how many image pixels export_rivers draws per map pixel.
+#[derive(Clone, Deserialize)]
+pub struct Rivers {
+    pub threshold: f64,
+    pub width: f64,
+    pub delta: f64,
+    pub branches: usize,
+    pub gradient: f64,
+    pub meander: f64,
+    pub scale: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mouth {
+    Sea,
+    Lake,
+    SaltLake,
+    Delta,
+}
+
+// One pixel of channel. `down` is the segment it flows into; a segment
+// emptying into water has a `mouth` with the water pixel instead, and a
+// delta outlet shares its pixel with the main mouth.
+#[derive(Clone, Debug)]
+pub struct RiverSegment {
+    pub pixel: usize,
+    pub width: f64,
+    pub down: Option<usize>,
+    pub mouth: Option<(Mouth, usize)>,
+    pub offset: (f64, f64),
+}
+
+// A lake overflows at the lowest pixel of its shore when the rain falling on
+// it and reaching it by river is more than it loses, `evaporation` times the
+// temperature for each of its pixels; one that does not is a salt lake.
+#[derive(Clone, Deserialize)]
+pub struct Basins {
+    pub evaporation: f64,
+}
+
+// Water seeps from lakes, rivers and land rained on more than `rain` into
+// the ground around them over `steps` rounds, each moving `flow` of the
+// difference towards the neighbours' mean. Steep ground lets less through,
+// by `slope` per unit of slope, and each round `loss` of it drains away.
+#[derive(Clone, Deserialize)]
+pub struct Groundwater {
+    pub rain: f64,
+    pub steps: usize,
+    pub flow: f64,
+    pub slope: f64,
+    pub loss: f64,
+}
+
 fn do_wind(x: usize, y: usize, y_to: usize, lat: f64, lat_goal: f64, 
-    flow: (f64, f64), size: usize, 
+    flow: (f64, f64), size: usize, wrap: WrapMode,
     cloudmap: &mut Vec<f64>, latitudes: &Vec<f64>, heightmap: &Vec<f64>, 
-    water_gain: f64, water_mult: f64) 
+    water_gain: f64, water_mult: f64, orography: Option<(&Orography, &HashMap<usize, Water>)>) 
 {
     let mut flow_t = flow;
 
@@ -131,7 +603,10 @@ fn do_wind(x: usize, y: usize, y_to: usize, lat: f64, lat_goal: f64,
             line.push(x_t + y_t * size);
         }
 
-        x_t = (x_t as isize + flow_t.0 as isize) as usize;
+        x_t = match wrap {
+            WrapMode::None => (x_t as isize + flow_t.0 as isize) as usize,
+            _ => (x_t as isize + flow_t.0 as isize).rem_euclid(size as isize) as usize,
+        };
         res += flow_t.1.abs();
 
         while res >= 1. {
@@ -149,6 +624,27 @@ fn do_wind(x: usize, y: usize, y_to: usize, lat: f64, lat_goal: f64,
 
     let mut water = size as f64 * water_mult / 100.;
 
+    if let Some((orography, waters)) = orography {
+        let mut prev: Option<usize> = None;
+
+        for &ii in line.iter() {
+            water += match waters.get(&ii) {
+                Some(Water::Sea) => water_gain * orography.pickup,
+                _ => water_gain * orography.land,
+            };
+
+            let climb = prev.map_or(0., |prev| heightmap[ii].max(0.) - heightmap[prev].max(0.));
+            let rate = clamp(orography.base + orography.lift * climb.max(0.) - orography.sink * (-climb).max(0.), 0., 1.);
+            let cloud = water * rate;
+
+            cloudmap[ii] = cloud;
+            water -= cloud;
+            prev = Some(ii);
+        }
+
+        return;
+    }
+
     for &ii in line.iter() {
         water += water_gain;
 
@@ -160,20 +656,178 @@ fn do_wind(x: usize, y: usize, y_to: usize, lat: f64, lat_goal: f64,
     }
 }
 
+// A controlled world for exercising one system at a time: flat land at
+// `height`, a strip of sea `sea_rows` deep along the top edge, a single river
+// running straight down column `river_x` into it, uniform rain and
+// temperature, and one settlement on the river bank halfway down.
+#[derive(Clone, Deserialize)]
+pub struct Synthetic {
+    pub height: f64,
+    pub sea_rows: usize,
+    pub river: f64,
+    pub river_x: usize,
+    pub rain: f64,
+    pub temp: f64,
+}
+
+// Each pass moves `rate` of the steepest excess out of every cell towards
+// the neighbours it stands above by more than `talus` per unit of distance,
+// shared in proportion to each one's excess.
+#[derive(Clone, Deserialize)]
+pub struct Thermal {
+    pub talus: f64,
+    pub rate: f64,
+    pub passes: usize,
+}
+
+// `land` is the chance a plate is continental; its base height is then
+// `continent` rather than `ocean`, before the water level is taken off.
+#[derive(Clone, Deserialize)]
+pub struct Plates {
+    pub count: usize,
+    pub land: f64,
+    pub continent: f64,
+    pub ocean: f64,
+    pub uplift: f64,
+    pub rift: f64,
+    pub width: usize,
+    pub warp: f64,
+    pub detail: f64,
+}
+
+// Across each row of an ocean at least `width` pixels wide the western side
+// is warmed and the eastern cooled by up to `strength` between 10 and 45
+// degrees, the other way round and half as much from 45 to 70, as the gyres
+// carry water. Coasts take the nearest sea's change, fading over `reach`.
+#[derive(Clone, Deserialize)]
+pub struct Currents {
+    pub strength: f64,
+    pub width: usize,
+    pub reach: usize,
+}
+
+// Land freezes where it is colder than `snowline`, raised by `altitude` times
+// its height and `latitude` times its latitude over 90 degrees, and sea does
+// the same beyond `sea_ice` degrees. A glacier reaches `tongue` pixels down
+// from the edge of the ice and adds `melt` to the river below it.
+#[derive(Clone, Deserialize)]
+pub struct Glaciers {
+    pub snowline: f64,
+    pub altitude: f64,
+    pub latitude: f64,
+    pub sea_ice: f64,
+    pub tongue: usize,
+    pub melt: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ice {
+    Snow,
+    SeaIce,
+    Glacier,
+}
+
+pub const MONTHS: usize = 12;
+
+// The sun and the wind belts swing `tilt` degrees of latitude either side of
+// the equator over a year of `year` ticks.
+#[derive(Clone, Deserialize)]
+pub struct Seasons {
+    pub tilt: f64,
+    pub year: usize,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum Terrain {
+    Perlin,
+    Plates(Plates),
+}
+
+// Everything `ProvBuilder::new` takes. `min` and `max` bound the raw noise,
+// `water_level` is cut from it before the edges are lowered by
+// `water_taper`, and the rows run from `lat_start` to `lat_end` degrees.
+#[derive(Clone, Deserialize)]
+pub struct WorldGenConfig {
+    pub size: usize,
+    pub freq: f64,
+    pub pers: f64,
+    pub lac: f64,
+    pub min: f64,
+    pub max: f64,
+    pub water_level: f64,
+    pub water_taper: f64,
+    pub lat_start: f64,
+    pub lat_end: f64,
+    pub seed: u64,
+    pub wrap: WrapMode,
+    pub noise: NoiseKind,
+}
+
+impl WorldGenConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        from_reader(File::open(path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let checks = [
+            (self.size >= 2, "size must be at least 2"),
+            (self.freq > 0., "freq must be positive"),
+            (self.pers > 0. && self.pers <= 1., "pers must be in (0, 1]"),
+            (self.lac >= 1., "lac must be at least 1"),
+            (self.min < self.max, "min must be below max"),
+            (self.water_level >= 0. && self.water_level < 1., "water_level must be in [0, 1)"),
+            (self.water_taper >= 0., "water_taper must not be negative"),
+            (self.lat_start.abs() <= 90. && self.lat_end.abs() <= 90., "lat_start and lat_end must be within 90 degrees"),
+            (self.lat_start < self.lat_end, "lat_start must be below lat_end"),
+        ];
+
+        match checks.iter().find(|(ok, _)| !ok) {
+            Some(&(_, error)) => Err(error.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
 pub struct ProvBuilder {
     noise: PerlinOctave,
+    pub seed: u64,
+    pub wrap: WrapMode,
+    pub terrain: Terrain,
+    pub thermal: Option<Thermal>,
+    pub seasons: Option<Seasons>,
+    pub currents: Option<Currents>,
+    pub glaciers: Option<Glaciers>,
+    pub orography: Option<Orography>,
+    pub basins: Option<Basins>,
+    pub rivers: Option<Rivers>,
+    pub provinces: Option<Provinces>,
+    pub groundwater: Option<Groundwater>,
     pub size: usize,
+    noise_cache: bool,
+    noise_values: Vec<f64>,
     pub neighbs: Vec<Vec<(usize, f64)>>,
     pub heightmap: Vec<f64>,
     pub waters: HashMap<usize, Water>,
     pub insolation: Vec<f64>,
     pub latitude: Vec<f64>,
     pub cloudmap: Vec<f64>,
+    pub currentmap: Vec<f64>,
     pub rivermap: Vec<f64>,
+    pub drainage: Vec<usize>,
+    pub segments: Vec<RiverSegment>,
     pub tempmap: Vec<f64>,
+    pub monthly_temp: Vec<Vec<f64>>,
+    pub monthly_cloud: Vec<Vec<f64>>,
     pub watermap: Vec<f64>,
+    pub groundwatermap: Vec<f64>,
     pub vegetmap: Vec<f64>,
+    pub soilmap: Vec<f64>,
+    pub snowmap: Vec<Option<Ice>>,
+    pub biomes: Vec<Biome>,
     pub settlements: Vec<bool>,
+    pub provincemap: Vec<u32>,
+    pub province_adjacency: Vec<Vec<u32>>,
+    pub navigable: Vec<bool>,
     water_level: f64,
     water_taper: f64,
     lat_start: f64,
@@ -183,10 +837,13 @@ pub struct ProvBuilder {
 impl ProvBuilder {
     pub fn new(
         size: usize, freq: f64, pers: f64, lac: f64, min: f64, max: f64, water_level: f64, water_taper: f64, 
-        lat_start: f64, lat_end: f64,
+        lat_start: f64, lat_end: f64, seed: u64, wrap: WrapMode, kind: NoiseKind,
     ) -> Self {
-        let noise = PerlinOctave {
-            noise: Perlin::new(),
+        let mut noise = PerlinOctave {
+            kind,
+            noise: Perlin::new().set_seed(perlin_seed(seed)),
+            simplex: OpenSimplex::new().set_seed(perlin_seed(seed)),
+            wrap,
             size,
             octave: (size as f64).log2() as usize,
             freq,
@@ -194,32 +851,40 @@ impl ProvBuilder {
             lac,
             min,
             max,
+            threshold: 0.,
+            octaves: Vec::new(),
+            peak: 0.,
+            max_total: 0.,
         };
 
-        let choices = [
-            (1, 1.),
-            (-1 as isize, 1.),
-            (size as isize, 1.),
-            (-(size as isize), 1.),
-            (1 + size as isize, 2f64.sqrt()),
-            (-1 + size as isize, 2f64.sqrt()),
-            (1 - (size as isize), 2f64.sqrt()),
-            (-1 - (size as isize), 2f64.sqrt()),
-        ];
+        noise.tabulate();
 
         let neighbs = (0..size * size)
             .into_iter()
             .map(|i| {
-                choices
+                OFFSETS
                     .iter()
-                    .map(|&(ii, c)| ((i as isize + ii) as usize, c))
-                    .filter(|&(ii, _)| is_neighbor(i, ii, size))
+                    .filter_map(|&(dx, dy)| neighbor_at(i, dx, dy, size, wrap).map(|ii| (ii, if dx != 0 && dy != 0 { 2f64.sqrt() } else { 1. })))
                     .collect()
             })
             .collect();
 
         ProvBuilder {
             size,
+            seed,
+            wrap,
+            terrain: Terrain::Perlin,
+            thermal: None,
+            seasons: None,
+            currents: None,
+            glaciers: None,
+            orography: None,
+            basins: None,
+            rivers: None,
+            provinces: None,
+            groundwater: None,
+            noise_cache: false,
+            noise_values: Vec::new(),
             noise,
             neighbs,
             heightmap: Vec::new(),
@@ -227,11 +892,23 @@ impl ProvBuilder {
             insolation: Vec::new(),
             latitude: Vec::new(),
             cloudmap: Vec::new(),
+            currentmap: Vec::new(),
             rivermap: Vec::new(),
+            drainage: Vec::new(),
+            segments: Vec::new(),
             tempmap: Vec::new(),
+            monthly_temp: Vec::new(),
+            monthly_cloud: Vec::new(),
             watermap: Vec::new(),
+            groundwatermap: Vec::new(),
             vegetmap: Vec::new(),
+            soilmap: Vec::new(),
+            snowmap: Vec::new(),
+            biomes: Vec::new(),
             settlements: Vec::new(),
+            provincemap: Vec::new(),
+            province_adjacency: Vec::new(),
+            navigable: Vec::new(),
             water_level,
             water_taper,
             lat_start,
@@ -239,307 +916,1395 @@ impl ProvBuilder {
         }
     }
 
-    pub fn gen_heightmap(&mut self) {
-        let size = self.size;
-        let med = size / 2;
+    pub fn with_config(config: &WorldGenConfig) -> Result<Self, String> {
+        config.validate()?;
 
-        self.heightmap.clear();
-        self.heightmap.reserve_exact(size * size);
+        Ok(ProvBuilder::new(
+            config.size, config.freq, config.pers, config.lac, config.min, config.max, config.water_level, config.water_taper,
+            config.lat_start, config.lat_end, config.seed, config.wrap, config.noise,
+        ))
+    }
 
-        for y in 0..size {
-            for x in 0..size {
-                let dist = (((med as f64 - x as f64).powi(2) + (med as f64 - y as f64).powi(2)).sqrt() / med as f64).powi(2);
-                let val = match self.noise.get(x as f64, y as f64) - self.water_level - self.water_taper * dist {
-                    x if x >= 0. => x / (1. - self.water_level),
-                    _ => 0.,
-                };
+    pub fn from_config<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        ProvBuilder::with_config(&WorldGenConfig::load(path)?)
+    }
 
-                self.heightmap.push(val * val * val);
-            }
-        }
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.noise.noise = Perlin::new().set_seed(perlin_seed(seed));
+        self.noise.simplex = OpenSimplex::new().set_seed(perlin_seed(seed));
+        self.noise_values.clear();
     }
 
-    pub fn gen_waters(&mut self) {
-        let size = self.size;
-        let mut stack = Vec::new();
+    pub fn set_noise(&mut self, defines: &NoiseDefines) {
+        self.noise.threshold = defines.threshold;
+        self.noise.tabulate();
+        self.noise_cache = defines.cache;
+        self.noise_values.clear();
+    }
 
-        stack.push(0);
-        self.waters.clear();
+    // The noise at every pixel, evaluated once per call unless it is cached.
+    fn noise_values(&mut self) -> Vec<f64> {
+        let size = self.size;
 
-        while !stack.is_empty() {
-            let i = stack.pop().unwrap();
+        if self.noise_values.len() != size * size {
+            let noise = &self.noise;
 
-            if self.waters.contains_key(&i) || self.heightmap[i] > 0. {
-                continue;
-            }
+            self.noise_values = (0..size * size).map(|i| noise.get((i % size) as f64, (i / size) as f64)).collect();
+        }
 
-            self.waters.insert(i, Water::Sea);
+        match self.noise_cache {
+            true => self.noise_values.clone(),
+            false => take(&mut self.noise_values),
+        }
+    }
 
-            if (i + 1) % size > 0 {
-                stack.push(i + 1);
-            }
-            if i % size > 0 {
-                stack.push(i - 1);
-            }
-            if i >= size {
-                stack.push(i - size);
-            }
-            if i + size < size * size {
-                stack.push(i + size);
-            }
+    pub fn generate(&mut self) {
+        match self.terrain.clone() {
+            Terrain::Perlin => self.gen_heightmap(),
+            Terrain::Plates(plates) => self.gen_heightmap_plates(&plates),
         }
 
-        for (i, &height) in self.heightmap.iter().enumerate() {
-            if height == 0. && !self.waters.contains_key(&i) {
-                self.waters.insert(i, Water::Lake);
-            }
+        if let Some(thermal) = self.thermal.clone() {
+            self.erode_thermal(&thermal);
         }
+
+        self.gen_from_heightmap();
     }
 
-    pub fn gen_insolation(&mut self) {
-        let size = self.size;
+    // Tries `tries` seeds from `seed` on and keeps the first map without
+    // violations, returning the seed used or the violations of the last try.
+    pub fn generate_valid(&mut self, seed: u64, tries: u64) -> Result<u64, Vec<Violation>> {
+        let mut violations = Vec::new();
 
-        self.latitude = vec![0.; size * size];
-        self.insolation = vec![0.; size * size];
+        for n in 0..tries.max(1) {
+            let seed = seed.wrapping_add(n);
 
-        for y in 0..size {
-            let lat = (self.lat_end - self.lat_start) * y as f64 / (size - 1) as f64 + self.lat_start;
-            let insolation = -lat.powi(2) / 10000. + 1.;
+            self.set_seed(seed);
+            self.generate();
 
-            for x in 0..size {
-                self.latitude[x + y * size] = lat;
-                self.insolation[x + y * size] = insolation;
+            violations = self.invariants();
+
+            if violations.is_empty() {
+                return Ok(seed);
             }
         }
+
+        Err(violations)
     }
 
-    pub fn gen_cloud(&mut self) {
+    pub fn invariants(&self) -> Vec<Violation> {
         let size = self.size;
+        let mut violations = Vec::new();
 
-        self.cloudmap = vec![0.; size * size];
+        for i in 0..self.drainage.len() {
+            if self.drainage[i] == NO_OUTLET || self.waters.contains_key(&i) {
+                continue;
+            }
 
-        let s60 = find_lat(&self.latitude, -60., size);
-        let s30 = find_lat(&self.latitude, -30., size);
-        let s0 = find_lat(&self.latitude, 0., size);
-        let n30 = find_lat(&self.latitude, 30., size);
-        let n60 = find_lat(&self.latitude, 60., size);
+            let mut ii = i;
+            let mut steps = 0;
 
-        if s30 != s60 {
-            for x in 0..size {
-                do_wind(x, s30, s60, -30., -60., (1., -1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.1, 1.);
+            while self.drainage[ii] != NO_OUTLET && steps <= size * size {
+                ii = self.drainage[ii];
+                steps += 1;
             }
-            for y in s60..s30 {
-                do_wind(0, y, s60, -30., -60., (1., -1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.1, (y - s60) as f64 / (s30 - s60) as f64);
+
+            if steps > size * size || !self.waters.contains_key(&ii) {
+                violations.push(Violation::RiverSink(i));
             }
         }
-        if s30 != s0 {
-            for x in 0..size {
-                do_wind(x, s30, s0, -30., 0., (-1., 1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.25, 1.);
-            }
-            for y in s30..s0 {
-                do_wind(size - 1, y, s0, -30., 0., (-1., 1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.25, (s0 - y) as f64 / (s0 - s30) as f64);
+
+        // Latitude and currents are signed by design.
+        for (name, map) in self.layers().into_iter().filter(|&(name, _)| name != "latitude" && name != "currentmap") {
+            if let Some(i) = map.iter().position(|&value| !(value >= 0.)) {
+                violations.push(Violation::Negative(name, i));
             }
         }
-        if n30 != s0 {
-            let s00;
 
-            if s30 != s0 {
-                s00 = s0 - 1;
-            } else {
-                s00 = s0;
+        for (i, &height) in self.heightmap.iter().enumerate() {
+            if (height > 0.) == self.waters.contains_key(&i) {
+                violations.push(Violation::WaterHeight(i));
             }
+        }
 
-            for x in 0..size {
-                do_wind(x, n30, s00, 30., 0., (-1., -1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.25, 1.);
-            }
-            for y in s00..n30 {
-                do_wind(size - 1, y, s00, 30., 0., (-1., -1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.25, (y - s00) as f64 / (n30 - s00) as f64);
+        let settlements: Vec<usize> = (0..self.settlements.len()).filter(|&i| self.settlements[i]).collect();
+
+        for (n, &i) in settlements.iter().enumerate() {
+            if self.waters.contains_key(&i) {
+                violations.push(Violation::SettlementWater(i));
             }
 
-            if s30 != s0 {
-                let y_diff = (size as f64).cbrt() as usize;
-                for x in 0..size { 
-                    self.cloudmap[s0 * size + x] += 0.05; 
-                }
+            for &ii in settlements[n + 1..].iter() {
+                let (dx, dy) = offset_between(i, ii, size, self.wrap);
 
-                for y in 1..y_diff {
-                    for x in 0..size {
-                        self.cloudmap[(s0 - y) * size + x] += 0.05 * (y_diff as f64 - y as f64) / y_diff as f64;
-                        self.cloudmap[(s0 + y) * size + x] += 0.05 * (y_diff as f64 - y as f64) / y_diff as f64;
-                    }
+                if (dx.abs().max(dy.abs()) as usize) < SETTLEMENT_SPACING {
+                    violations.push(Violation::SettlementSpacing(i, ii));
                 }
             }
         }
-        if n30 != n60 {
-            for x in 0..size {
-                do_wind(x, n30, n60, 30., 60., (1., 1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.1, 1.);
-            }
-            for y in n30..n60 {
-                do_wind(0, y, n60, 30., 60., (1., 1.), size, &mut self.cloudmap, &self.latitude, &self.heightmap, 0.1, (n60 - y) as f64 / (n60 - n30) as f64);
-            }
-        }
 
-        let max = self.cloudmap.iter().max_by(|x, y| x.partial_cmp(y).unwrap()).unwrap();
-        self.cloudmap = self.cloudmap.iter().map(|x| x / max).collect();
+        violations
     }
 
-    pub fn gen_temp(&mut self) {
-        let size = self.size;
+    // Everything downstream of the heightmap, so an imported one goes through
+    // the same climate and river passes as a generated one.
+    pub fn gen_from_heightmap(&mut self) {
+        self.gen_insolation();
+        self.gen_waters();
 
-        self.tempmap = vec![0.; size * size];
-
-        for i in 0..size * size {
-            self.tempmap[i] = clamp(self.insolation[i] * (1. - self.cloudmap[i] / 2.) - (self.heightmap[i] / 4.), 0., 1.);
+        match self.currents.clone() {
+            Some(currents) => self.gen_currents(&currents),
+            None => self.currentmap.clear(),
         }
-    }
 
-    pub fn gen_rivermap(&mut self) {
-        let size = self.size;
+        self.gen_cloud();
+        self.gen_temp();
 
-        let mut river_drainage = vec![0; size * size];
-        let mut height_ordered = self.heightmap
-            .iter()
-            .enumerate()
-            .filter(|(_, &height)| height > 0.)
-            .collect::<Vec<(usize, &f64)>>();
-        height_ordered.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
-
-        loop {
-            if let Some((i, _)) = height_ordered.pop() {
-                let (paths, _) = dijkstra(&i, 
-                    |&i| {
-                        let neighbs: Vec<(usize, usize)> = self.neighbs[i]
-                            .iter()
-                            .map(|&(ii, c)| (ii, (10000. * c * (self.heightmap[ii] / (self.heightmap[i] + 0.001))) as usize))
-                            .collect();
+        if let Some(seasons) = self.seasons.clone() {
+            self.gen_seasons(&seasons);
+        }
 
-                        return neighbs;
-                    },
-                    |&i| {
-                        if river_drainage[i] != 0 {
-                            return true;
-                        } else if let Some(water) = self.waters.get(&i) {
-                            match water {
-                                Water::Lake => return false,
-                                Water::Sea => return true,
-                            };
-                        }
+        self.gen_rivermap();
 
-                        return false;
-                    }
-                ).unwrap();
+        match self.glaciers.clone() {
+            Some(glaciers) => self.gen_snowmap(&glaciers),
+            None => self.snowmap = vec![None; self.size * self.size],
+        }
+
+        match self.rivers.clone() {
+            Some(rivers) => self.gen_segments(&rivers),
+            None => self.segments.clear(),
+        }
+
+        self.gen_watermap();
+
+        match self.groundwater.clone() {
+            Some(groundwater) => self.gen_groundwater(&groundwater),
+            None => self.groundwatermap.clear(),
+        }
+
+        self.gen_vegetmap();
+        self.gen_soilmap();
+        self.gen_biomes();
+        self.gen_settlements();
+
+        match self.provinces.clone() {
+            Some(provinces) => self.gen_provinces(&provinces),
+            None => {
+                self.provincemap.clear();
+                self.province_adjacency.clear();
+            }
+        }
+    }
+
+    // Lowers `raw` by the water level and towards the edges of the map that
+    // do not wrap, the same way for every generator.
+    fn shape(&self, raw: f64, x: usize, y: usize) -> f64 {
+        let med = self.size / 2;
+        let dx = match self.wrap {
+            WrapMode::None => (med as f64 - x as f64).powi(2),
+            _ => 0.,
+        };
+        let dy = match self.wrap {
+            WrapMode::Torus => 0.,
+            _ => (med as f64 - y as f64).powi(2),
+        };
+        let dist = ((dx + dy).sqrt() / med as f64).powi(2);
+        let val = match raw - self.water_level - self.water_taper * dist {
+            x if x >= 0. => x / (1. - self.water_level),
+            _ => 0.,
+        };
+
+        val * val * val
+    }
+
+    pub fn gen_heightmap(&mut self) {
+        let size = self.size;
+
+        let noise = self.noise_values();
+
+        self.heightmap.clear();
+        self.heightmap.reserve_exact(size * size);
+
+        for y in 0..size {
+            for x in 0..size {
+                let val = self.shape(noise[x + y * size], x, y);
+
+                self.heightmap.push(val);
+            }
+        }
+    }
+
+    // Every pixel joins the plate with the nearest centre, the distance bent
+    // by the noise so plates are not plain Voronoi cells. Where two plates
+    // close on each other the boundary is raised and where they part it is
+    // sunk, fading out over `width` pixels.
+    pub fn gen_heightmap_plates(&mut self, plates: &Plates) {
+        let size = self.size;
+        let mut rng = StdRng::seed_from_u64(self.seed ^ 0x504c_4154);
+
+        let centres: Vec<(f64, f64)> = (0..plates.count.max(1))
+            .map(|_| (rng.gen_range(0., size as f64), rng.gen_range(0., size as f64)))
+            .collect();
+        let drifts: Vec<(f64, f64)> = centres
+            .iter()
+            .map(|_| {
+                let angle = rng.gen_range(0., 2. * PI);
+
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        let bases: Vec<f64> = centres
+            .iter()
+            .map(|_| if rng.gen::<f64>() < plates.land { plates.continent } else { plates.ocean })
+            .collect();
+
+        let noise = self.noise_values();
+        let mut plate = vec![0; size * size];
+
+        for y in 0..size {
+            for x in 0..size {
+                let warp = (noise[x + y * size] - 0.5) * plates.warp;
+
+                plate[x + y * size] = centres
+                    .iter()
+                    .map(|&(cx, cy)| {
+                        let mut dx = (cx - x as f64 - warp).abs();
+                        let mut dy = (cy - y as f64 + warp).abs();
+
+                        if self.wrap != WrapMode::None {
+                            dx = dx.min(size as f64 - dx);
+                        }
+                        if self.wrap == WrapMode::Torus {
+                            dy = dy.min(size as f64 - dy);
+                        }
+
+                        (dx * dx + dy * dy).sqrt()
+                    })
+                    .enumerate()
+                    .fold((0, f64::MAX), |best, (p, d)| if d < best.1 { (p, d) } else { best })
+                    .0;
+            }
+        }
+
+        // Positive where the plates move towards each other across the
+        // boundary, negative where they move apart.
+        let mut stress = vec![0.; size * size];
+        let mut dist = vec![usize::MAX; size * size];
+        let mut queue = VecDeque::new();
+
+        for i in 0..size * size {
+            let a = plate[i];
+            let others: Vec<usize> = self.neighbs[i].iter().map(|&(ii, _)| plate[ii]).filter(|&b| b != a).collect();
+
+            if others.is_empty() {
+                continue;
+            }
+
+            let mut total = 0.;
+
+            for &b in others.iter() {
+                let (nx, ny) = (centres[b].0 - centres[a].0, centres[b].1 - centres[a].1);
+                let len = (nx * nx + ny * ny).sqrt().max(1.);
+
+                total += ((drifts[a].0 - drifts[b].0) * nx + (drifts[a].1 - drifts[b].1) * ny) / len;
+            }
+
+            stress[i] = total / others.len() as f64;
+            dist[i] = 0;
+            queue.push_back(i);
+        }
+
+        while let Some(i) = queue.pop_front() {
+            for &(ii, _) in self.neighbs[i].iter() {
+                if dist[ii] == usize::MAX && dist[i] + 1 < plates.width {
+                    dist[ii] = dist[i] + 1;
+                    stress[ii] = stress[i];
+                    queue.push_back(ii);
+                }
+            }
+        }
+
+        self.heightmap.clear();
+        self.heightmap.reserve_exact(size * size);
+
+        for y in 0..size {
+            for x in 0..size {
+                let i = x + y * size;
+                let fade = match dist[i] {
+                    usize::MAX => 0.,
+                    d => (1. - d as f64 / plates.width as f64).powi(2),
+                };
+                let lift = match stress[i] {
+                    s if s > 0. => s * plates.uplift,
+                    s => s * plates.rift,
+                };
+                let detail = (noise[i] - 0.5) * plates.detail;
+                let val = self.shape(bases[plate[i]] + lift * fade + detail, x, y);
+
+                self.heightmap.push(val);
+            }
+        }
+    }
+
+    // Material sliding into the sea is lost rather than raising the sea
+    // floor, so the coastline stays where the heightmap put it.
+    pub fn erode_thermal(&mut self, thermal: &Thermal) {
+        let size = self.size;
+
+        for _ in 0..thermal.passes {
+            let mut delta = vec![0.; size * size];
+
+            for i in 0..size * size {
+                let height = self.heightmap[i];
+
+                if height <= 0. {
+                    continue;
+                }
+
+                let excess: Vec<(usize, f64)> = self.neighbs[i]
+                    .iter()
+                    .map(|&(ii, c)| (ii, height - self.heightmap[ii] - thermal.talus * c))
+                    .filter(|&(_, d)| d > 0.)
+                    .collect();
+                let total: f64 = excess.iter().map(|&(_, d)| d).sum();
+                let most = excess.iter().map(|&(_, d)| d).fold(0., f64::max);
+
+                if total <= 0. {
+                    continue;
+                }
+
+                let moved = thermal.rate * most / 2.;
+
+                delta[i] -= moved;
+
+                for &(ii, d) in excess.iter() {
+                    if self.heightmap[ii] > 0. {
+                        delta[ii] += moved * d / total;
+                    }
+                }
+            }
+
+            for i in 0..size * size {
+                self.heightmap[i] = (self.heightmap[i] + delta[i]).max(0.);
+            }
+        }
+    }
+
+    pub fn gen_synthetic(&mut self, synthetic: &Synthetic) {
+        let size = self.size;
+        let river_x = synthetic.river_x.min(size - 1);
+
+        self.heightmap = (0..size * size)
+            .map(|i| if i / size < synthetic.sea_rows { 0. } else { synthetic.height })
+            .collect();
+        self.gen_waters();
+
+        self.latitude = vec![0.; size * size];
+        self.insolation = vec![1.; size * size];
+        self.cloudmap = vec![synthetic.rain; size * size];
+        self.tempmap = vec![synthetic.temp; size * size];
+        self.rivermap = (0..size * size)
+            .map(|i| if i % size == river_x && self.heightmap[i] > 0. { synthetic.river } else { 0. })
+            .collect();
+        self.drainage = (0..size * size)
+            .map(|i| if self.rivermap[i] > 0. && i >= size { i - size } else { NO_OUTLET })
+            .collect();
+
+        self.snowmap = vec![None; size * size];
+        self.gen_watermap();
+        self.gen_vegetmap();
+        self.gen_soilmap();
+        self.gen_biomes();
+
+        self.settlements = vec![false; size * size];
+
+        let bank = (size / 2) * size + (river_x + 1).min(size - 1);
+
+        if self.heightmap[bank] > 0. {
+            self.settlements[bank] = true;
+        }
+    }
+
+    // Elevations at or below sea_level become water, the rest is scaled so the
+    // highest point of the DEM is 1.
+    pub fn import_dem(&mut self, dem: &Dem, sea_level: f64) {
+        let heights = dem.resample(self.size);
+        let max = heights.iter().fold(sea_level, |max, &h| max.max(h as f64));
+        let range = (max - sea_level).max(1e-9);
+
+        self.heightmap = heights.into_iter().map(|h| clamp((h as f64 - sea_level) / range, 0., 1.)).collect();
+    }
+
+    pub fn gen_waters(&mut self) {
+        let size = self.size;
+        let mut stack = Vec::new();
+
+        stack.push(0);
+        self.waters.clear();
+
+        while !stack.is_empty() {
+            let i = stack.pop().unwrap();
+
+            if self.waters.contains_key(&i) || self.heightmap[i] > 0. {
+                continue;
+            }
+
+            self.waters.insert(i, Water::Sea);
+
+            for &(dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+                if let Some(ii) = neighbor_at(i, dx, dy, size, self.wrap) {
+                    stack.push(ii);
+                }
+            }
+        }
+
+        for (i, &height) in self.heightmap.iter().enumerate() {
+            if height == 0. && !self.waters.contains_key(&i) {
+                self.waters.insert(i, Water::Lake);
+            }
+        }
+    }
+
+    pub fn gen_insolation(&mut self) {
+        let size = self.size;
+
+        self.latitude = vec![0.; size * size];
+
+        for y in 0..size {
+            let lat = (self.lat_end - self.lat_start) * y as f64 / (size - 1) as f64 + self.lat_start;
+
+            for x in 0..size {
+                self.latitude[x + y * size] = lat;
+            }
+        }
+
+        self.insolation = self.insolation_for(0.);
+    }
+
+    // With the sun overhead at `declination` degrees of latitude.
+    fn insolation_for(&self, declination: f64) -> Vec<f64> {
+        self.latitude.iter().map(|&lat| -(lat - declination).powi(2) / 10000. + 1.).collect()
+    }
+
+    pub fn gen_cloud(&mut self) {
+        self.cloudmap = self.winds(0.);
+    }
+
+    // Rain carried by the wind belts, each `shift` degrees of latitude north
+    // of where it sits at the equinox.
+    fn winds(&self, shift: f64) -> Vec<f64> {
+        let size = self.size;
+
+        let mut cloudmap = vec![0.; size * size];
+        let orography = self.orography.as_ref().map(|orography| (orography, &self.waters));
+
+        let s60 = find_lat(&self.latitude, -60. + shift, size);
+        let s30 = find_lat(&self.latitude, -30. + shift, size);
+        let s0 = find_lat(&self.latitude, shift, size);
+        let n30 = find_lat(&self.latitude, 30. + shift, size);
+        let n60 = find_lat(&self.latitude, 60. + shift, size);
+
+        if s30 != s60 {
+            for x in 0..size {
+                do_wind(x, s30, s60, -30. + shift, -60. + shift, (1., -1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.1, 1., orography);
+            }
+            for y in s60..s30 {
+                do_wind(0, y, s60, -30. + shift, -60. + shift, (1., -1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.1, (y - s60) as f64 / (s30 - s60) as f64, orography);
+            }
+        }
+        if s30 != s0 {
+            for x in 0..size {
+                do_wind(x, s30, s0, -30. + shift, shift, (-1., 1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.25, 1., orography);
+            }
+            for y in s30..s0 {
+                do_wind(size - 1, y, s0, -30. + shift, shift, (-1., 1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.25, (s0 - y) as f64 / (s0 - s30) as f64, orography);
+            }
+        }
+        if n30 != s0 {
+            let s00;
+
+            if s30 != s0 {
+                s00 = s0 - 1;
+            } else {
+                s00 = s0;
+            }
+
+            for x in 0..size {
+                do_wind(x, n30, s00, 30. + shift, shift, (-1., -1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.25, 1., orography);
+            }
+            for y in s00..n30 {
+                do_wind(size - 1, y, s00, 30. + shift, shift, (-1., -1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.25, (y - s00) as f64 / (n30 - s00) as f64, orography);
+            }
+
+            if s30 != s0 {
+                let y_diff = (size as f64).cbrt() as usize;
+                for x in 0..size { 
+                    cloudmap[s0 * size + x] += 0.05; 
+                }
+
+                for y in (1..y_diff).take_while(|&y| y <= s0 && s0 + y < size) {
+                    for x in 0..size {
+                        cloudmap[(s0 - y) * size + x] += 0.05 * (y_diff as f64 - y as f64) / y_diff as f64;
+                        cloudmap[(s0 + y) * size + x] += 0.05 * (y_diff as f64 - y as f64) / y_diff as f64;
+                    }
+                }
+            }
+        }
+        if n30 != n60 {
+            for x in 0..size {
+                do_wind(x, n30, n60, 30. + shift, 60. + shift, (1., 1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.1, 1., orography);
+            }
+            for y in n30..n60 {
+                do_wind(0, y, n60, 30. + shift, 60. + shift, (1., 1.), size, self.wrap, &mut cloudmap, &self.latitude, &self.heightmap, 0.1, (n60 - y) as f64 / (n60 - n30) as f64, orography);
+            }
+        }
+
+        let max = cloudmap.iter().max_by(|x, y| x.partial_cmp(y).unwrap()).unwrap();
+        cloudmap.iter().map(|x| x / max).collect()
+    }
+
+    // Rows are cut into runs of sea between land, or between the edges that do
+    // not wrap, and a run is placed from its western end eastward.
+    pub fn gen_currents(&mut self, currents: &Currents) {
+        let size = self.size;
+
+        self.currentmap = vec![0.; size * size];
+
+        for y in 0..size {
+            let start = match self.wrap {
+                WrapMode::None => 0,
+                _ => match (0..size).find(|&x| !self.is_sea(x + y * size)) {
+                    Some(x) => x,
+                    None => continue,
+                },
+            };
+            let mut run = Vec::new();
+
+            for step in 0..=size {
+                let x = (start + step) % size;
+                let i = x + y * size;
+
+                if step < size && self.is_sea(i) {
+                    run.push(i);
+                    continue;
+                }
+
+                if run.len() >= currents.width.max(1) {
+                    let lat = self.latitude[i].abs();
+                    let gyre = match lat {
+                        l if l >= 10. && l < 45. => (PI * (l - 10.) / 35.).sin(),
+                        l if l >= 45. && l < 70. => -(PI * (l - 45.) / 25.).sin() / 2.,
+                        _ => 0.,
+                    };
+
+                    for (k, &ii) in run.iter().enumerate() {
+                        let t = k as f64 / (run.len() - 1).max(1) as f64;
+
+                        self.currentmap[ii] = currents.strength * gyre * (1. - 2. * t).powi(3);
+                    }
+                }
+
+                run.clear();
+            }
+        }
+
+        let mut reached = vec![None; size * size];
+        let mut queue = VecDeque::new();
+
+        for i in 0..size * size {
+            if self.is_sea(i) {
+                reached[i] = Some((i, 0));
+                queue.push_back(i);
+            }
+        }
+
+        while let Some(i) = queue.pop_front() {
+            let (sea, dist) = reached[i].unwrap();
+
+            if dist >= currents.reach {
+                continue;
+            }
+
+            for &(ii, _) in self.neighbs[i].iter() {
+                if reached[ii].is_none() {
+                    reached[ii] = Some((sea, dist + 1));
+                    self.currentmap[ii] = self.currentmap[sea] * (1. - (dist + 1) as f64 / (currents.reach + 1) as f64);
+                    queue.push_back(ii);
+                }
+            }
+        }
+    }
+
+    fn is_sea(&self, i: usize) -> bool {
+        match self.waters.get(&i) {
+            Some(Water::Sea) => true,
+            _ => false,
+        }
+    }
+
+    pub fn gen_temp(&mut self) {
+        self.tempmap = self.temp_for(&self.insolation, &self.cloudmap);
+    }
+
+    fn temp_for(&self, insolation: &Vec<f64>, cloudmap: &Vec<f64>) -> Vec<f64> {
+        (0..self.size * self.size)
+            .map(|i| {
+                let current = self.currentmap.get(i).cloned().unwrap_or(0.);
+
+                clamp(insolation[i] * (1. - cloudmap[i] / 2.) - (self.heightmap[i] / 4.) + current, 0., 1.)
+            })
+            .collect()
+    }
+
+    // Each month is taken at its middle, with the sun furthest north in June.
+    // A month's rain is scaled to its own wettest pixel, as the year's is.
+    pub fn gen_seasons(&mut self, seasons: &Seasons) {
+        self.monthly_temp = Vec::with_capacity(MONTHS);
+        self.monthly_cloud = Vec::with_capacity(MONTHS);
+
+        for month in 0..MONTHS {
+            let declination = seasons.tilt * (2. * PI * (month as f64 + 0.5 - 3.) / MONTHS as f64).sin();
+            let insolation = self.insolation_for(declination);
+            let cloudmap = self.winds(declination);
+
+            self.monthly_temp.push(self.temp_for(&insolation, &cloudmap));
+            self.monthly_cloud.push(cloudmap);
+        }
+    }
+
+    // The year's map for maps generated without seasons.
+    pub fn temp_at(&self, i: usize, month: usize) -> f64 {
+        self.monthly_temp.get(month % MONTHS).map_or(self.tempmap[i], |tempmap| tempmap[i])
+    }
+
+    pub fn cloud_at(&self, i: usize, month: usize) -> f64 {
+        self.monthly_cloud.get(month % MONTHS).map_or(self.cloudmap[i], |cloudmap| cloudmap[i])
+    }
+
+    // Priority flood from every water pixel: land is reached lowest first,
+    // pits included, and drains to the pixel it was reached from, so each
+    // river runs down to the sea or a lake without a search of its own.
+    pub fn gen_rivermap(&mut self) {
+        let size = self.size;
+
+        let mut river_drainage = vec![NO_OUTLET; size * size];
+        let mut seen = vec![false; size * size];
+        let mut heap = BinaryHeap::new();
+        let mut order = 0usize;
+
+        for i in 0..size * size {
+            if self.waters.contains_key(&i) {
+                seen[i] = true;
+                heap.push(Reverse((0f64.to_bits(), order, i)));
+                order += 1;
+            }
+        }
+
+        if heap.is_empty() {
+            if let Some(lowest) = (0..size * size).min_by(|&a, &b| self.heightmap[a].partial_cmp(&self.heightmap[b]).unwrap()) {
+                seen[lowest] = true;
+                heap.push(Reverse((self.heightmap[lowest].max(0.).to_bits(), order, lowest)));
+            }
+        }
+
+        // Heights are never negative, so their bits order as they do.
+        while let Some(Reverse((level, _, i))) = heap.pop() {
+            let level = f64::from_bits(level);
+
+            for &(ii, _) in self.neighbs[i].iter() {
+                if seen[ii] {
+                    continue;
+                }
+
+                seen[ii] = true;
+                river_drainage[ii] = i;
+                order += 1;
+                heap.push(Reverse((self.heightmap[ii].max(level).to_bits(), order, ii)));
+            }
+        }
+
+        self.spill_lakes(&mut river_drainage);
+
+        self.rivermap = self.accumulate(&river_drainage);
+
+        for river in self.rivermap.iter_mut() {
+            *river = river.sqrt();
+        }
+
+        let mx = self.rivermap
+            .iter()
+            .max_by(|a, b| a.partial_cmp(b).unwrap())
+            .unwrap()
+            .clone();
+        
+        for river in self.rivermap.iter_mut() {
+            *river /= mx;
+        }
+
+        self.drainage = river_drainage;
+    }
+
+    // Rain gathered down `drainage`, each pixel passed on only once all the
+    // pixels above it have been.
+    fn accumulate(&self, drainage: &Vec<usize>) -> Vec<f64> {
+        let size = self.size;
+        let mut flow: Vec<f64> = (0..size * size).map(|i| if drainage[i] != NO_OUTLET { self.cloudmap[i] } else { 0. }).collect();
+        let mut above = vec![0; size * size];
+
+        for i in 0..size * size {
+            if drainage[i] != NO_OUTLET {
+                above[drainage[i]] += 1;
+            }
+        }
+
+        let mut ready: Vec<usize> = (0..size * size).filter(|&i| above[i] == 0).collect();
+
+        while let Some(i) = ready.pop() {
+            let next = drainage[i];
+
+            if next == NO_OUTLET {
+                continue;
+            }
+
+            flow[next] += flow[i];
+            above[next] -= 1;
+
+            if above[next] == 0 {
+                ready.push(next);
+            }
+        }
+
+        flow
+    }
+
+    // Connected lake pixels, each lake with the lowest land on its shore.
+    fn lake_bodies(&self) -> Vec<(Vec<usize>, Option<usize>)> {
+        let mut seen = vec![false; self.size * self.size];
+        let mut lakes = Vec::new();
+
+        for i in 0..self.size * self.size {
+            if !matches!(self.waters.get(&i), Some(Water::Lake)) || seen[i] {
+                continue;
+            }
+
+            let mut body = Vec::new();
+            let mut stack = vec![i];
+            let mut spill: Option<usize> = None;
+
+            seen[i] = true;
+
+            while let Some(ii) = stack.pop() {
+                body.push(ii);
+
+                for &(iii, _) in self.neighbs[ii].iter() {
+                    match self.waters.get(&iii) {
+                        Some(Water::Lake) if !seen[iii] => {
+                            seen[iii] = true;
+                            stack.push(iii);
+                        }
+                        None if spill.map_or(true, |spill| self.heightmap[iii] < self.heightmap[spill]) => spill = Some(iii),
+                        _ => {}
+                    }
+                }
+            }
+
+            lakes.push((body, spill));
+        }
+
+        lakes.sort_by(|(_, a), (_, b)| {
+            let a = a.map_or(0., |a| self.heightmap[a]);
+            let b = b.map_or(0., |b| self.heightmap[b]);
+
+            b.partial_cmp(&a).unwrap()
+        });
+
+        lakes
+    }
+
+    // Rivers first end in whatever water they reach. Lakes are then let out
+    // from the highest shore down, each through its spill point along a
+    // course that does not lead back into it, and what overflows is passed
+    // on to the lake the course ends in.
+    fn spill_lakes(&mut self, drainage: &mut Vec<usize>) {
+        let size = self.size;
+        let lakes = self.lake_bodies();
+        let mut lake_of = vec![None; size * size];
+
+        for (l, (body, _)) in lakes.iter().enumerate() {
+            for &i in body.iter() {
+                lake_of[i] = Some(l);
+            }
+        }
+
+        let end = |drainage: &Vec<usize>, i: usize| {
+            let mut ii = i;
+            let mut steps = 0;
+
+            while drainage[ii] != NO_OUTLET && steps <= size * size {
+                ii = drainage[ii];
+                steps += 1;
+            }
+
+            ii
+        };
+
+        let flow = self.accumulate(drainage);
+        let mut inflow: Vec<f64> = lakes.iter().map(|(body, _)| body.iter().map(|&i| flow[i] + self.cloudmap[i]).sum()).collect();
+
+        for (l, (body, spill)) in lakes.iter().enumerate() {
+            let loss = self.basins.as_ref().map_or(0., |basins| body.iter().map(|&i| basins.evaporation * self.tempmap[i]).sum());
+            let surplus = inflow[l] - loss;
+
+            let course = match spill {
+                Some(spill) if surplus > 0. || self.basins.is_none() => dijkstra(spill,
+                    |&i| {
+                        self.neighbs[i]
+                            .iter()
+                            .filter(|&&(ii, _)| lake_of[ii] != Some(l))
+                            .map(|&(ii, c)| (ii, (10000. * c * (self.heightmap[ii] / (self.heightmap[i] + 0.001))) as usize))
+                            .collect::<Vec<(usize, usize)>>()
+                    },
+                    |&i| {
+                        i != *spill
+                            && (drainage[i] != NO_OUTLET || self.waters.contains_key(&i))
+                            && lake_of[end(drainage, i)] != Some(l)
+                    }
+                ),
+                _ => None,
+            };
+
+            match course {
+                Some((path, _)) => {
+                    for n in 0..path.len() - 1 {
+                        drainage[path[n]] = path[n + 1];
+                    }
+                    for &i in body.iter() {
+                        drainage[i] = path[0];
+                    }
+                    if let Some(next) = lake_of[end(drainage, path[path.len() - 1])] {
+                        inflow[next] += surplus.max(0.);
+                    }
+                }
+                None => {
+                    for &i in body.iter() {
+                        self.waters.insert(i, Water::SaltLake);
+                    }
+                }
+            }
+        }
+    }
+
+    // Laid over the finished rivermap, meltwater included.
+    pub fn gen_segments(&mut self, rivers: &Rivers) {
+        let size = self.size;
+        let mut segment_of = vec![None; size * size];
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        self.segments.clear();
+
+        for i in 0..size * size {
+            if self.waters.get(&i).is_none() && self.drainage[i] != NO_OUTLET && self.rivermap[i] >= rivers.threshold {
+                segment_of[i] = Some(self.segments.len());
+                self.segments.push(RiverSegment { pixel: i, width: rivers.width * self.rivermap[i], down: None, mouth: None, offset: (0., 0.) });
+            }
+        }
+
+        for s in 0..self.segments.len() {
+            let i = self.segments[s].pixel;
+            let next = self.drainage[i];
+
+            match self.waters.get(&next) {
+                Some(Water::Sea) => self.segments[s].mouth = Some((Mouth::Sea, next)),
+                Some(Water::Lake) => self.segments[s].mouth = Some((Mouth::Lake, next)),
+                Some(Water::SaltLake) => self.segments[s].mouth = Some((Mouth::SaltLake, next)),
+                None => self.segments[s].down = segment_of[next],
+            }
+
+            let gradient = (self.heightmap[i] - self.heightmap[next].max(0.)).max(0.);
+
+            if gradient < rivers.gradient {
+                let (dx, dy) = offset_between(i, next, size, self.wrap);
+                let len = (dx * dx + dy * dy).sqrt().max(1.);
+                let amount = rng.gen_range(-1., 1.) * rivers.meander * (1. - gradient / rivers.gradient);
+
+                self.segments[s].offset = (-dy / len * amount, dx / len * amount);
+            }
+        }
+
+        let mouths: Vec<usize> = (0..self.segments.len())
+            .filter(|&s| matches!(self.segments[s].mouth, Some((Mouth::Sea, _))) && self.segments[s].width >= rivers.delta)
+            .collect();
+
+        for s in mouths.into_iter() {
+            let (pixel, width) = (self.segments[s].pixel, self.segments[s].width);
+            let main = self.drainage[pixel];
+            let outlets: Vec<usize> = self.neighbs[pixel]
+                .iter()
+                .map(|&(ii, _)| ii)
+                .filter(|&ii| ii != main && matches!(self.waters.get(&ii), Some(Water::Sea)))
+                .take(rivers.branches)
+                .collect();
+            let share = width / (outlets.len() + 1) as f64;
+
+            self.segments[s].width = share;
+
+            for outlet in outlets.into_iter() {
+                self.segments.push(RiverSegment { pixel, width: share, down: None, mouth: Some((Mouth::Delta, outlet)), offset: self.segments[s].offset });
+            }
+        }
+    }
+
+    pub fn gen_snowmap(&mut self, glaciers: &Glaciers) {
+        let size = self.size;
+
+        self.snowmap = (0..size * size)
+            .map(|i| {
+                let lat = self.latitude[i].abs();
+                let line = glaciers.snowline + glaciers.altitude * self.heightmap[i].max(0.) + glaciers.latitude * lat / 90.;
+
+                match (self.waters.get(&i), self.tempmap[i] < line) {
+                    (None, true) => Some(Ice::Snow),
+                    (Some(Water::Sea), true) if lat >= glaciers.sea_ice => Some(Ice::SeaIce),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        // Tongues follow the drainage down from ice whose water leaves it;
+        // past their snouts the meltwater swells the river to its mouth.
+        let edges: Vec<usize> = (0..size * size)
+            .filter(|&i| self.snowmap[i] == Some(Ice::Snow) && self.drainage[i] != NO_OUTLET && self.snowmap[self.drainage[i]].is_none())
+            .collect();
+
+        for i in edges.into_iter() {
+            let mut ii = self.drainage[i];
+            let mut length = 0;
+
+            while length < glaciers.tongue && self.waters.get(&ii).is_none() && self.snowmap[ii].is_none() {
+                self.snowmap[ii] = Some(Ice::Glacier);
+                length += 1;
+
+                match self.drainage[ii] {
+                    NO_OUTLET => break,
+                    next => ii = next,
+                }
+            }
+
+            loop {
+                self.rivermap[ii] = (self.rivermap[ii] + glaciers.melt).min(1.);
+
+                match self.drainage[ii] {
+                    NO_OUTLET => break,
+                    next => ii = next,
+                }
+            }
+        }
+    }
+
+    pub fn frozen(&self, i: usize) -> bool {
+        self.snowmap.get(i).map_or(false, |ice| ice.is_some())
+    }
+
+    pub fn gen_watermap(&mut self) {
+        let size = self.size;
+
+        self.watermap = vec![0.; size * size];
+
+        for i in 0..size*size {
+            if let Some(Water::Lake) = self.waters.get(&i) {
+                self.watermap[i] = (self.cloudmap[i] + 1.) / 2.;
+            } else if let Some(Water::SaltLake) = self.waters.get(&i) {
+                self.watermap[i] = self.cloudmap[i] / 2.;
+            } else if self.heightmap[i] > 0. {
+                let best_river = self.neighbs[i]
+                    .iter()
+                    .map(|&(ii, _)| {
+                        if let Some(Water::Lake) = self.waters.get(&ii) {
+                            return 1.;
+                        } else {
+                            return self.rivermap[ii];
+                        }
+                    })
+                    .max_by(|&a, &b| a.partial_cmp(&b).unwrap())
+                    .unwrap();
                 
-                for (i, &path) in paths.iter().enumerate() {
-                    if i + 1 < paths.len() {
-                        river_drainage[path] = paths[i + 1];
+                self.watermap[i] = (self.cloudmap[i] + best_river) / (1. + best_river);
+            }
+        }
+    }
+    
+    // Sources hold their level and the sea takes what reaches it.
+    pub fn gen_groundwater(&mut self, groundwater: &Groundwater) {
+        let size = self.size;
+        let mut source = vec![0.; size * size];
+        let mut permeability = vec![0.; size * size];
+
+        for i in 0..size * size {
+            match self.waters.get(&i) {
+                Some(Water::Sea) => continue,
+                Some(Water::Lake) => source[i] = 1.,
+                Some(Water::SaltLake) => {}
+                None => {
+                    let rain = ((self.cloudmap[i] - groundwater.rain) / (1. - groundwater.rain)).max(0.);
+
+                    source[i] = self.rivermap[i].max(rain);
+                }
+            }
+
+            let slope = self.neighbs[i].iter().map(|&(ii, c)| (self.heightmap[ii] - self.heightmap[i]).abs() / c).fold(0., f64::max);
+
+            permeability[i] = 1. / (1. + groundwater.slope * slope);
+        }
+
+        self.groundwatermap = source.clone();
+
+        for _ in 0..groundwater.steps {
+            let level = &self.groundwatermap;
+            let next: Vec<f64> = (0..size * size)
+                .map(|i| match self.waters.get(&i) {
+                    Some(Water::Sea) => 0.,
+                    _ => {
+                        let mean = self.neighbs[i].iter().map(|&(ii, _)| level[ii]).sum::<f64>() / self.neighbs[i].len().max(1) as f64;
+                        let seeped = level[i] + groundwater.flow * permeability[i] * (mean - level[i]);
+
+                        (seeped * (1. - groundwater.loss)).max(source[i])
+                    }
+                })
+                .collect();
+
+            self.groundwatermap = next;
+        }
+    }
+
+    pub fn groundwater_at(&self, i: usize) -> f64 {
+        self.groundwatermap.get(i).copied().unwrap_or(0.)
+    }
+
+    pub fn gen_biomes(&mut self) {
+        let size = self.size;
+
+        self.biomes = (0..size * size)
+            .map(|i| match self.waters.get(&i) {
+                Some(Water::Sea) => Biome::Ocean,
+                Some(Water::Lake) | Some(Water::SaltLake) => Biome::Lake,
+                None if self.frozen(i) => Biome::Ice,
+                None => Biome::classify(self.tempmap[i], self.watermap[i], self.heightmap[i]),
+            })
+            .collect();
+    }
+
+    pub fn gen_vegetmap(&mut self) {
+        let size = self.size;
+
+        self.vegetmap = vec![0.; size * size];
+
+        for i in 0..size * size {
+            if self.heightmap[i] > 0. && !self.frozen(i) {
+                let water = self.watermap[i].max(self.groundwater_at(i));
+
+                self.vegetmap[i] = to_f32(calc_veget(real(self.tempmap[i] as f32), real(water as f32))) as f64;
+            }
+        }
+    }
+
+    // Silt from the largest river on or beside a pixel and the humus of its
+    // vegetation make the soil, which steep ground and a climate far from
+    // mild both thin out.
+    pub fn gen_soilmap(&mut self) {
+        let size = self.size;
+
+        self.soilmap = vec![0.; size * size];
+
+        for i in 0..size * size {
+            if self.heightmap[i] <= 0. || self.frozen(i) {
+                continue;
+            }
+
+            let river = self.neighbs[i].iter().map(|&(ii, _)| self.rivermap[ii]).fold(self.rivermap[i], f64::max);
+            let slope = self.neighbs[i].iter().map(|&(ii, c)| (self.heightmap[ii] - self.heightmap[i]).abs() / c).fold(0., f64::max);
+            let flat = 1. / (1. + 50. * slope);
+            let mild = clamp(1. - ((self.tempmap[i] - 0.6) / 0.6).powi(2), 0., 1.);
+
+            self.soilmap[i] = clamp((0.2 + 0.4 * self.vegetmap[i] + 0.4 * river.sqrt()) * flat * mild, 0., 1.);
+        }
+    }
+
+    pub fn gen_settlements(&mut self) {
+        let size = self.size;
+
+        self.settlements = vec![false; size * size];
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut ys = Vec::new();
+        ys.push(0);
+
+        while *ys.last().unwrap() < size {
+            ys.push(ys.last().unwrap() + rng.gen_range(SETTLEMENT_SPACING, 20))
+        }
+
+        ys.pop();
+
+        'outer: for y in ys.iter() {
+            for x in 0..size {
+                let i = y * size + x;
+
+                if self.waters.get(&i).is_none() && !self.frozen(i) {
+                    if rng.gen::<bool>() && rng.gen::<bool>() {
+                        self.settlements[i] = true;
+
+                        continue 'outer;
                     }
                 }
-            } else {
+            }
+        }
+    }
+
+    // Land steps only, from every pixel in `from` at once.
+    fn land_distances(&self, from: &Vec<usize>) -> Vec<usize> {
+        let mut dist = vec![usize::MAX; self.size * self.size];
+        let mut queue = VecDeque::new();
+
+        for &i in from.iter() {
+            dist[i] = 0;
+            queue.push_back(i);
+        }
+
+        while let Some(i) = queue.pop_front() {
+            for &(ii, _) in self.neighbs[i].iter() {
+                if dist[ii] == usize::MAX && self.waters.get(&ii).is_none() {
+                    dist[ii] = dist[i] + 1;
+                    queue.push_back(ii);
+                }
+            }
+        }
+
+        dist
+    }
+
+    // Province ids start at 1, 0 being water. Land cut off from every seed
+    // becomes a province of its own, so each one is contiguous, and
+    // `province_adjacency[id]` lists the provinces bordering `id`.
+    pub fn gen_provinces(&mut self, provinces: &Provinces) {
+        let size = self.size;
+        let land: Vec<usize> = (0..size * size).filter(|i| self.waters.get(i).is_none()).collect();
+        let mut seeds: Vec<usize> = land.iter().cloned().filter(|&i| self.settlements[i]).take(provinces.count).collect();
+
+        while seeds.len() < provinces.count.min(land.len()) {
+            let dist = self.land_distances(&seeds);
+            let far = land.iter().cloned().max_by_key(|&i| (dist[i], Reverse(i))).unwrap();
+
+            if dist[far] == 0 {
                 break;
             }
+
+            seeds.push(far);
         }
-        
-        self.rivermap = vec![0.; size * size];
 
-        for i in 0..size * size {
-            if river_drainage[i] > 0 {
-                let mut ii = i;
+        self.provincemap = vec![0; size * size];
+
+        let mut cost = vec![u32::MAX; size * size];
+        let mut heap = BinaryHeap::new();
+        let mut next = 1;
+
+        for &seed in seeds.iter() {
+            cost[seed] = 0;
+            self.provincemap[seed] = next;
+            heap.push(Reverse((0u32, seed, next)));
+            next += 1;
+        }
+
+        for &i in land.iter() {
+            if self.provincemap[i] == 0 && heap.is_empty() {
+                cost[i] = 0;
+                self.provincemap[i] = next;
+                heap.push(Reverse((0u32, i, next)));
+                next += 1;
+            }
+
+            while let Some(Reverse((d, i, province))) = heap.pop() {
+                if d > cost[i] {
+                    continue;
+                }
+
+                for &(ii, c) in self.neighbs[i].iter() {
+                    if self.waters.get(&ii).is_some() {
+                        continue;
+                    }
 
-                loop {
-                    self.rivermap[ii] += self.cloudmap[i];
+                    let step = c * (1. + provinces.river * self.rivermap[ii] + provinces.slope * (self.heightmap[ii] - self.heightmap[i]).abs() * size as f64);
+                    let d = d + (step * 100.) as u32;
 
-                    if river_drainage[ii] > 0 {
-                        ii = river_drainage[ii];
-                    } else {
-                        break;
+                    if d < cost[ii] {
+                        cost[ii] = d;
+                        self.provincemap[ii] = province;
+                        heap.push(Reverse((d, ii, province)));
                     }
                 }
             }
         }
 
-        for river in self.rivermap.iter_mut() {
-            *river = river.sqrt();
+        self.province_adjacency = vec![Vec::new(); next as usize];
+
+        for &i in land.iter() {
+            let province = self.provincemap[i];
+
+            for &(ii, _) in self.neighbs[i].iter() {
+                let other = self.provincemap[ii];
+
+                if other != 0 && other != province && !self.province_adjacency[province as usize].contains(&other) {
+                    self.province_adjacency[province as usize].push(other);
+                }
+            }
         }
 
-        let mx = self.rivermap
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap()
-            .clone();
-        
-        for river in self.rivermap.iter_mut() {
-            *river /= mx;
+        for adjacent in self.province_adjacency.iter_mut() {
+            adjacent.sort();
         }
     }
 
-    pub fn gen_watermap(&mut self) {
+    // Every sea and lake pixel is navigable, and so is a river pixel carrying
+    // at least `flow` where no step to a neighbouring river or water pixel
+    // drops more than `slope`; steeper steps are rapids.
+    pub fn gen_navigation(&mut self, flow: f64, slope: f64) {
         let size = self.size;
 
-        self.watermap = vec![0.; size * size];
+        self.navigable = (0..size * size)
+            .map(|i| {
+                if matches!(self.waters.get(&i), Some(Water::Sea) | Some(Water::Lake)) {
+                    return true;
+                }
+                if self.waters.contains_key(&i) || self.rivermap[i] < flow {
+                    return false;
+                }
 
-        for i in 0..size*size {
-            if let Some(Water::Lake) = self.waters.get(&i) {
-                self.watermap[i] = (self.cloudmap[i] + 1.) / 2.;
-            } else if self.heightmap[i] > 0. {
-                let best_river = self.neighbs[i]
+                !self.neighbs[i].iter().any(|&(ii, _)| {
+                    let joins = self.waters.contains_key(&ii) || self.rivermap[ii] >= flow;
+
+                    joins && (self.heightmap[ii].max(0.) - self.heightmap[i]).abs() > slope
+                })
+            })
+            .collect();
+    }
+
+    // Cheapest way by water from `from` to `to`, either of which may be a
+    // land pixel beside navigable water such as a port. Going upriver costs
+    // twice what sailing does.
+    pub fn route_water(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let size = self.size;
+        let navigable = |i: usize| self.navigable.get(i).cloned().unwrap_or(false);
+        let (path, _) = astar(&from,
+            |&i| {
+                self.neighbs[i]
                     .iter()
-                    .map(|&(ii, _)| {
-                        if let Some(Water::Lake) = self.waters.get(&ii) {
-                            return 1.;
-                        } else {
-                            return self.rivermap[ii];
-                        }
+                    .filter(|&&(ii, _)| navigable(ii) || ii == to)
+                    .map(|&(ii, c)| {
+                        let upriver = self.waters.get(&ii).is_none() && self.heightmap[ii] > self.heightmap[i].max(0.);
+
+                        (ii, (100. * c * if upriver { 2. } else { 1. }) as usize)
                     })
-                    .max_by(|&a, &b| a.partial_cmp(&b).unwrap())
-                    .unwrap();
-                
-                self.watermap[i] = (self.cloudmap[i] + best_river) / (1. + best_river);
-            }
-        }
+                    .collect::<Vec<(usize, usize)>>()
+            },
+            |&i| {
+                let (dx, dy) = offset_between(i, to, size, self.wrap);
+
+                (100. * dx.abs().max(dy.abs())) as usize
+            },
+            |&i| i == to
+        )?;
+
+        Some(path)
     }
-    
-    pub fn gen_vegetmap(&mut self) {
-        let size = self.size;
 
-        self.vegetmap = vec![0.; size * size];
+    // Connected sea pixels, largest first, leaving out any under `min`.
+    pub fn sea_bodies(&self, min: usize) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.size * self.size];
+        let mut bodies = Vec::new();
 
-        for i in 0..size * size {
-            if self.heightmap[i] > 0. {
-                let water = clamp(1.5 * self.watermap[i] - self.tempmap[i] / 2., 0., 1.);
+        for i in 0..self.size * self.size {
+            if seen[i] || !matches!(self.waters.get(&i), Some(Water::Sea)) {
+                continue;
+            }
 
-                self.vegetmap[i] = (water * (-(self.tempmap[i] - 0.75).powi(2) + 1.)).sqrt();
+            let mut body = Vec::new();
+            let mut stack = vec![i];
+
+            seen[i] = true;
+
+            while let Some(ii) = stack.pop() {
+                body.push(ii);
+
+                for &(iii, _) in self.neighbs[ii].iter() {
+                    if !seen[iii] && matches!(self.waters.get(&iii), Some(Water::Sea)) {
+                        seen[iii] = true;
+                        stack.push(iii);
+                    }
+                }
+            }
+
+            if body.len() >= min {
+                bodies.push(body);
             }
         }
+
+        bodies.sort_by_key(|body| Reverse(body.len()));
+
+        bodies
     }
 
-    pub fn gen_settlements(&mut self) {
+    // Each river reaching water with at least `flow`, as the land pixels of
+    // it and its tributaries carrying that much, largest first.
+    pub fn river_systems(&self, flow: f64) -> Vec<Vec<usize>> {
         let size = self.size;
+        let mut above = vec![Vec::new(); size * size];
+        let mut systems = Vec::new();
 
-        self.settlements = vec![false; size * size];
+        for i in 0..size * size {
+            if self.drainage[i] != NO_OUTLET {
+                above[self.drainage[i]].push(i);
+            }
+        }
 
-        let mut rng = rand::thread_rng();
-        let mut ys = Vec::new();
-        ys.push(0);
+        for i in 0..size * size {
+            let mouth = self.waters.get(&i).is_none()
+                && self.rivermap[i] >= flow
+                && (self.drainage[i] == NO_OUTLET || self.waters.contains_key(&self.drainage[i]));
 
-        while *ys.last().unwrap() < size {
-            ys.push(ys.last().unwrap() + rng.gen_range(5, 20))
+            if !mouth {
+                continue;
+            }
+
+            let mut system = Vec::new();
+            let mut stack = vec![i];
+
+            while let Some(ii) = stack.pop() {
+                system.push(ii);
+                stack.extend(above[ii].iter().filter(|&&iii| self.rivermap[iii] >= flow && self.waters.get(&iii).is_none()));
+            }
+
+            systems.push((self.rivermap[i], system));
         }
 
-        ys.pop();
+        systems.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap());
 
-        'outer: for y in ys.iter() {
-            for x in 0..size {
-                let i = y * size + x;
+        systems.into_iter().map(|(_, system)| system).collect()
+    }
 
-                if self.waters.get(&i).is_none() {
-                    if rng.gen::<bool>() && rng.gen::<bool>() {
-                        self.settlements[i] = true;
+    pub fn export_provinces<T: Into<PathBuf>>(&self, path: T) {
+        let mut img = RgbImage::new(self.size as u32, self.size as u32);
 
-                        continue 'outer;
-                    }
-                }
-            }
+        for (i, &province) in self.provincemap.iter().enumerate() {
+            let color = match province {
+                0 => Rgb([32, 64, 128]),
+                _ if self.neighbs[i].iter().any(|&(ii, _)| self.provincemap[ii] != province && self.provincemap[ii] != 0) => Rgb([0, 0, 0]),
+                _ => owner_color(province as usize),
+            };
+
+            img.put_pixel((i % self.size) as u32, (i / self.size) as u32, color);
         }
+
+        img.save(path.into()).unwrap();
     }
 
     pub fn export<T: Into<PathBuf>>(&self, map: &Vec<f64>, path: T) {
@@ -582,6 +2347,74 @@ impl ProvBuilder {
         img.save(path.into()).unwrap();
     }
 
+    pub fn export_colormap<T: Into<PathBuf>>(&self, map: &Vec<f64>, path: T, min: f64, max: f64, colormap: Colormap) {
+        self.export_styled(map, path, min, max, &Style { colormap, title: None, bar: false });
+    }
+
+    pub fn export_styled<T: Into<PathBuf>>(&self, map: &Vec<f64>, path: T, min: f64, max: f64, style: &Style) {
+        let mut i = 0;
+        let mut img = RgbImage::new(self.size as u32, self.size as u32);
+
+        for y in 0..self.size {
+            for x in 0..self.size {
+                img.put_pixel(x as u32, y as u32, style.colormap.color((map[i] - min) / (max - min)));
+
+                i += 1;
+            }
+        }
+
+        decorate(img, min, max, style).save(path.into()).unwrap();
+    }
+
+    // Every per-pixel map, biomes and provinces as their indices. The passes
+    // that are switched off leave theirs empty and are skipped.
+    pub fn layers(&self) -> Vec<(&'static str, Cow<[f64]>)> {
+        let biomes: Vec<f64> = self.biomes.iter().map(|&biome| biome as u8 as f64).collect();
+        let provinces: Vec<f64> = self.provincemap.iter().map(|&province| province as f64).collect();
+
+        vec![
+            ("heightmap", Cow::Borrowed(&self.heightmap[..])),
+            ("insolation", Cow::Borrowed(&self.insolation[..])),
+            ("latitude", Cow::Borrowed(&self.latitude[..])),
+            ("cloudmap", Cow::Borrowed(&self.cloudmap[..])),
+            ("currentmap", Cow::Borrowed(&self.currentmap[..])),
+            ("tempmap", Cow::Borrowed(&self.tempmap[..])),
+            ("rivermap", Cow::Borrowed(&self.rivermap[..])),
+            ("watermap", Cow::Borrowed(&self.watermap[..])),
+            ("groundwatermap", Cow::Borrowed(&self.groundwatermap[..])),
+            ("vegetmap", Cow::Borrowed(&self.vegetmap[..])),
+            ("soilmap", Cow::Borrowed(&self.soilmap[..])),
+            ("biomes", Cow::Owned(biomes)),
+            ("provincemap", Cow::Owned(provinces)),
+        ]
+        .into_iter()
+        .filter(|(_, map)| !map.is_empty())
+        .collect()
+    }
+
+    pub fn export_npy<P: AsRef<Path>>(&self, map: &Vec<f64>, path: P) -> io::Result<()> {
+        write_npy(map, &[self.size, self.size], path)
+    }
+
+    pub fn export_all_npy<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let settlements: Vec<f32> = self.settlements.iter().map(|&s| if s { 1. } else { 0. }).collect();
+
+        write_layers(self.layers(), self.size, &dir)?;
+        write_layers(vec![("settlements", settlements)], self.size, &dir)
+    }
+
+    pub fn render_rgba(&self, map: &Vec<f64>, min: f64, max: f64, colormap: Colormap) -> Vec<u8> {
+        let mut out = Vec::with_capacity(map.len() * 4);
+
+        for value in map.iter() {
+            let color = colormap.color((value - min) / (max - min));
+
+            out.extend_from_slice(&[color.0[0], color.0[1], color.0[2], 255]);
+        }
+
+        out
+    }
+
     pub fn export_settlements<T: Into<PathBuf>>(&self, path: T) {
         let mut i = 0;
         let mut img = RgbImage::new(self.size as u32, self.size as u32);
@@ -601,6 +2434,88 @@ impl ProvBuilder {
         img.save(path.into()).unwrap();
     }
 
+    pub fn export_biomes<T: Into<PathBuf>>(&self, path: T) {
+        let mut img = RgbImage::new(self.size as u32, self.size as u32);
+
+        for (i, biome) in self.biomes.iter().enumerate() {
+            img.put_pixel((i % self.size) as u32, (i / self.size) as u32, biome.color());
+        }
+
+        img.save(path.into()).unwrap();
+    }
+
+    // Channels as blue lines as wide as the river over the terrain, each
+    // drawn from its pixel to the segment or water it flows into.
+    pub fn export_rivers<T: Into<PathBuf>>(&self, scale: usize, path: T) {
+        let size = self.size;
+        let scale = scale.max(1);
+        let mut img = RgbImage::new((size * scale) as u32, (size * scale) as u32);
+
+        for y in 0..size * scale {
+            for x in 0..size * scale {
+                let i = (y / scale) * size + x / scale;
+                let color = match self.waters.get(&i) {
+                    Some(_) => Rgb([32, 64, 128]),
+                    None => Colormap::Terrain.color(self.heightmap[i]),
+                };
+
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+
+        for segment in self.segments.iter() {
+            let (target, offset) = match (segment.down, segment.mouth) {
+                (Some(down), _) => (self.segments[down].pixel, self.segments[down].offset),
+                (None, Some((_, water))) => (water, (0., 0.)),
+                (None, None) => continue,
+            };
+            let (x, y) = ((segment.pixel % size) as f64 + 0.5, (segment.pixel / size) as f64 + 0.5);
+            let (dx, dy) = offset_between(segment.pixel, target, size, self.wrap);
+            let from = ((x + segment.offset.0) * scale as f64, (y + segment.offset.1) * scale as f64);
+            let to = ((x + dx + offset.0) * scale as f64, (y + dy + offset.1) * scale as f64);
+            let radius = (segment.width * scale as f64 / 2.).max(0.5);
+            let steps = ((to.0 - from.0).abs().max((to.1 - from.1).abs()) as usize).max(1);
+
+            for step in 0..=steps {
+                let t = step as f64 / steps as f64;
+                let (cx, cy) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+                let r = radius.ceil() as isize;
+
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (x, y) = (cx as isize + dx, cy as isize + dy);
+
+                        if x < 0 || y < 0 || x >= (size * scale) as isize || y >= (size * scale) as isize {
+                            continue;
+                        }
+                        if ((dx * dx + dy * dy) as f64).sqrt() <= radius {
+                            img.put_pixel(x as u32, y as u32, Rgb([40, 90, 200]));
+                        }
+                    }
+                }
+            }
+        }
+
+        img.save(path.into()).unwrap();
+    }
+
+    pub fn export_snow<T: Into<PathBuf>>(&self, path: T) {
+        let mut img = RgbImage::new(self.size as u32, self.size as u32);
+
+        for (i, ice) in self.snowmap.iter().enumerate() {
+            let color = match ice {
+                Some(Ice::Snow) => Rgb([255, 255, 255]),
+                Some(Ice::SeaIce) => Rgb([160, 200, 230]),
+                Some(Ice::Glacier) => Rgb([90, 160, 220]),
+                None => Rgb([0, 0, 0]),
+            };
+
+            img.put_pixel((i % self.size) as u32, (i / self.size) as u32, color);
+        }
+
+        img.save(path.into()).unwrap();
+    }
+
     pub fn export_waters<T: Into<PathBuf>>(&self, path: T) {
         let mut i = 0;
         let mut img = RgbImage::new(self.size as u32, self.size as u32);
@@ -610,6 +2525,7 @@ impl ProvBuilder {
                 if let Some(water) = self.waters.get(&i) {
                     match water {
                         Water::Lake => img.put_pixel(x as u32, y as u32, Rgb([128, 128, 128])),
+                        Water::SaltLake => img.put_pixel(x as u32, y as u32, Rgb([200, 190, 150])),
                         Water::Sea => img.put_pixel(x as u32, y as u32, Rgb([255, 255, 255])),
                     }
                 } else {
@@ -622,4 +2538,144 @@ impl ProvBuilder {
 
         img.save(path.into()).unwrap();
     }
-}
\ No newline at end of file
+
+    pub fn export_political<T: Into<PathBuf>>(&self, owners: &Vec<Option<usize>>, borders: Option<&BorderStyle>, path: T) {
+        let snapped;
+        let owners = match borders {
+            Some(style) => {
+                let water = (0..self.size * self.size).map(|i| self.waters.contains_key(&i)).collect();
+
+                snapped = snap_borders(self.size, self.wrap, &self.heightmap, &self.rivermap, &water, owners, style);
+                &snapped
+            }
+            None => owners,
+        };
+        let mut i = 0;
+        let mut img = RgbImage::new(self.size as u32, self.size as u32);
+
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let color = match owners[i] {
+                    Some(_) if self.neighbs[i].iter().any(|&(ii, _)| owners[ii] != owners[i]) => Rgb([0, 0, 0]),
+                    Some(owner) => owner_color(owner),
+                    None => match self.waters.get(&i) {
+                        Some(_) => Rgb([32, 64, 128]),
+                        None => Rgb([128, 128, 128]),
+                    },
+                };
+
+                img.put_pixel(x as u32, y as u32, color);
+
+                i += 1;
+            }
+        }
+
+        for (i, &owner) in owners.iter().enumerate() {
+            if owner != Some(i) || !self.settlements[i] {
+                continue;
+            }
+
+            img.put_pixel((i % self.size) as u32, (i / self.size) as u32, Rgb([255, 255, 255]));
+
+            for &(ii, _) in self.neighbs[i].iter() {
+                img.put_pixel((ii % self.size) as u32, (ii / self.size) as u32, Rgb([255, 255, 255]));
+            }
+        }
+
+        img.save(path.into()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(seed: u64) -> ProvBuilder {
+        ProvBuilder::new(48, 0.1, 0.6, 2., 0., 1., 0.1, 0.9, -20., -10., seed, WrapMode::None, NoiseKind::Perlin)
+    }
+
+    #[test]
+    fn valid_maps_hold_their_invariants() {
+        let mut placed = 0;
+
+        for seed in 0..4 {
+            let mut map = builder(seed);
+            let used = map.generate_valid(seed, 4).expect("no valid map in four seeds");
+
+            assert!(used >= seed && used < seed + 4);
+
+            let size = map.size;
+
+            for i in 0..size * size {
+                if map.drainage[i] == NO_OUTLET || map.waters.contains_key(&i) {
+                    continue;
+                }
+
+                let mut ii = i;
+                let mut steps = 0;
+
+                while map.drainage[ii] != NO_OUTLET && steps <= size * size {
+                    ii = map.drainage[ii];
+                    steps += 1;
+                }
+
+                assert!(map.waters.contains_key(&ii), "river from {} ends on land", i);
+            }
+
+            let settlements: Vec<usize> = (0..size * size).filter(|&i| map.settlements[i]).collect();
+
+            placed += settlements.len();
+
+            for (n, &i) in settlements.iter().enumerate() {
+                assert!(!map.waters.contains_key(&i), "settlement at {} is on water", i);
+
+                for &ii in settlements[n + 1..].iter() {
+                    let (dx, dy) = offset_between(i, ii, size, map.wrap);
+
+                    assert!(dx.abs().max(dy.abs()) as usize >= SETTLEMENT_SPACING, "settlements at {} and {} are too close", i, ii);
+                }
+            }
+        }
+
+        assert!(placed > 0, "no settlements to check");
+    }
+
+    #[test]
+    fn drainage_points_at_pixels_or_nowhere() {
+        for seed in 0..4 {
+            let mut map = builder(seed);
+
+            map.generate();
+
+            let len = map.size * map.size;
+
+            assert_eq!(map.drainage.len(), len);
+            assert!(map.drainage.iter().all(|&next| next == NO_OUTLET || next < len));
+
+            for i in 0..len {
+                let mut ii = i;
+                let mut steps = 0;
+
+                while map.drainage[ii] != NO_OUTLET && steps <= len {
+                    ii = map.drainage[ii];
+                    steps += 1;
+                }
+
+                assert!(steps <= len, "drainage from {} loops", i);
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_wrap_past_the_last() {
+        let mut map = builder(0);
+
+        match map.generate_valid(u64::MAX, 2) {
+            Ok(used) => {
+                assert!(used == u64::MAX || used == 0);
+                assert!(map.invariants().is_empty());
+            }
+            Err(violations) => assert!(!violations.is_empty()),
+        }
+    }
+}
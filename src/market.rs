@@ -0,0 +1,270 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Location;
+use crate::Skill;
+use crate::Building;
+use crate::Settlement;
+use crate::PixelGrid;
+use crate::guild::Guild;
+use crate::guild::Stock;
+use crate::path::LAND;
+use crate::path::WATER;
+use crate::trade::Trade;
+use crate::symbol::Symbol;
+
+const SCALE: f32 = 1000.;
+const UNREACHED: u32 = u32::MAX;
+
+#[derive(Clone, Deserialize)]
+pub struct MarketDefines {
+    pub period: usize,
+    pub hubs: usize,
+    pub reference: f32,
+    pub local: f32,
+    pub arbitrage: f32,
+    pub freight: f32,
+    pub haul: f32,
+    pub stockpile: f32,
+}
+
+pub struct Price { pub item: Vec<f32> }
+
+// What a settlement's guilds lack of their inputs, per good.
+pub struct Demand { pub item: Vec<f32> }
+
+pub struct Market {
+    pub hubs: Vec<Entity>,
+    pub zone: HashMap<Entity, usize>,
+    pub adjacent: Vec<BTreeSet<usize>>,
+    pub prices: Vec<Vec<f32>>,
+    period: usize,
+    tick: usize,
+}
+
+impl Market {
+    pub fn new(period: usize) -> Self {
+        Market {
+            hubs: Vec::new(),
+            zone: HashMap::new(),
+            adjacent: Vec::new(),
+            prices: Vec::new(),
+            period,
+            tick: 0,
+        }
+    }
+}
+
+// Travel by land or, where both ends are on the water network, by boat,
+// whichever is cheaper.
+fn travel(world: &World, road: usize, from: Entity, to: Entity) -> Option<f32> {
+    match ((LAND.step)(world, road, from, to), (WATER.step)(world, road, from, to)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// The `hubs` most populous settlements each claim everything closer to them
+// in travel time than to any other hub; zones that touch are adjacent.
+fn build_zones(world: &World, grid: &PixelGrid, market: &mut Market, road: usize, hubs: usize) {
+    let mut towns: Vec<(f32, usize, Entity)> = <(Read<Index>, TryRead<Pop>)>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(world)
+        .map(|(settlement, (index, pop))| (pop.map_or(0., |pop| pop.item), index.item, settlement))
+        .collect();
+
+    towns.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then(a.1.cmp(&b.1)));
+    towns.truncate(hubs.max(1));
+
+    let mut dist = vec![UNREACHED; grid.item.len()];
+    let mut zone = vec![usize::MAX; grid.item.len()];
+    let mut heap = BinaryHeap::new();
+
+    for (z, &(_, index, _)) in towns.iter().enumerate() {
+        dist[index] = 0;
+        zone[index] = z;
+        heap.push(Reverse((0, index)));
+    }
+
+    while let Some(Reverse((d, index))) = heap.pop() {
+        if d > dist[index] {
+            continue;
+        }
+
+        let pixel = grid.get(index);
+        let neighb = grid.neighb(world, index);
+
+        for next in neighb.iter() {
+            let step = match travel(world, road, pixel, grid.get(next)) {
+                Some(step) => (SCALE * step) as u32,
+                None => continue,
+            };
+            let d = d.saturating_add(step);
+
+            if d < dist[next] {
+                dist[next] = d;
+                zone[next] = zone[index];
+                heap.push(Reverse((d, next)));
+            }
+        }
+    }
+
+    let mut adjacent = vec![BTreeSet::new(); towns.len()];
+
+    for (index, &z) in zone.iter().enumerate() {
+        if z == usize::MAX {
+            continue;
+        }
+
+        for next in grid.neighb(world, index).iter() {
+            if zone[next] != usize::MAX && zone[next] != z {
+                adjacent[z].insert(zone[next]);
+            }
+        }
+    }
+
+    market.zone = <Read<Index>>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(world)
+        .filter(|(_, index)| zone[index.item] != usize::MAX)
+        .map(|(settlement, index)| (settlement, zone[index.item]))
+        .collect();
+    market.prices.resize(towns.len(), Vec::new());
+    market.prices.truncate(towns.len());
+    market.hubs = towns.into_iter().map(|(_, _, hub)| hub).collect();
+    market.adjacent = adjacent;
+}
+
+// Each settlement's own price of a good rises as its stock runs short of
+// `reference`. Within a zone prices are pulled quickly toward the zone's
+// price, which follows the average of its members; zone prices drift toward
+// those of adjacent zones at the much slower `arbitrage` rate. Zones are
+// rebuilt every `period` ticks, as settlements grow and roads are laid.
+// Guilds want `stockpile` ticks of their inputs on hand, and whatever they
+// lack raises the price their settlement pays above its zone's.
+pub fn update_markets(world: &mut World, resources: &mut Resources) {
+    let mut market = match resources.get_mut::<Market>() {
+        Some(market) => market,
+        None => return,
+    };
+    let market = &mut *market;
+    let defines = resources.get::<Defines>().unwrap();
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let m = &defines.market;
+    let goods = defines.good_i.len();
+    let trade = Trade::new(&defines);
+    let base = trade.value.clone();
+
+    // Goods dense in value and slow to spoil are worth hauling between zones,
+    // so their prices level out; bulky or perishable ones stay local.
+    let reach: Vec<f32> = (0..goods)
+        .map(|g| trade.density(g) / (trade.density(g) + m.freight) * trade.kept(g, m.haul))
+        .collect();
+
+    if market.tick % market.period.max(1) == 0 {
+        build_zones(world, &grid, market, defines.building_i[&Symbol::new("Road")], m.hubs);
+    }
+
+    market.tick += 1;
+
+    let zones = market.hubs.len();
+    let mut sums = vec![vec![0.; goods]; zones];
+    let mut counts = vec![0; zones];
+    let mut local = Vec::new();
+    let mut want: HashMap<Entity, Vec<f32>> = HashMap::new();
+
+    for (location, skill) in <(Read<Location>, Read<Skill>)>::query().filter(tag::<Guild>()).iter(&*world) {
+        let building = match world.get_component::<Building>(location.item) {
+            Some(building) => building,
+            None => continue,
+        };
+        let want = want.entry(location.item).or_insert_with(|| vec![0.; goods]);
+
+        for (r, recipe) in defines.recipes.iter().enumerate() {
+            let batches = skill.item[r] * building.item[defines.building_i[&recipe.building]] * m.stockpile;
+
+            for (good, &amount) in recipe.inputs.iter() {
+                want[defines.good_i[good]] += batches * amount;
+            }
+        }
+    }
+
+    for (settlement, stock) in <Read<Stock>>::query().filter(tag::<Settlement>()).iter_entities(&*world) {
+        let z = match market.zone.get(&settlement) {
+            Some(&z) => z,
+            None => continue,
+        };
+        let target: Vec<f32> = (0..goods).map(|g| base[g] * 2. * m.reference / (stock.item[g].max(0.) + m.reference)).collect();
+
+        for g in 0..goods {
+            sums[z][g] += target[g];
+        }
+
+        let need: Vec<f32> = match want.get(&settlement) {
+            Some(want) => (0..goods).map(|g| (want[g] - stock.item[g].max(0.)).max(0.)).collect(),
+            None => vec![0.; goods],
+        };
+
+        counts[z] += 1;
+        local.push((settlement, z, need));
+    }
+
+    for z in 0..zones {
+        if market.prices[z].len() != goods {
+            market.prices[z] = base.clone();
+        }
+
+        for g in 0..goods {
+            if counts[z] > 0 {
+                let mean = sums[z][g] / counts[z] as f32;
+
+                market.prices[z][g] += m.local * (mean - market.prices[z][g]);
+            }
+        }
+    }
+
+    let before = market.prices.clone();
+
+    for z in 0..zones {
+        let adjacent = &market.adjacent[z];
+
+        if adjacent.is_empty() {
+            continue;
+        }
+
+        for g in 0..goods {
+            let mean = adjacent.iter().map(|&a| before[a][g]).sum::<f32>() / adjacent.len() as f32;
+
+            market.prices[z][g] += m.arbitrage * reach[g] * (mean - before[z][g]);
+        }
+    }
+
+    for (settlement, z, need) in local.into_iter() {
+        let zone_price: Vec<f32> = (0..goods).map(|g| market.prices[z][g] * (1. + need[g] / m.reference)).collect();
+
+        match world.get_component_mut::<Price>(settlement) {
+            Some(mut price) => {
+                for g in 0..goods {
+                    price.item[g] += m.local * (zone_price[g] - price.item[g]);
+                }
+            }
+            None => {
+                world.add_component(settlement, Price { item: zone_price }).unwrap();
+            }
+        }
+
+        match world.get_component_mut::<Demand>(settlement) {
+            Some(mut demand) => demand.item = need,
+            None => world.add_component(settlement, Demand { item: need }).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::mem::size_of;
+use std::collections::BTreeMap;
+
+use crate::*;
+use crate::guild::Guild;
+use crate::guild::Stock;
+use crate::caravan::Caravan;
+use crate::caravan::Ship;
+use crate::caravan::Route;
+use crate::census::Census;
+use crate::census::SettlementCensus;
+use crate::timeline::Timeline;
+use crate::minimap::Minimap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Profile {
+    Full,
+    Lean,
+}
+
+// Under Lean pixels carry no neighbour tables and history buffers start at
+// `history` of their capacity. Past a `budget` in MiB, history buffers are
+// halved at each measurement until the total fits again.
+#[derive(Clone, Deserialize)]
+pub struct MemoryDefines {
+    pub profile: Profile,
+    pub history: f32,
+    pub budget: Option<usize>,
+}
+
+impl MemoryDefines {
+    pub fn capacity(&self, capacity: usize) -> usize {
+        match self.profile {
+            Profile::Full => capacity,
+            Profile::Lean => ((capacity as f32 * self.history).ceil() as usize).max(1),
+        }
+    }
+}
+
+pub struct MemoryStats {
+    pub entities: BTreeMap<&'static str, usize>,
+    pub component_bytes: usize,
+    pub heap_bytes: usize,
+    pub census_bytes: usize,
+    pub timeline_bytes: usize,
+    pub minimap_bytes: usize,
+    pub events: usize,
+    pub period: usize,
+    pub profile: Profile,
+    pub budget: Option<usize>,
+    pub shed: usize,
+    tick: usize,
+}
+
+impl MemoryStats {
+    pub fn new(period: usize, defines: &MemoryDefines) -> Self {
+        MemoryStats {
+            entities: BTreeMap::new(),
+            component_bytes: 0,
+            heap_bytes: 0,
+            census_bytes: 0,
+            timeline_bytes: 0,
+            minimap_bytes: 0,
+            events: 0,
+            period,
+            profile: defines.profile,
+            budget: defines.budget.map(|budget| budget << 20),
+            shed: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.component_bytes + self.heap_bytes + self.census_bytes + self.timeline_bytes + self.minimap_bytes
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.budget.map_or(false, |budget| self.total() > budget)
+    }
+
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+
+        for (name, count) in self.entities.iter() {
+            out += &format!("{:<12}{:>12}\n", name, count);
+        }
+
+        out += &format!("{:<12}{:>12} KiB\n", "components", self.component_bytes / 1024);
+        out += &format!("{:<12}{:>12} KiB\n", "heap", self.heap_bytes / 1024);
+        out += &format!("{:<12}{:>12} KiB\n", "census", self.census_bytes / 1024);
+        out += &format!("{:<12}{:>12} KiB\n", "timeline", self.timeline_bytes / 1024);
+        out += &format!("{:<12}{:>12} KiB\n", "minimap", self.minimap_bytes / 1024);
+        out += &format!("{:<12}{:>12}\n", "events", self.events);
+        out += &format!("{:<12}{:>12} KiB\n", "total", self.total() / 1024);
+        out += &format!("{:<12}{:>12}\n", "shed", self.shed);
+
+        out
+    }
+}
+
+fn pixel_bytes(profile: Profile) -> usize {
+    let neighb = match profile {
+        Profile::Full => size_of::<Neighb>(),
+        Profile::Lean => 0,
+    };
+
+    neighb + size_of::<Entity>()
+        + size_of::<Height>() + size_of::<Heat>() + size_of::<River>() + size_of::<Rain>()
+        + size_of::<Veget>() + size_of::<Water>() + size_of::<WaterBase>() + size_of::<Aquifer>()
+        + size_of::<RiverBase>() + size_of::<VegetBase>() + size_of::<HeatBase>()
+        + size_of::<Building>() + size_of::<Land>() + size_of::<Index>()
+}
+
+fn vec_bytes<T>(v: &Vec<T>) -> usize {
+    v.capacity() * size_of::<T>()
+}
+
+pub fn measure_memory() -> Box<dyn Schedulable> {
+    SystemBuilder::new("measure_memory")
+        .read_resource::<Census>()
+        .write_resource::<Timeline>()
+        .write_resource::<Minimap>()
+        .write_resource::<MemoryStats>()
+        .with_query(<(Read<Building>, Read<Land>)>::query().filter(tag::<Pixel>()))
+        .with_query(<Read<Stock>>::query().filter(tag::<Settlement>()))
+        .with_query(<Read<Skill>>::query().filter(tag::<Guild>()))
+        .with_query(<Read<Route>>::query().filter(tag::<Caravan>() | tag::<Ship>()))
+        .build(|_, world, (census, timeline, minimap, stats), (pixel_query, stock_query, guild_query, route_query)| {
+            stats.tick += 1;
+
+            if stats.tick % stats.period.max(1) != 0 {
+                return;
+            }
+
+            let mut pixels = 0;
+            let mut heap = 0;
+
+            for (building, land) in pixel_query.iter(&*world) {
+                pixels += 1;
+                heap += vec_bytes(&building.item) + vec_bytes(&land.item);
+            }
+
+            let settlements = stock_query.iter(&*world).map(|stock| heap += vec_bytes(&stock.item)).count();
+            let guilds = guild_query.iter(&*world).map(|skill| heap += vec_bytes(&skill.item)).count();
+            let agents = route_query.iter(&*world).map(|route| heap += vec_bytes(&route.item)).count();
+
+            stats.entities.insert("pixels", pixels);
+            stats.entities.insert("settlements", settlements);
+            stats.entities.insert("guilds", guilds);
+            stats.entities.insert("agents", agents);
+            stats.component_bytes = pixels * pixel_bytes(stats.profile);
+            stats.heap_bytes = heap;
+            stats.census_bytes = census.settlements
+                .values()
+                .map(|settlement| size_of::<(Entity, SettlementCensus)>() + vec_bytes(&settlement.stock))
+                .sum();
+            stats.timeline_bytes = timeline.snapshots
+                .iter()
+                .map(|snapshot| vec_bytes(&snapshot.owners))
+                .sum();
+            stats.minimap_bytes = minimap.frames.iter().map(|frame| frame.as_raw().len()).sum();
+
+            if stats.over_budget() {
+                timeline.capacity = (timeline.capacity / 2).max(1);
+                minimap.capacity = (minimap.capacity / 2).max(1);
+
+                while timeline.snapshots.len() > timeline.capacity {
+                    timeline.snapshots.pop_front();
+                }
+                while minimap.frames.len() > minimap.capacity {
+                    minimap.frames.pop_front();
+                }
+
+                stats.shed += 1;
+            }
+        })
+}
@@ -0,0 +1,89 @@
+use legion::prelude::*;
+
+use std::io;
+use std::fs;
+use std::path::Path;
+
+use crate::Index;
+use crate::Neighb;
+use crate::Building;
+use crate::Name;
+use crate::Pop;
+use crate::Owns;
+use crate::Sea;
+use crate::Settlement;
+use crate::PixelGrid;
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn list<T: ToString>(items: &Vec<T>) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<String>>().join(",")
+}
+
+// A settlement is a harbor when it touches the sea; a road edge joins two
+// neighbouring pixels that both have a road, listed once with the lower
+// index first. The province is the set of pixels the settlement administers.
+pub fn export_metadata<P: AsRef<Path>>(world: &World, grid: &PixelGrid, road: usize, path: P) -> io::Result<()> {
+    let size = grid.size;
+    let mut sea = vec![false; size * size];
+    let mut roads = Vec::new();
+    let mut harbors = Vec::new();
+    let mut settlements = Vec::new();
+
+    for index in Read::<Index>::query().filter(tag::<Sea>()).iter(world) {
+        sea[index.item] = true;
+    }
+
+    let query = <(Read<Index>, TryRead<Neighb>, Read<Building>)>::query();
+    let mut is_road = vec![false; size * size];
+
+    for (index, _, building) in query.iter(world) {
+        is_road[index.item] = building.item[road] > 0.;
+    }
+
+    for (index, neighb, _) in query.iter(world) {
+        if is_road[index.item] {
+            for ii in grid.around(neighb.as_deref(), index.item).iter().filter(|&ii| ii > index.item && is_road[ii]) {
+                roads.push(format!("[{},{}]", index.item, ii));
+            }
+        }
+    }
+
+    let query = <(Read<Index>, TryRead<Neighb>, TryRead<Name>, TryRead<Pop>, TryRead<Owns>)>::query().filter(tag::<Settlement>());
+
+    for (index, neighb, name, pop, owns) in query.iter(world) {
+        let harbor = grid.around(neighb.as_deref(), index.item).iter().any(|ii| sea[ii]);
+        let province: Vec<usize> = owns
+            .map(|owns| owns.item.iter().filter_map(|&pixel| world.get_component::<Index>(pixel).map(|index| index.item)).collect())
+            .unwrap_or_default();
+
+        if harbor {
+            harbors.push(index.item);
+        }
+
+        settlements.push(format!(
+            "{{\"index\":{},\"x\":{},\"y\":{},\"name\":\"{}\",\"pop\":{},\"harbor\":{},\"province\":[{}]}}",
+            index.item, index.item % size, index.item / size,
+            name.map_or(String::new(), |name| escape(name.item.as_str())),
+            pop.map_or(0., |pop| pop.item), harbor, list(&province),
+        ));
+    }
+
+    fs::write(path, format!(
+        "{{\"size\":{},\"settlements\":[{}],\"harbors\":[{}],\"roads\":[{}]}}\n",
+        size, settlements.join(","), list(&harbors), roads.join(","),
+    ))
+}
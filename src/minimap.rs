@@ -0,0 +1,179 @@
+use legion::prelude::*;
+
+use image::RgbaImage;
+use image::Frame;
+use image::gif::GifEncoder;
+
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::Index;
+use crate::Veget;
+use crate::Owned;
+use crate::Pixel;
+use crate::Sea;
+use crate::Lake;
+use crate::map::owner_color;
+use crate::tags::TagEvents;
+use crate::tags::TagKind;
+
+const SEA: [u8; 3] = [40, 70, 140];
+const LAKE: [u8; 3] = [70, 110, 180];
+const BARE: [u8; 3] = [150, 130, 90];
+const GREEN: [u8; 3] = [50, 120, 40];
+
+pub fn mix(a: [u8; 3], b: [u8; 3], t: f32) -> [u8; 3] {
+    let mut out = [0; 3];
+
+    for c in 0..3 {
+        out[c] = (a[c] as f32 * (1. - t) + b[c] as f32 * t) as u8;
+    }
+
+    out
+}
+
+pub struct Minimap {
+    pub size: usize,
+    pub scale: usize,
+    pub width: usize,
+    pub rgba: Vec<u8>,
+    pub frames: VecDeque<RgbaImage>,
+    pub period: usize,
+    pub capacity: usize,
+    colors: Vec<[u8; 3]>,
+    subscriber: Option<usize>,
+    tick: usize,
+}
+
+impl Minimap {
+    pub fn new(size: usize, scale: usize, period: usize, capacity: usize) -> Self {
+        let scale = scale.max(1);
+        let width = (size + scale - 1) / scale;
+
+        Minimap {
+            size,
+            scale,
+            width,
+            rgba: vec![255; width * width * 4],
+            frames: VecDeque::new(),
+            period,
+            capacity,
+            colors: vec![[0; 3]; size * size],
+            subscriber: None,
+            tick: 0,
+        }
+    }
+
+    fn resample(&mut self, (bx, by): (usize, usize)) {
+        let mut sum = [0; 3];
+        let mut count = 0;
+
+        for y in by * self.scale..((by + 1) * self.scale).min(self.size) {
+            for x in bx * self.scale..((bx + 1) * self.scale).min(self.size) {
+                let color = self.colors[y * self.size + x];
+
+                for c in 0..3 {
+                    sum[c] += color[c] as usize;
+                }
+
+                count += 1;
+            }
+        }
+
+        let i = (by * self.width + bx) * 4;
+
+        for c in 0..3 {
+            self.rgba[i + c] = (sum[c] / count.max(1)) as u8;
+        }
+    }
+
+    pub fn image(&self) -> RgbaImage {
+        RgbaImage::from_raw(self.width as u32, self.width as u32, self.rgba.clone()).unwrap()
+    }
+
+    pub fn export<T: Into<PathBuf>>(&self, path: T) {
+        self.image().save(path.into()).unwrap();
+    }
+
+    pub fn export_gif<T: Into<PathBuf>>(&self, path: T) {
+        let mut encoder = GifEncoder::new(File::create(path.into()).unwrap());
+
+        encoder.encode_frames(self.frames.iter().map(|frame| Frame::new(frame.clone()))).unwrap();
+    }
+}
+
+fn color(world: &SubWorld, pixel: Entity, veget: f32, owned: Option<&Owned>) -> [u8; 3] {
+    let terrain = match (world.get_tag::<Sea>(pixel), world.get_tag::<Lake>(pixel)) {
+        (Some(_), _) => SEA,
+        (_, Some(_)) => LAKE,
+        _ => mix(BARE, GREEN, veget),
+    };
+
+    match owned.and_then(|owned| world.get_component::<Index>(owned.item)) {
+        Some(owner) => mix(terrain, owner_color(owner.item).0, 0.5),
+        None => terrain,
+    }
+}
+
+// Only pixels whose vegetation or owner was written, or that became or
+// stopped being water, get their composite color recomputed, and only the
+// minimap blocks holding a pixel whose color actually changed are averaged again.
+pub fn update_minimap() -> Box<dyn Schedulable> {
+    SystemBuilder::new("update_minimap")
+        .write_resource::<Minimap>()
+        .write_resource::<TagEvents>()
+        .read_component::<Index>()
+        .read_component::<Veget>()
+        .read_component::<Owned>()
+        .with_query(<(Read<Index>, Read<Veget>, TryRead<Owned>)>::query().filter(tag::<Pixel>() & (changed::<Veget>() | changed::<Owned>())))
+        .build(|_, world, (minimap, events), query| {
+            let subscriber = *minimap.subscriber.get_or_insert_with(|| events.subscribe());
+            let mut changed: Vec<(usize, [u8; 3])> = query
+                .iter_entities(&*world)
+                .map(|(pixel, (index, veget, owned))| (index.item, color(&*world, pixel, veget.item, owned.as_deref())))
+                .collect();
+
+            for change in events.read(subscriber).into_iter() {
+                match change.kind {
+                    TagKind::Sea | TagKind::Lake => {},
+                    _ => continue,
+                }
+
+                let pixel = change.entity;
+                let (index, veget) = match (world.get_component::<Index>(pixel), world.get_component::<Veget>(pixel)) {
+                    (Some(index), Some(veget)) => (index.item, veget.item),
+                    _ => continue,
+                };
+                let owned = world.get_component::<Owned>(pixel);
+
+                changed.push((index, color(&*world, pixel, veget, owned.as_deref())));
+            }
+
+            let mut blocks = BTreeSet::new();
+
+            for (index, color) in changed.into_iter() {
+                if minimap.colors[index] != color {
+                    minimap.colors[index] = color;
+                    blocks.insert(((index % minimap.size) / minimap.scale, (index / minimap.size) / minimap.scale));
+                }
+            }
+
+            for block in blocks.into_iter() {
+                minimap.resample(block);
+            }
+
+            minimap.tick += 1;
+
+            if minimap.period > 0 && minimap.tick % minimap.period == 0 {
+                if minimap.frames.len() >= minimap.capacity {
+                    minimap.frames.pop_front();
+                }
+
+                let frame = minimap.image();
+
+                minimap.frames.push_back(frame);
+            }
+        })
+}
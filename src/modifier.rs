@@ -0,0 +1,57 @@
+use legion::prelude::*;
+
+use serde::Serialize;
+use serde::Deserialize;
+
+use crate::symbol::Symbol;
+
+// Multiplies `stat` by `factor` for `left` more ticks, or for good when
+// `left` is None. `source` names what granted it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Modifier {
+    pub stat: Symbol,
+    pub factor: f32,
+    pub left: Option<u32>,
+    pub source: Symbol,
+}
+
+pub struct Modifiers { pub item: Vec<Modifier> }
+
+impl Modifiers {
+    pub fn factor(&self, stat: Symbol) -> f32 {
+        self.item.iter().filter(|modifier| modifier.stat == stat).map(|modifier| modifier.factor).product()
+    }
+
+    pub fn from(&self, source: Symbol) -> bool {
+        self.item.iter().any(|modifier| modifier.source == source)
+    }
+}
+
+pub fn factor(modifiers: Option<&Modifiers>, stat: Symbol) -> f32 {
+    modifiers.map_or(1., |modifiers| modifiers.factor(stat))
+}
+
+pub fn add_modifier(world: &mut World, entity: Entity, modifier: Modifier) {
+    if let Some(mut modifiers) = world.get_component_mut::<Modifiers>(entity) {
+        modifiers.item.push(modifier);
+        return;
+    }
+
+    world.add_component(entity, Modifiers { item: vec![modifier] }).ok();
+}
+
+pub fn expire_modifiers() -> Box<dyn Schedulable> {
+    SystemBuilder::new("expire_modifiers")
+        .with_query(<Write<Modifiers>>::query())
+        .build(|_, world, _, query| {
+            for mut modifiers in query.iter_mut(world) {
+                for modifier in modifiers.item.iter_mut() {
+                    if let Some(left) = modifier.left.as_mut() {
+                        *left = left.saturating_sub(1);
+                    }
+                }
+
+                modifiers.item.retain(|modifier| modifier.left != Some(0));
+            }
+        })
+}
@@ -0,0 +1,170 @@
+use serde::Deserialize;
+
+use ron::de::from_reader;
+
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::irrigation::IrrigationDefines;
+use crate::guild::RecipeDefines;
+use crate::trade::TradeDefines;
+use crate::symbol::Symbol;
+
+#[derive(Clone, Deserialize)]
+pub struct ModInfo {
+    pub name: Symbol,
+    #[serde(default)]
+    pub depends: Vec<Symbol>,
+    #[serde(default)]
+    pub after: Vec<Symbol>,
+}
+
+// Everything a mod can layer over the base defines. New names get the next
+// free index, existing names keep theirs, and entries keyed by name replace
+// the earlier definition.
+#[derive(Clone, Default, Deserialize)]
+pub struct ModDefines {
+    #[serde(default)]
+    pub buildings: Vec<Symbol>,
+    #[serde(default)]
+    pub lands: Vec<Symbol>,
+    #[serde(default)]
+    pub goods: Vec<Symbol>,
+    #[serde(default)]
+    pub trade: HashMap<Symbol, TradeDefines>,
+    #[serde(default)]
+    pub irrigation: HashMap<Symbol, IrrigationDefines>,
+    #[serde(default)]
+    pub recipes: Vec<RecipeDefines>,
+}
+
+pub struct Mod {
+    pub info: ModInfo,
+    pub path: PathBuf,
+}
+
+fn extend_index(index: &mut HashMap<Symbol, usize>, names: &Vec<Symbol>) {
+    for &name in names.iter() {
+        let next = index.len();
+
+        index.entry(name).or_insert(next);
+    }
+}
+
+impl ModDefines {
+    pub fn apply(self, defines: &mut Defines) {
+        extend_index(&mut defines.building_i, &self.buildings);
+        extend_index(&mut defines.land_i, &self.lands);
+        extend_index(&mut defines.good_i, &self.goods);
+
+        defines.irrigation.extend(self.irrigation.into_iter());
+        defines.trade.extend(self.trade.into_iter());
+
+        for recipe in self.recipes.into_iter() {
+            match defines.recipes.iter_mut().find(|old| old.name == recipe.name) {
+                Some(old) => *old = recipe,
+                None => defines.recipes.push(recipe),
+            }
+        }
+    }
+}
+
+pub fn discover<P: AsRef<Path>>(dir: P) -> Result<Vec<Mod>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut mods = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let info = path.join("mod.ron");
+
+        if !info.is_file() {
+            continue;
+        }
+
+        let info: ModInfo = from_reader(File::open(&info).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("{}: {}", info.display(), e))?;
+
+        mods.push(Mod { info, path });
+    }
+
+    Ok(mods)
+}
+
+// Kahn's algorithm over hard dependencies plus soft `after` edges to mods
+// that are actually present; ties are broken by name so the order is stable.
+pub fn resolve(mods: Vec<Mod>) -> Result<Vec<Mod>, String> {
+    let mut by_name: BTreeMap<Symbol, Mod> = BTreeMap::new();
+
+    for m in mods.into_iter() {
+        if by_name.contains_key(&m.info.name) {
+            return Err(format!("mod {} found twice", m.info.name));
+        }
+
+        by_name.insert(m.info.name, m);
+    }
+
+    let mut waiting: BTreeMap<Symbol, BTreeSet<Symbol>> = BTreeMap::new();
+
+    for (&name, m) in by_name.iter() {
+        for dep in m.info.depends.iter() {
+            if !by_name.contains_key(dep) {
+                return Err(format!("mod {} depends on missing mod {}", name, dep));
+            }
+        }
+
+        let deps = m.info.depends
+            .iter()
+            .chain(m.info.after.iter().filter(|&after| by_name.contains_key(after)))
+            .cloned()
+            .collect();
+
+        waiting.insert(name, deps);
+    }
+
+    let mut order = Vec::new();
+
+    while !waiting.is_empty() {
+        let ready = match waiting.iter().find(|(_, deps)| deps.is_empty()) {
+            Some((&name, _)) => name,
+            None => return Err(format!("dependency cycle among {:?}", waiting.keys().collect::<Vec<_>>())),
+        };
+
+        waiting.remove(&ready);
+
+        for deps in waiting.values_mut() {
+            deps.remove(&ready);
+        }
+
+        order.push(by_name.remove(&ready).unwrap());
+    }
+
+    Ok(order)
+}
+
+pub fn load_mods<P: AsRef<Path>>(defines: &mut Defines, dir: P) -> Result<Vec<Symbol>, String> {
+    let mods = resolve(discover(dir)?)?;
+
+    for m in mods.iter() {
+        let path = m.path.join("defines.ron");
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let patch: ModDefines = from_reader(File::open(&path).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        patch.apply(defines);
+    }
+
+    Ok(mods.into_iter().map(|m| m.info.name).collect())
+}
@@ -0,0 +1,238 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use ron::de::from_reader;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use std::fs::File;
+use std::path::Path;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::Name;
+use crate::symbol::Symbol;
+
+#[derive(Clone, Deserialize)]
+pub struct Syllables {
+    pub first: Vec<String>,
+    #[serde(default)]
+    pub middle: Vec<String>,
+    pub last: Vec<String>,
+    #[serde(default)]
+    pub max_middle: usize,
+}
+
+// With `markov` set, new names are spelled letter by letter from a chain of
+// that order learned from the culture's hand-written names, instead of from
+// its syllables. A pattern for a kind wraps the names made up for it, "{}"
+// standing for the name.
+#[derive(Clone, Deserialize)]
+pub struct CultureNames {
+    pub syllables: Syllables,
+    #[serde(default)]
+    pub markov: Option<usize>,
+    #[serde(default)]
+    pub patterns: HashMap<Kind, String>,
+    #[serde(default)]
+    pub settlements: Vec<String>,
+    #[serde(default)]
+    pub people: Vec<String>,
+    #[serde(default)]
+    pub realms: Vec<String>,
+    #[serde(default)]
+    pub provinces: Vec<String>,
+    #[serde(default)]
+    pub seas: Vec<String>,
+    #[serde(default)]
+    pub rivers: Vec<String>,
+    #[serde(skip)]
+    chain: HashMap<String, Vec<char>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Kind {
+    Settlement,
+    Person,
+    Realm,
+    Province,
+    Sea,
+    River,
+}
+
+// Seas of at least `sea` pixels and rivers carrying at least `river` at
+// their mouth are named.
+#[derive(Clone, Deserialize)]
+pub struct NamingDefines {
+    pub sea: usize,
+    pub river: f64,
+}
+
+const START: char = '^';
+const END: char = '$';
+
+pub struct Names {
+    pub cultures: HashMap<Symbol, CultureNames>,
+    used: HashSet<Symbol>,
+}
+
+impl CultureNames {
+    fn list(&self, kind: Kind) -> &Vec<String> {
+        match kind {
+            Kind::Settlement => &self.settlements,
+            Kind::Person => &self.people,
+            Kind::Realm => &self.realms,
+            Kind::Province => &self.provinces,
+            Kind::Sea => &self.seas,
+            Kind::River => &self.rivers,
+        }
+    }
+
+    fn learn(&mut self, order: usize) {
+        let mut chain: HashMap<String, Vec<char>> = HashMap::new();
+        let lists = [&self.settlements, &self.people, &self.realms, &self.provinces, &self.seas, &self.rivers];
+
+        for name in lists.iter().flat_map(|list| list.iter()) {
+            let letters: Vec<char> = (0..order).map(|_| START).chain(name.chars()).chain(Some(END)).collect();
+
+            for window in letters.windows(order + 1) {
+                chain.entry(window[..order].iter().collect()).or_insert_with(Vec::new).push(window[order]);
+            }
+        }
+
+        self.chain = chain;
+    }
+
+    fn spell<R: Rng>(&self, order: usize, rng: &mut R) -> Option<String> {
+        let mut state: Vec<char> = (0..order).map(|_| START).collect();
+        let mut name = String::new();
+
+        while name.chars().count() < 16 {
+            let next = *self.chain.get(&state.iter().collect::<String>())?.choose(rng)?;
+
+            if next == END {
+                break;
+            }
+
+            name.push(next);
+            state.remove(0);
+            state.push(next);
+        }
+
+        Some(name).filter(|name| name.chars().count() >= 3)
+    }
+
+    fn wrap(&self, kind: Kind, name: String) -> String {
+        match self.patterns.get(&kind) {
+            Some(pattern) => pattern.replace("{}", &name),
+            None => name,
+        }
+    }
+
+    fn compose<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        if let Some(order) = self.markov.filter(|_| !self.chain.is_empty()) {
+            if let Some(name) = (0..8).find_map(|_| self.spell(order, rng)) {
+                return Some(name);
+            }
+        }
+
+        let syllables = &self.syllables;
+        let mut name = syllables.first.choose(rng)?.clone();
+
+        if !syllables.middle.is_empty() {
+            for _ in 0..rng.gen_range(0, syllables.max_middle + 1) {
+                name += syllables.middle.choose(rng)?;
+            }
+        }
+
+        name += syllables.last.choose(rng)?;
+
+        Some(name)
+    }
+}
+
+impl Names {
+    pub fn new(mut cultures: HashMap<Symbol, CultureNames>) -> Self {
+        for names in cultures.values_mut() {
+            if let Some(order) = names.markov {
+                names.learn(order.max(1));
+            }
+        }
+
+        Names {
+            cultures,
+            used: HashSet::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let cultures = from_reader(File::open(path).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+
+        Ok(Names::new(cultures))
+    }
+
+    fn is_used(&self, name: &str) -> bool {
+        Symbol::lookup(name).map_or(false, |name| self.used.contains(&name))
+    }
+
+    // Hand-written names for the culture are used up first; after that names
+    // are built from its syllables, and reused only if nothing new turns up.
+    pub fn generate<R: Rng>(&mut self, culture: Symbol, kind: Kind, rng: &mut R) -> Option<Symbol> {
+        let names = self.cultures.get(&culture)?;
+        let free: Vec<&String> = names.list(kind).iter().filter(|name| !self.is_used(name)).collect();
+
+        let name = match free.choose(rng) {
+            Some(name) => name.to_string(),
+            None => {
+                let mut name = names.wrap(kind, names.compose(rng)?);
+
+                for _ in 0..16 {
+                    if !self.is_used(&name) {
+                        break;
+                    }
+
+                    name = names.wrap(kind, names.compose(rng)?);
+                }
+
+                name
+            }
+        };
+        let name = Symbol::new(&name);
+
+        self.used.insert(name);
+
+        Some(name)
+    }
+
+    pub fn release(&mut self, name: Symbol) {
+        self.used.remove(&name);
+    }
+}
+
+pub fn rename<R: Rng>(world: &mut World, names: &mut Names, entity: Entity, culture: Symbol, kind: Kind, rng: &mut R) -> Option<Symbol> {
+    let name = names.generate(culture, kind, rng)?;
+
+    if let Some(old) = world.get_component::<Name>(entity).map(|name| name.item) {
+        names.release(old);
+    }
+
+    world.add_component(entity, Name { item: name }).ok()?;
+
+    Some(name)
+}
+
+// One name for a whole feature, such as a sea or a river, given to each of
+// its entities that is not already named for something else.
+pub fn name_all<R: Rng>(world: &mut World, names: &mut Names, entities: &[Entity], culture: Symbol, kind: Kind, rng: &mut R) -> Option<Symbol> {
+    let name = names.generate(culture, kind, rng)?;
+
+    for &entity in entities.iter() {
+        if world.get_component::<Name>(entity).is_none() {
+            world.add_component(entity, Name { item: name }).ok()?;
+        }
+    }
+
+    Some(name)
+}
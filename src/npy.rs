@@ -0,0 +1,75 @@
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+
+pub trait Element: Copy {
+    const DESCR: &'static str;
+
+    fn write_le(&self, out: &mut Vec<u8>);
+}
+
+impl Element for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Element for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+// Format version 1.0: magic, version, a little-endian u16 header length, then
+// a Python dict literal padded with spaces so the data starts 64-aligned.
+pub fn write_npy<T: Element, P: AsRef<Path>>(values: &[T], shape: &[usize], path: P) -> io::Result<()> {
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!("({})", shape.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", T::DESCR, shape);
+
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+
+    header.push('\n');
+
+    let mut out = Vec::with_capacity(10 + header.len() + values.len() * 8);
+
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+
+    for value in values.iter() {
+        value.write_le(&mut out);
+    }
+
+    File::create(path)?.write_all(&out)
+}
+
+// Every layer is a `size` by `size` grid written to `<name>.npy` in `dir`.
+pub fn write_layers<T, N, V, I, P>(layers: I, size: usize, dir: P) -> io::Result<()>
+where
+    T: Element,
+    N: AsRef<str>,
+    V: AsRef<[T]>,
+    I: IntoIterator<Item = (N, V)>,
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+
+    fs::create_dir_all(dir)?;
+
+    for (name, values) in layers.into_iter() {
+        write_npy(values.as_ref(), &[size, size], dir.join(format!("{}.npy", name.as_ref())))?;
+    }
+
+    Ok(())
+}
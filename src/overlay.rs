@@ -0,0 +1,99 @@
+use legion::prelude::*;
+use legion::storage::Component;
+
+use image::RgbImage;
+
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::Index;
+use crate::Water;
+use crate::Veget;
+use crate::Heat;
+use crate::Rain;
+use crate::River;
+use crate::Height;
+use crate::Pop;
+use crate::Aquifer;
+use crate::Fertility;
+use crate::garrison::Coverage;
+use crate::colormap::Colormap;
+use crate::npy::write_npy;
+use crate::npy::write_layers;
+
+pub trait Value {
+    fn value(&self) -> f32;
+}
+
+macro_rules! impl_value {
+    ($($t:ident),*) => {
+        $(
+            impl Value for $t {
+                fn value(&self) -> f32 {
+                    self.item
+                }
+            }
+        )*
+    };
+}
+
+impl_value!(Water, Veget, Heat, Rain, River, Height, Pop, Aquifer, Fertility, Coverage);
+
+pub fn layer<T: Component + Value>(world: &World, size: usize) -> Vec<f64> {
+    let query = <(Read<Index>, Read<T>)>::query();
+    let mut out = vec![0.; size * size];
+
+    for (index, value) in query.iter(world) {
+        out[index.item] = value.value() as f64;
+    }
+
+    out
+}
+
+pub const LAYERS: [&str; 10] = ["Water", "Veget", "Heat", "Rain", "River", "Height", "Pop", "Aquifer", "Fertility", "Coverage"];
+
+pub fn named_layer(world: &World, name: &str, size: usize) -> Option<Vec<f64>> {
+    match name {
+        "Water" => Some(layer::<Water>(world, size)),
+        "Veget" => Some(layer::<Veget>(world, size)),
+        "Heat" => Some(layer::<Heat>(world, size)),
+        "Rain" => Some(layer::<Rain>(world, size)),
+        "River" => Some(layer::<River>(world, size)),
+        "Height" => Some(layer::<Height>(world, size)),
+        "Pop" => Some(layer::<Pop>(world, size)),
+        "Aquifer" => Some(layer::<Aquifer>(world, size)),
+        "Fertility" => Some(layer::<Fertility>(world, size)),
+        "Coverage" => Some(layer::<Coverage>(world, size)),
+        _ => None,
+    }
+}
+
+pub fn render(values: &Vec<f64>, size: usize, min: f64, max: f64, colormap: Colormap) -> RgbImage {
+    let mut img = RgbImage::new(size as u32, size as u32);
+
+    for (i, value) in values.iter().enumerate() {
+        img.put_pixel((i % size) as u32, (i / size) as u32, colormap.color((value - min) / (max - min)));
+    }
+
+    img
+}
+
+pub fn export_heatmap<T: Into<PathBuf>>(values: &Vec<f64>, size: usize, path: T, min: f64, max: f64, colormap: Colormap) {
+    render(values, size, min, max, colormap).save(path.into()).unwrap();
+}
+
+pub fn export_npy<P: AsRef<Path>>(values: &Vec<f64>, size: usize, path: P) -> io::Result<()> {
+    let values: Vec<f32> = values.iter().map(|&value| value as f32).collect();
+
+    write_npy(&values, &[size, size], path)
+}
+
+// Layers go out as f32, which is all the components hold.
+pub fn export_all_npy<P: AsRef<Path>>(world: &World, size: usize, dir: P) -> io::Result<()> {
+    let layers = LAYERS
+        .iter()
+        .filter_map(|name| named_layer(world, name, size).map(|values| (name, values.iter().map(|&value| value as f32).collect::<Vec<f32>>())));
+
+    write_layers(layers, size, dir)
+}
@@ -0,0 +1,340 @@
+use legion::prelude::*;
+
+use pathfinding::directed::astar::astar;
+
+use rayon::prelude::*;
+
+use serde::Deserialize;
+
+use std::mem::replace;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+
+use crate::Building;
+use crate::Height;
+use crate::Sea;
+use crate::Navigable;
+use crate::Settlement;
+use crate::PixelGrid;
+use crate::tags::TagEvents;
+use crate::tags::TagKind;
+use crate::isochrone::Isochrones;
+use crate::map::offset_between;
+use crate::map::WrapMode;
+
+const SCALE: f32 = 1000.;
+const UNREACHED: u32 = u32::MAX;
+
+// Share of the normal cost paid for a step between two navigable pixels,
+// whether on a boat or hauling along the bank.
+pub const RIVER_FACTOR: f32 = 0.5;
+
+// Road levels above this speed nothing up further, which keeps a step from
+// being cheaper than a cost's `min`.
+pub const MAX_ROAD: f32 = 4.;
+
+#[derive(Clone, Deserialize)]
+pub struct NavigationDefines {
+    pub flow: f32,
+    pub slope: f32,
+}
+
+fn navigable(world: &World, pixel: Entity) -> bool {
+    world.get_tag::<Navigable>(pixel).is_some()
+}
+
+// Tags the pixels the map found navigable, but the sea, which needs no tag.
+// Runs once after the pixels are spawned.
+pub fn mark_navigable(world: &mut World, grid: &PixelGrid, navigable: &Vec<bool>) {
+    let marked: Vec<Entity> = (0..navigable.len())
+        .filter(|&i| navigable[i])
+        .map(|i| grid.get(i))
+        .filter(|&pixel| world.get_tag::<Sea>(pixel).is_none())
+        .collect();
+
+    for pixel in marked.into_iter() {
+        world.add_tag(pixel, Navigable).unwrap();
+    }
+}
+
+pub fn step_cost(height: f32, height_next: f32, road: f32) -> f32 {
+    (1. + 10. * (height_next - height).abs()) / (1. + road.max(0.).min(MAX_ROAD))
+}
+
+// `min` is the least a single step can cost, which A* multiplies by the
+// distance left as its heuristic, so it must never be above a real step.
+#[derive(Clone, Copy)]
+pub struct Cost {
+    pub name: &'static str,
+    pub step: fn(&World, usize, Entity, Entity) -> Option<f32>,
+    pub min: f32,
+}
+
+fn height(world: &World, pixel: Entity) -> f32 {
+    world.get_component::<Height>(pixel).map_or(0., |height| height.item)
+}
+
+// `road` is the index of the Road building, whose level on the pixel being
+// left speeds the step up.
+fn land_step(world: &World, road: usize, from: Entity, to: Entity) -> Option<f32> {
+    if world.get_tag::<Sea>(to).is_some() {
+        return None;
+    }
+
+    let road = world.get_component::<Building>(from).map_or(0., |building| building.item[road]);
+    let cost = step_cost(height(world, from), height(world, to), road);
+
+    match navigable(world, from) && navigable(world, to) {
+        true => Some(cost * RIVER_FACTOR),
+        false => Some(cost),
+    }
+}
+
+fn sea_step(world: &World, _: usize, _: Entity, to: Entity) -> Option<f32> {
+    world.get_tag::<Sea>(to).map(|_| 1.)
+}
+
+// Boats go anywhere on the sea and up navigable rivers, so inland river
+// ports join the sea network.
+fn water_step(world: &World, _: usize, _: Entity, to: Entity) -> Option<f32> {
+    match world.get_tag::<Sea>(to).is_some() || navigable(world, to) {
+        true => Some(1.),
+        false => None,
+    }
+}
+
+pub const LAND: Cost = Cost { name: "land", step: land_step, min: RIVER_FACTOR / (1. + MAX_ROAD) };
+pub const SEA: Cost = Cost { name: "sea", step: sea_step, min: 1. };
+pub const WATER: Cost = Cost { name: "water", step: water_step, min: 1. };
+
+fn distance(a: usize, b: usize, size: usize, wrap: WrapMode) -> usize {
+    let (dx, dy) = offset_between(a, b, size, wrap);
+
+    dx.abs().max(dy.abs()) as usize
+}
+
+fn neighbs(world: &World, grid: &PixelGrid, index: usize) -> Vec<usize> {
+    grid.neighb(world, index).iter().collect()
+}
+
+// The goal itself is always enterable, so a ship can reach a port pixel on
+// land and a caravan can reach one on the coast. Returned paths exclude the
+// start and are stored goal-first, the way routes are consumed.
+pub fn find_path(world: &World, grid: &PixelGrid, cost: &Cost, road: usize, from: usize, to: usize) -> Option<Vec<usize>> {
+    let size = (grid.item.len() as f64).sqrt() as usize;
+    let (path, _) = astar(&from,
+        |&index| {
+            neighbs(world, grid, index)
+                .into_iter()
+                .filter_map(|next| {
+                    let step = (cost.step)(world, road, grid.get(index), grid.get(next)).or(if next == to { Some(1.) } else { None })?;
+
+                    Some((next, (SCALE * step) as usize))
+                })
+                .collect::<Vec<(usize, usize)>>()
+        },
+        |&index| (SCALE * cost.min) as usize * distance(index, to, size, grid.wrap),
+        |&index| index == to
+    )?;
+
+    Some(path.into_iter().skip(1).rev().collect())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Settlement,
+    Coast,
+}
+
+impl Target {
+    fn sources(&self, world: &World, grid: &PixelGrid) -> Vec<usize> {
+        (0..grid.item.len())
+            .filter(|&index| {
+                let pixel = grid.get(index);
+
+                match self {
+                    Target::Settlement => world.get_tag::<Settlement>(pixel).is_some(),
+                    Target::Coast => {
+                        world.get_tag::<Sea>(pixel).is_none()
+                            && neighbs(world, grid, index).into_iter().any(|n| world.get_tag::<Sea>(grid.get(n)).is_some())
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct FlowField {
+    pub dist: Vec<u32>,
+    pub next: Vec<u32>,
+}
+
+impl FlowField {
+    pub fn build(world: &World, grid: &PixelGrid, cost: &Cost, road: usize, sources: &Vec<usize>) -> Self {
+        FlowField::build_by(world, grid, sources, |from, to| (cost.step)(world, road, from, to), f32::MAX)
+    }
+
+    // `step` is the cost of moving from its first pixel to its second, and
+    // pixels farther than `limit` from every source are left unreached.
+    pub fn build_by<F: Fn(Entity, Entity) -> Option<f32>>(world: &World, grid: &PixelGrid, sources: &Vec<usize>, step: F, limit: f32) -> Self {
+        let limit = (SCALE * limit) as u32;
+        let mut dist = vec![UNREACHED; grid.item.len()];
+        let mut next = vec![UNREACHED; grid.item.len()];
+        let mut heap = BinaryHeap::new();
+
+        for &source in sources.iter() {
+            dist[source] = 0;
+            heap.push(Reverse((0, source)));
+        }
+
+        while let Some(Reverse((d, index))) = heap.pop() {
+            if d > dist[index] {
+                continue;
+            }
+
+            for from in neighbs(world, grid, index) {
+                let step = match step(grid.get(from), grid.get(index)) {
+                    Some(step) => (SCALE * step) as u32,
+                    None => continue,
+                };
+                let d = d.saturating_add(step);
+
+                if d <= limit && d < dist[from] {
+                    dist[from] = d;
+                    next[from] = index as u32;
+                    heap.push(Reverse((d, from)));
+                }
+            }
+        }
+
+        FlowField { dist, next }
+    }
+
+    pub fn next(&self, index: usize) -> Option<usize> {
+        match self.next[index] {
+            UNREACHED => None,
+            next => Some(next as usize),
+        }
+    }
+
+    pub fn dist(&self, index: usize) -> Option<f32> {
+        match self.dist[index] {
+            UNREACHED => None,
+            dist => Some(dist as f32 / SCALE),
+        }
+    }
+}
+
+// Fields are built lazily and kept until the water, the settlements or a
+// road level changes.
+pub struct FlowFields {
+    pub road: usize,
+    fields: HashMap<(&'static str, Target), FlowField>,
+    subscriber: Option<usize>,
+}
+
+impl FlowFields {
+    pub fn new(road: usize) -> Self {
+        FlowFields { road, fields: HashMap::new(), subscriber: None }
+    }
+
+    // A field's costs and sources only depend on water, navigable rivers,
+    // roads and where the settlements are, so other tag changes leave it
+    // valid. Road levels are reported through roads_changed.
+    pub fn refresh(&mut self, events: &mut TagEvents) {
+        let subscriber = *self.subscriber.get_or_insert_with(|| events.subscribe());
+        let stale = events
+            .read(subscriber)
+            .iter()
+            .any(|change| match change.kind {
+                TagKind::Colony => false,
+                _ => true,
+            });
+
+        if stale {
+            self.invalidate();
+        }
+    }
+
+    pub fn get(&mut self, world: &World, grid: &PixelGrid, cost: &Cost, target: Target) -> &FlowField {
+        let road = self.road;
+
+        self.fields
+            .entry((cost.name, target))
+            .or_insert_with(|| FlowField::build(world, grid, cost, road, &target.sources(world, grid)))
+    }
+
+    pub fn invalidate(&mut self) {
+        self.fields.clear();
+    }
+}
+
+// Called by whatever changes the level of a Road.
+pub fn roads_changed(resources: &Resources) {
+    if let Some(mut flows) = resources.get_mut::<FlowFields>() {
+        flows.invalidate();
+    }
+}
+
+pub struct PathRequest {
+    pub agent: Entity,
+    pub from: usize,
+    pub to: usize,
+    pub cost: Cost,
+}
+
+pub struct PathResult { pub item: Option<Vec<Entity>> }
+
+pub struct PathRequests {
+    pub item: Vec<PathRequest>,
+    pub road: usize,
+}
+
+impl PathRequests {
+    pub fn new(road: usize) -> Self {
+        PathRequests { item: Vec::new(), road }
+    }
+
+    pub fn submit(&mut self, agent: Entity, from: usize, to: usize, cost: Cost) {
+        self.item.push(PathRequest { agent, from, to, cost });
+    }
+}
+
+// Runs as a thread-local step so the whole world can be shared read-only
+// across the pool; each agent finds a PathResult on itself the next tick.
+pub fn solve_paths(world: &mut World, resources: &mut Resources) {
+    if let (Some(mut flows), Some(mut events)) = (resources.get_mut::<FlowFields>(), resources.get_mut::<TagEvents>()) {
+        flows.refresh(&mut events);
+    }
+    if let (Some(mut isochrones), Some(mut events)) = (resources.get_mut::<Isochrones>(), resources.get_mut::<TagEvents>()) {
+        isochrones.refresh(&mut events);
+    }
+
+    let (requests, road) = match resources.get_mut::<PathRequests>() {
+        Some(mut requests) => (replace(&mut requests.item, Vec::new()), requests.road),
+        None => return,
+    };
+    let grid = match resources.get::<PixelGrid>() {
+        Some(grid) => grid,
+        None => return,
+    };
+
+    let results: Vec<(Entity, Option<Vec<Entity>>)> = {
+        let world = &*world;
+
+        requests
+            .into_par_iter()
+            .map(|request| {
+                let path = find_path(world, &grid, &request.cost, road, request.from, request.to)
+                    .map(|path| path.into_iter().map(|index| grid.get(index)).collect());
+
+                (request.agent, path)
+            })
+            .collect()
+    };
+
+    for (agent, path) in results.into_iter() {
+        let _ = world.add_component(agent, PathResult { item: path });
+    }
+}
@@ -0,0 +1,81 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::symbol::Symbol;
+use crate::condition::Condition;
+use crate::modifier::Modifier;
+use crate::modifier::Modifiers;
+use crate::modifier::add_modifier;
+
+// `weight` is how often the profile is drawn, multiplied by the founding
+// culture's entry in `cultures` and by `favour` where the site meets
+// `likes`. `modifiers` scale the stats the settlement decides by, for good.
+#[derive(Clone, Deserialize)]
+pub struct PersonalityDefines {
+    pub name: Symbol,
+    pub weight: f32,
+    #[serde(default)]
+    pub cultures: HashMap<Symbol, f32>,
+    #[serde(default)]
+    pub likes: Option<Condition>,
+    #[serde(default)]
+    pub favour: f32,
+    pub modifiers: HashMap<Symbol, f32>,
+}
+
+pub struct Personality { pub item: Symbol }
+
+fn weight(world: &World, defines: &Defines, personality: &PersonalityDefines, settlement: Entity, culture: Symbol) -> f32 {
+    let mut weight = personality.weight * personality.cultures.get(&culture).copied().unwrap_or(1.);
+
+    if personality.likes.as_ref().map_or(false, |likes| likes.eval(world, defines, settlement)) {
+        weight *= personality.favour;
+    }
+
+    weight.max(0.)
+}
+
+// Draws a profile for a newly founded settlement and grants its modifiers.
+pub fn assign<R: Rng>(world: &mut World, defines: &Defines, settlement: Entity, culture: Symbol, rng: &mut R) {
+    let weights: Vec<f32> = defines.personalities
+        .iter()
+        .map(|personality| weight(world, defines, personality, settlement, culture))
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    if total <= 0. {
+        return;
+    }
+
+    let mut roll = rng.gen::<f32>() * total;
+    let mut chosen = weights.len() - 1;
+
+    for (p, &weight) in weights.iter().enumerate() {
+        if roll < weight {
+            chosen = p;
+            break;
+        }
+
+        roll -= weight;
+    }
+
+    let personality = &defines.personalities[chosen];
+    let previous = world.get_component::<Personality>(settlement).map(|previous| previous.item);
+
+    // A ruin resettled sheds the profile of the town it was.
+    if let (Some(previous), Some(mut modifiers)) = (previous, world.get_component_mut::<Modifiers>(settlement)) {
+        modifiers.item.retain(|modifier| modifier.source != previous);
+    }
+
+    for (&stat, &factor) in personality.modifiers.iter() {
+        add_modifier(world, settlement, Modifier { stat, factor, left: None, source: personality.name });
+    }
+
+    world.add_component(settlement, Personality { item: personality.name }).unwrap();
+}
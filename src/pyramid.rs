@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use crate::colormap::Colormap;
+
+// How four cells are folded into the one above them: Mean for quantities,
+// Mode for labels such as owners, where an average would mean nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reduce {
+    Mean,
+    Mode,
+}
+
+// Level 0 is the full layer and every level above it is half as wide, down to
+// a single cell. Only the cells above a changed one are reduced again.
+pub struct Pyramid {
+    pub reduce: Reduce,
+    pub sizes: Vec<usize>,
+    pub levels: Vec<Vec<f32>>,
+    dirty: Vec<usize>,
+}
+
+impl Pyramid {
+    pub fn new(size: usize, reduce: Reduce) -> Self {
+        let mut sizes = vec![size.max(1)];
+
+        while *sizes.last().unwrap() > 1 {
+            sizes.push((sizes.last().unwrap() + 1) / 2);
+        }
+
+        Pyramid {
+            reduce,
+            levels: sizes.iter().map(|&size| vec![0.; size * size]).collect(),
+            sizes,
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: f32) {
+        if self.levels[0][index] != value {
+            self.levels[0][index] = value;
+            self.dirty.push(index);
+        }
+    }
+
+    pub fn update(&mut self) {
+        let mut dirty = std::mem::replace(&mut self.dirty, Vec::new());
+
+        for level in 1..self.levels.len() {
+            let below = self.sizes[level - 1];
+            let size = self.sizes[level];
+
+            dirty = dirty.into_iter().map(|i| ((i / below) / 2) * size + (i % below) / 2).collect();
+            dirty.sort();
+            dirty.dedup();
+
+            for &i in dirty.iter() {
+                self.levels[level][i] = self.reduce_cell(level, i);
+            }
+        }
+    }
+
+    fn reduce_cell(&self, level: usize, i: usize) -> f32 {
+        let below = self.sizes[level - 1];
+        let size = self.sizes[level];
+        let (x, y) = (2 * (i % size), 2 * (i / size));
+        let children: Vec<f32> = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+            .iter()
+            .filter(|&&(x, y)| x < below && y < below)
+            .map(|&(x, y)| self.levels[level - 1][y * below + x])
+            .collect();
+
+        match self.reduce {
+            Reduce::Mean => children.iter().sum::<f32>() / children.len() as f32,
+            Reduce::Mode => {
+                let mut counts: HashMap<u32, usize> = HashMap::new();
+
+                for value in children.iter() {
+                    *counts.entry(value.to_bits()).or_insert(0) += 1;
+                }
+
+                children
+                    .iter()
+                    .cloned()
+                    .max_by(|a, b| counts[&a.to_bits()].cmp(&counts[&b.to_bits()]).then(b.partial_cmp(a).unwrap()))
+                    .unwrap()
+            }
+        }
+    }
+
+    // The coarsest level that still has at least one cell per `scale` pixels
+    // of the full layer.
+    pub fn level_for(&self, scale: usize) -> usize {
+        let mut level = 0;
+
+        while level + 1 < self.levels.len() && 1 << (level + 1) <= scale {
+            level += 1;
+        }
+
+        level
+    }
+
+    pub fn get(&self, level: usize, x: usize, y: usize) -> f32 {
+        self.levels[level][y * self.sizes[level] + x]
+    }
+
+    pub fn render_rgba(&self, level: usize, min: f32, max: f32, colormap: Colormap) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.levels[level].len() * 4);
+
+        for value in self.levels[level].iter() {
+            let color = colormap.color(((value - min) / (max - min)) as f64);
+
+            out.extend_from_slice(&[color.0[0], color.0[1], color.0[2], 255]);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,44 @@
+use legion::prelude::*;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+// splitmix64, taken for spreading neighbouring seeds far apart.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+fn stream_id(stream: &str) -> u64 {
+    stream.bytes().fold(0xCBF29CE484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+// Every draw the simulation makes comes from here, seeded from the map seed,
+// the tick and the name of the stream drawn from, so every participant
+// draws the same numbers whichever systems ran before.
+pub struct WorldRng {
+    pub seed: u64,
+    pub tick: u64,
+}
+
+impl WorldRng {
+    pub fn new(seed: u64) -> Self {
+        WorldRng {
+            seed,
+            tick: 0,
+        }
+    }
+
+    pub fn get(&self, stream: &str) -> StdRng {
+        StdRng::seed_from_u64(mix(mix(self.seed ^ stream_id(stream)) ^ self.tick))
+    }
+}
+
+pub fn advance_rng(_: &mut World, resources: &mut Resources) {
+    if let Some(mut rng) = resources.get_mut::<WorldRng>() {
+        rng.tick += 1;
+    }
+}
@@ -0,0 +1,126 @@
+use legion::prelude::*;
+
+use image::RgbImage;
+use image::Rgb;
+
+use serde::Deserialize;
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::Index;
+use crate::Defines;
+use crate::Height;
+use crate::River;
+use crate::Sea;
+use crate::Lake;
+use crate::overlay;
+use crate::dirty::DirtyMap;
+use crate::admin::Admin;
+use crate::map::owner_color;
+use crate::map::snap_borders;
+use crate::map::BorderStyle;
+use crate::minimap::mix;
+use crate::export::ExportManager;
+use crate::writer::Writer;
+
+#[derive(Clone, Deserialize)]
+pub struct RecordingDefines {
+    pub dir: String,
+    pub period: usize,
+    pub political: bool,
+    #[serde(default)]
+    pub borders: Option<BorderStyle>,
+}
+
+// The frame follows the dirty pixels of the display every tick, so writing
+// one out only costs the political blend and the PNG encode.
+pub struct Recorder {
+    pub dir: PathBuf,
+    pub period: usize,
+    pub political: bool,
+    pub borders: Option<BorderStyle>,
+    pub frame: usize,
+    image: RgbImage,
+    tick: usize,
+}
+
+impl Recorder {
+    pub fn new(defines: &RecordingDefines, size: usize, exports: &mut ExportManager) -> Self {
+        let dir = exports.dir("recording", &defines.dir);
+
+        Recorder {
+            dir,
+            period: defines.period,
+            political: defines.political,
+            borders: defines.borders.clone(),
+            frame: 0,
+            image: RgbImage::new(size as u32, size as u32),
+            tick: 0,
+        }
+    }
+}
+
+pub fn record_frames(world: &mut World, resources: &mut Resources) {
+    let mut recorder = match resources.get_mut::<Recorder>() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    let dirty = match resources.get::<DirtyMap>() {
+        Some(dirty) => dirty,
+        None => return,
+    };
+    let size = dirty.size;
+
+    for &index in dirty.pixels.iter() {
+        recorder.image.put_pixel((index % size) as u32, (index / size) as u32, Rgb(dirty.colors[index]));
+    }
+
+    recorder.tick += 1;
+
+    if recorder.period == 0 || recorder.tick % recorder.period != 0 {
+        return;
+    }
+
+    let mut frame = recorder.image.clone();
+
+    if recorder.political {
+        if let Some(admin) = resources.get::<Admin>() {
+            let mut owners: Vec<Option<usize>> = admin
+                .owner
+                .iter()
+                .map(|owner| owner.and_then(|owner| world.get_component::<Index>(owner)).map(|owner| owner.item))
+                .collect();
+
+            if let Some(style) = recorder.borders.as_ref() {
+                let mut water = vec![false; size * size];
+
+                for index in Read::<Index>::query().filter(tag::<Sea>() | tag::<Lake>()).iter(world) {
+                    water[index.item] = true;
+                }
+
+                let wrap = resources.get::<Defines>().unwrap().wrap;
+
+                owners = snap_borders(size, wrap, &overlay::layer::<Height>(world, size), &overlay::layer::<River>(world, size), &water, &owners, style);
+            }
+
+            for (index, owner) in owners.iter().enumerate() {
+                if let Some(&owner) = owner.as_ref() {
+                    let (x, y) = ((index % size) as u32, (index / size) as u32);
+                    let color = mix(frame.get_pixel(x, y).0, owner_color(owner).0, 0.5);
+
+                    frame.put_pixel(x, y, Rgb(color));
+                }
+            }
+        }
+    }
+
+    let path = recorder.dir.join(format!("frame_{:06}.png", recorder.frame));
+
+    match resources.get::<Writer>() {
+        Some(writer) => writer.submit(move || frame.save(path).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))),
+        None => frame.save(path).unwrap(),
+    }
+
+    recorder.frame += 1;
+}
@@ -0,0 +1,277 @@
+use legion::prelude::*;
+
+use ron::de::from_str;
+use ron::ser::to_string;
+
+use std::collections::HashMap;
+
+use crate::Pixel;
+use crate::Lake;
+use crate::Sea;
+use crate::Settlement;
+use crate::Colony;
+use crate::Ruin;
+use crate::Navigable;
+use crate::Ice;
+use crate::Province;
+use crate::Owned;
+use crate::Owns;
+use crate::Location;
+use crate::Name;
+use crate::Water;
+use crate::WaterBase;
+use crate::Aquifer;
+use crate::River;
+use crate::Rain;
+use crate::Heat;
+use crate::Height;
+use crate::Veget;
+use crate::Neighb;
+use crate::RiverBase;
+use crate::VegetBase;
+use crate::HeatBase;
+use crate::Speed;
+use crate::Capacity;
+use crate::Index;
+use crate::Pop;
+use crate::Skill;
+use crate::Building;
+use crate::Land;
+use crate::Biome;
+use crate::Fertility;
+use crate::guild::Guild;
+use crate::guild::Stock;
+use crate::caravan::Caravan;
+use crate::caravan::Ship;
+use crate::caravan::Cargo;
+use crate::caravan::Route;
+use crate::caravan::Progress;
+use crate::garrison::Garrison;
+use crate::garrison::Coverage;
+use crate::ruins::Former;
+use crate::market::Price;
+use crate::crops::Crop;
+use crate::ancestry::Ancestry;
+use crate::personality::Personality;
+use crate::modifier::Modifiers;
+
+pub struct EntityIds {
+    pub ids: HashMap<Entity, u64>,
+    pub entities: HashMap<u64, Entity>,
+}
+
+impl EntityIds {
+    pub fn new() -> Self {
+        EntityIds {
+            ids: HashMap::new(),
+            entities: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, id: u64) {
+        self.ids.insert(entity, id);
+        self.entities.insert(id, entity);
+    }
+
+    pub fn id(&self, entity: Entity) -> Option<u64> {
+        self.ids.get(&entity).cloned()
+    }
+
+    pub fn entity(&self, id: u64) -> Option<Entity> {
+        self.entities.get(&id).cloned()
+    }
+}
+
+pub struct ComponentEntry {
+    pub id: u16,
+    pub name: &'static str,
+    pub serialize: fn(&World, Entity, &EntityIds) -> Option<String>,
+    pub deserialize: fn(&mut World, Entity, &str, &EntityIds) -> Result<(), String>,
+    pub remove: fn(&mut World, Entity),
+}
+
+fn missing(entity: Entity) -> String {
+    format!("entity {:?} does not exist", entity)
+}
+
+macro_rules! value_entry {
+    ($id:expr, $t:ident) => {
+        ComponentEntry {
+            id: $id,
+            name: stringify!($t),
+            serialize: |world, entity, _| {
+                world.get_component::<$t>(entity).map(|component| to_string(&component.item).unwrap())
+            },
+            deserialize: |world, entity, text, _| {
+                let item = from_str(text).map_err(|e| e.to_string())?;
+
+                world.add_component(entity, $t { item }).map_err(|_| missing(entity))
+            },
+            remove: |world, entity| {
+                let _ = world.remove_component::<$t>(entity);
+            },
+        }
+    };
+}
+
+macro_rules! entity_entry {
+    ($id:expr, $t:ident) => {
+        ComponentEntry {
+            id: $id,
+            name: stringify!($t),
+            serialize: |world, entity, ids| {
+                world.get_component::<$t>(entity).and_then(|component| ids.id(component.item)).map(|id| to_string(&id).unwrap())
+            },
+            deserialize: |world, entity, text, ids| {
+                let id: u64 = from_str(text).map_err(|e| e.to_string())?;
+                let item = ids.entity(id).ok_or_else(|| format!("unknown entity id {}", id))?;
+
+                world.add_component(entity, $t { item }).map_err(|_| missing(entity))
+            },
+            remove: |world, entity| {
+                let _ = world.remove_component::<$t>(entity);
+            },
+        }
+    };
+}
+
+macro_rules! entities_entry {
+    ($id:expr, $t:ident) => {
+        ComponentEntry {
+            id: $id,
+            name: stringify!($t),
+            serialize: |world, entity, ids| {
+                world.get_component::<$t>(entity).map(|component| {
+                    let item: Vec<u64> = component.item.iter().filter_map(|&e| ids.id(e)).collect();
+
+                    to_string(&item).unwrap()
+                })
+            },
+            deserialize: |world, entity, text, ids| {
+                let item: Vec<u64> = from_str(text).map_err(|e| e.to_string())?;
+                let item = item
+                    .into_iter()
+                    .map(|id| ids.entity(id).ok_or_else(|| format!("unknown entity id {}", id)))
+                    .collect::<Result<Vec<Entity>, String>>()?;
+
+                world.add_component(entity, $t { item }).map_err(|_| missing(entity))
+            },
+            remove: |world, entity| {
+                let _ = world.remove_component::<$t>(entity);
+            },
+        }
+    };
+}
+
+macro_rules! tag_entry {
+    ($id:expr, $t:ident) => {
+        ComponentEntry {
+            id: $id,
+            name: stringify!($t),
+            serialize: |world, entity, _| {
+                world.get_tag::<$t>(entity).map(|_| String::new())
+            },
+            deserialize: |world, entity, _, _| {
+                world.add_tag(entity, $t).map_err(|_| missing(entity))
+            },
+            remove: |world, entity| {
+                let _ = world.remove_tag::<$t>(entity);
+            },
+        }
+    };
+}
+
+pub struct Registry {
+    pub entries: Vec<ComponentEntry>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry { entries: Vec::new() };
+
+        registry.register(tag_entry!(0, Pixel));
+        registry.register(tag_entry!(1, Lake));
+        registry.register(tag_entry!(2, Sea));
+        registry.register(tag_entry!(3, Settlement));
+        registry.register(tag_entry!(4, Colony));
+        registry.register(tag_entry!(5, Guild));
+        registry.register(tag_entry!(6, Caravan));
+        registry.register(tag_entry!(7, Ship));
+        registry.register(tag_entry!(8, Ruin));
+        registry.register(tag_entry!(9, Navigable));
+        registry.register(tag_entry!(10, Ice));
+        registry.register(tag_entry!(11, Province));
+
+        registry.register(entity_entry!(100, Owned));
+        registry.register(entities_entry!(101, Owns));
+        registry.register(entity_entry!(102, Location));
+        registry.register(entities_entry!(104, Route));
+
+        registry.register(value_entry!(200, Name));
+        registry.register(value_entry!(201, Water));
+        registry.register(value_entry!(202, WaterBase));
+        registry.register(value_entry!(203, Aquifer));
+        registry.register(value_entry!(204, River));
+        registry.register(value_entry!(205, Rain));
+        registry.register(value_entry!(206, Heat));
+        registry.register(value_entry!(207, Height));
+        registry.register(value_entry!(208, Veget));
+        registry.register(value_entry!(209, RiverBase));
+        registry.register(value_entry!(210, VegetBase));
+        registry.register(value_entry!(211, HeatBase));
+        registry.register(value_entry!(212, Speed));
+        registry.register(value_entry!(213, Capacity));
+        registry.register(value_entry!(214, Index));
+        registry.register(value_entry!(215, Pop));
+        registry.register(value_entry!(216, Skill));
+        registry.register(value_entry!(217, Building));
+        registry.register(value_entry!(218, Land));
+        registry.register(value_entry!(219, Stock));
+        registry.register(value_entry!(220, Cargo));
+        registry.register(value_entry!(221, Progress));
+        registry.register(value_entry!(222, Neighb));
+        registry.register(value_entry!(223, Garrison));
+        registry.register(value_entry!(224, Coverage));
+        registry.register(value_entry!(225, Former));
+        registry.register(value_entry!(226, Price));
+        registry.register(value_entry!(227, Crop));
+        registry.register(value_entry!(228, Ancestry));
+        registry.register(value_entry!(229, Modifiers));
+        registry.register(value_entry!(230, Biome));
+        registry.register(value_entry!(231, Fertility));
+        registry.register(value_entry!(232, Personality));
+
+        registry
+    }
+
+    pub fn register(&mut self, entry: ComponentEntry) {
+        assert!(self.by_id(entry.id).is_none(), "component id {} registered twice", entry.id);
+
+        self.entries.push(entry);
+    }
+
+    pub fn by_id(&self, id: u16) -> Option<&ComponentEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&ComponentEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn serialize(&self, world: &World, entity: Entity, ids: &EntityIds) -> Vec<(u16, String)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| (entry.serialize)(world, entity, ids).map(|text| (entry.id, text)))
+            .collect()
+    }
+
+    pub fn deserialize(&self, world: &mut World, entity: Entity, components: &Vec<(u16, String)>, ids: &EntityIds) -> Result<(), String> {
+        for (id, text) in components.iter() {
+            let entry = self.by_id(*id).ok_or_else(|| format!("unknown component id {}", id))?;
+
+            (entry.deserialize)(world, entity, text, ids)?;
+        }
+
+        Ok(())
+    }
+}
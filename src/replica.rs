@@ -0,0 +1,91 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use crate::Defines;
+use crate::registry::Registry;
+use crate::save::Baseline;
+use crate::save::History;
+use crate::save::snapshot;
+use crate::save::delta_snapshot;
+use crate::server::StateServer;
+
+// Every `period` ticks the clients of the state server are sent the world in
+// the save container format, compressed at `level`.
+#[derive(Clone, Deserialize)]
+pub struct ReplicaDefines {
+    pub period: usize,
+    pub level: i32,
+    pub deltas: usize,
+}
+
+pub struct Replica {
+    pub period: usize,
+    pub level: i32,
+    pub deltas: usize,
+    baseline: Option<Baseline>,
+    joined: usize,
+    since: usize,
+    tick: usize,
+}
+
+impl Replica {
+    pub fn new(defines: &ReplicaDefines) -> Self {
+        Replica {
+            period: defines.period,
+            level: defines.level,
+            deltas: defines.deltas,
+            baseline: None,
+            joined: 0,
+            since: 0,
+            tick: 0,
+        }
+    }
+}
+
+// A full snapshot goes out whenever a client has joined since the last one,
+// and otherwise after `deltas` deltas against it, so a client only ever
+// needs the newest snapshot and the newest delta. A client that joins while
+// one is being sent may get a delta first, which it skips until the full
+// snapshot that follows.
+pub fn replicate(world: &mut World, resources: &mut Resources) {
+    let mut replica = match resources.get_mut::<Replica>() {
+        Some(replica) => replica,
+        None => return,
+    };
+
+    replica.tick += 1;
+
+    if replica.period == 0 || replica.tick % replica.period != 0 {
+        return;
+    }
+
+    let server = match resources.get::<StateServer>() {
+        Some(server) => server,
+        None => return,
+    };
+    let size = resources.get::<Defines>().unwrap().size;
+    let registry = resources.get::<Registry>().unwrap();
+    let history = History::of(resources);
+    let replica = &mut *replica;
+    let joined = server.joined();
+    let due = joined == replica.joined && replica.since < replica.deltas;
+    let data = match replica.baseline.as_mut() {
+        Some(baseline) if due => {
+            replica.since += 1;
+
+            delta_snapshot(world, &registry, baseline, &history, size).unwrap()
+        }
+        _ => {
+            let (data, baseline) = snapshot(world, &registry, &history, size).unwrap();
+
+            replica.baseline = Some(baseline);
+            replica.joined = joined;
+            replica.since = 0;
+
+            data
+        }
+    };
+
+    server.broadcast_bytes(&data.encode(replica.level).unwrap());
+}
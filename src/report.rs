@@ -0,0 +1,262 @@
+use legion::prelude::*;
+
+use image::RgbaImage;
+
+use serde::Deserialize;
+
+use std::io;
+use std::fs;
+use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Owns;
+use crate::Owned;
+use crate::Pixel;
+use crate::Settlement;
+use crate::Province;
+use crate::symbol::Symbol;
+use crate::timeline::Timeline;
+use crate::timeline::Snapshot;
+use crate::timeline::Stat;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::export::ExportManager;
+use crate::writer::Writer;
+
+// Written once at `tick`, taken as the end of the run. `top` rows go in each
+// table, and the last `entries` chronicle entries of the `highlights` kinds
+// are quoted.
+#[derive(Clone, Deserialize)]
+pub struct ReportDefines {
+    pub tick: usize,
+    pub dir: String,
+    pub top: usize,
+    pub highlights: Vec<Symbol>,
+    pub entries: usize,
+}
+
+pub struct WorldReport {
+    pub at: usize,
+    pub dir: PathBuf,
+    tick: usize,
+}
+
+impl WorldReport {
+    pub fn new(defines: &ReportDefines, exports: &mut ExportManager) -> Self {
+        WorldReport {
+            at: defines.tick,
+            dir: exports.dir("report", &defines.dir),
+            tick: 0,
+        }
+    }
+}
+
+// Everything the report holds, gathered during the tick so writing it out
+// can be left to the writer.
+pub struct Report {
+    markdown: String,
+    charts: Vec<(&'static str, String)>,
+    map: RgbaImage,
+}
+
+impl Report {
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+
+        fs::write(dir.join("index.md"), &self.markdown)?;
+
+        for (name, svg) in self.charts.iter() {
+            fs::write(dir.join(format!("{}.svg", name)), svg)?;
+        }
+
+        self.map.save(dir.join("political.png")).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+fn cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+// A line chart of `values` over `ticks`, labelled with the range it spans.
+fn chart(title: &str, ticks: &[usize], values: &[f32]) -> String {
+    let (width, height, pad) = (480., 160., 24.);
+    let max = values.iter().cloned().fold(0., f32::max).max(1e-6);
+    let last = ticks.last().cloned().unwrap_or(0).max(1) as f32;
+    let points: Vec<String> = ticks
+        .iter()
+        .zip(values.iter())
+        .map(|(&tick, &value)| format!(
+            "{:.1},{:.1}",
+            pad + tick as f32 / last * (width - 2. * pad),
+            height - pad - value / max * (height - 2. * pad),
+        ))
+        .collect();
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n\
+         <text x=\"{p}\" y=\"16\" font-size=\"12\">{title}</text>\n\
+         <text x=\"{p}\" y=\"{b}\" font-size=\"10\">0 .. {max:.0} over {last} ticks</text>\n\
+         <polyline fill=\"none\" stroke=\"black\" points=\"{points}\"/>\n\
+         </svg>\n",
+        w = width, h = height, p = pad, b = height - 6., title = title, max = max, last = last, points = points.join(" "),
+    )
+}
+
+fn political(world: &World, timeline: &Timeline, size: usize) -> RgbaImage {
+    let indices: HashMap<Entity, u32> = Read::<Index>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(world)
+        .map(|(settlement, index)| (settlement, index.item as u32))
+        .collect();
+    let mut owners = vec![None; size * size];
+
+    for (index, owned) in <(Read<Index>, TryRead<Owned>)>::query().filter(tag::<Pixel>()).iter(world) {
+        owners[index.item] = owned.and_then(|owned| indices.get(&owned.item).cloned());
+    }
+
+    timeline.render(&Snapshot { tick: 0, pop: 0., settlements: indices.len(), owners }, size)
+}
+
+// Realms are ranked by the people of the settlements they administer, and
+// measured in pixels.
+fn realms(world: &World, top: usize) -> Vec<(String, usize, f32)> {
+    let mut realms: Vec<(String, usize, f32)> = Read::<Owns>::query()
+        .filter(!tag::<Province>())
+        .iter_entities(world)
+        .filter(|(_, owns)| !owns.item.is_empty())
+        .map(|(owner, owns)| {
+            let pop = owns.item.iter().filter_map(|&pixel| world.get_component::<Pop>(pixel).map(|pop| pop.item)).sum();
+
+            (site_name(world, owner), owns.item.len(), pop)
+        })
+        .collect();
+
+    realms.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+    realms.truncate(top);
+
+    realms
+}
+
+fn settlements(world: &World, size: usize, top: usize) -> Vec<(String, usize, usize, f32)> {
+    let mut settlements: Vec<(String, usize, usize, f32)> = <(Read<Index>, Read<Pop>)>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(world)
+        .map(|(settlement, (index, pop))| (site_name(world, settlement), index.item % size, index.item / size, pop.item))
+        .collect();
+
+    settlements.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap());
+    settlements.truncate(top);
+
+    settlements
+}
+
+pub fn build_report(world: &World, defines: &Defines, report: &ReportDefines, timeline: &Timeline, chronicle: Option<&Chronicle>, seed: u64, tick: usize) -> Report {
+    let size = defines.size;
+    let stats: &Vec<Stat> = &timeline.stats;
+    let ticks: Vec<usize> = stats.iter().map(|stat| stat.tick).collect();
+    let pop: Vec<f32> = stats.iter().map(|stat| stat.pop).collect();
+    let count: Vec<f32> = stats.iter().map(|stat| stat.settlements as f32).collect();
+    let mut md = String::new();
+
+    md += &format!("# World report\n\nSeed {}, size {}, {} ticks.\n\n", seed, size, tick);
+    md += "## Political map\n\n![political map](political.png)\n\n";
+    md += "## Statistics\n\n![population](population.svg)\n\n![settlements](settlements.svg)\n\n";
+
+    if let Some(last) = stats.last() {
+        md += &format!("Final population {:.0} in {} settlements.\n\n", last.pop, last.settlements);
+    }
+
+    md += "## Largest realms\n\n| Realm | Pixels | Population |\n|---|---:|---:|\n";
+
+    for (name, pixels, pop) in realms(world, report.top).into_iter() {
+        md += &format!("| {} | {} | {:.0} |\n", cell(&name), pixels, pop);
+    }
+
+    md += "\n## Largest settlements\n\n| Settlement | x | y | Population |\n|---|---:|---:|---:|\n";
+
+    for (name, x, y, pop) in settlements(world, size, report.top).into_iter() {
+        md += &format!("| {} | {} | {} | {:.0} |\n", cell(&name), x, y, pop);
+    }
+
+    if let Some(chronicle) = chronicle {
+        let mut kinds: BTreeMap<String, usize> = BTreeMap::new();
+
+        for entry in chronicle.entries.iter() {
+            *kinds.entry(entry.kind.to_string()).or_insert(0) += 1;
+        }
+
+        md += "\n## Chronicle\n\n| Kind | Entries |\n|---|---:|\n";
+
+        for (kind, n) in kinds.iter() {
+            md += &format!("| {} | {} |\n", cell(kind), n);
+        }
+
+        let highlights: Vec<_> = chronicle.entries.iter().filter(|entry| report.highlights.contains(&entry.kind)).collect();
+
+        md += "\n";
+
+        for entry in highlights[highlights.len().saturating_sub(report.entries)..].iter() {
+            md += &format!("- tick {}: {}\n", entry.tick, entry.text);
+        }
+    }
+
+    md += "\n## Reproducing\n\nThe mods applied on top of `defines.ron`, in order:\n\n";
+
+    for m in defines.mods.iter() {
+        md += &format!("- {}\n", m);
+    }
+
+    md += &format!("\nRun with seed {} ({} configured, {} tries).\n", seed, defines.seed, defines.seed_tries);
+
+    Report {
+        markdown: md,
+        charts: vec![
+            ("population", chart("Population", &ticks, &pop)),
+            ("settlements", chart("Settlements", &ticks, &count)),
+        ],
+        map: political(world, timeline, size),
+    }
+}
+
+// The defines.ron and worldgen config the run was started from are copied
+// alongside, so the bundle carries everything needed to run it again.
+pub fn export_report_at(world: &mut World, resources: &mut Resources) {
+    let mut export = match resources.get_mut::<WorldReport>() {
+        Some(export) => export,
+        None => return,
+    };
+
+    export.tick += 1;
+
+    if export.tick != export.at {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let report = match defines.report.as_ref() {
+        Some(report) => report,
+        None => return,
+    };
+    let timeline = resources.get::<Timeline>().unwrap();
+    let chronicle = resources.get::<Chronicle>();
+    let seed = resources.get::<ExportManager>().map_or(defines.seed, |exports| exports.seed);
+    let built = build_report(world, &defines, report, &timeline, chronicle.as_deref(), seed, export.tick);
+    let dir = export.dir.clone();
+    let worldgen = PathBuf::from(&defines.worldgen);
+    let job = move || {
+        fs::copy("defines.ron", dir.join("defines.ron"))?;
+        fs::copy(&worldgen, dir.join("worldgen.ron"))?;
+        built.write(&dir)
+    };
+
+    match resources.get::<Writer>() {
+        Some(writer) => writer.submit(job),
+        None => job().unwrap(),
+    }
+}
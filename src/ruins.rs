@@ -0,0 +1,157 @@
+use legion::prelude::*;
+
+use serde::Serialize;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pop;
+use crate::Name;
+use crate::Building;
+use crate::Location;
+use crate::Settlement;
+use crate::Colony;
+use crate::Ruin;
+use crate::symbol::Symbol;
+use crate::decay::spawn_ruin;
+use crate::map::offset_between;
+use crate::tags;
+use crate::tags::TagEvents;
+
+#[derive(Clone, Deserialize)]
+pub struct RuinsDefines {
+    pub abandon: f32,
+    pub radius: usize,
+    pub salvage: f32,
+}
+
+// The largest population a ruined settlement ever had.
+pub struct Former { pub item: f32 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChronicleEntry {
+    pub tick: usize,
+    pub site: usize,
+    pub kind: Symbol,
+    pub text: String,
+}
+
+pub struct Chronicle {
+    pub entries: Vec<ChronicleEntry>,
+    pub tick: usize,
+    peaks: HashMap<Entity, f32>,
+}
+
+impl Chronicle {
+    pub fn new() -> Self {
+        Chronicle {
+            entries: Vec::new(),
+            tick: 0,
+            peaks: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, site: usize, kind: Symbol, text: String) {
+        self.entries.push(ChronicleEntry { tick: self.tick, site, kind, text });
+    }
+
+    pub fn site(&self, site: usize) -> impl Iterator<Item = &ChronicleEntry> {
+        self.entries.iter().filter(move |entry| entry.site == site)
+    }
+}
+
+pub fn site_name(world: &World, entity: Entity) -> String {
+    world.get_component::<Name>(entity).map_or(String::from("a nameless place"), |name| name.item.to_string())
+}
+
+// Settlements are tracked at their peak, and one that has dwindled below
+// `abandon` after once being larger is left as a ruin carrying its name, its
+// peak and its buildings.
+pub fn abandon_settlements(world: &mut World, resources: &mut Resources) {
+    let mut chronicle = match resources.get_mut::<Chronicle>() {
+        Some(chronicle) => chronicle,
+        None => return,
+    };
+    let defines = resources.get::<Defines>().unwrap();
+    let mut events = resources.get_mut::<TagEvents>();
+    let abandon = defines.ruins.abandon;
+
+    chronicle.tick += 1;
+
+    let query = <(Read<Index>, Read<Pop>, Read<Building>)>::query().filter(tag::<Settlement>());
+    let mut abandoned = Vec::new();
+
+    for (settlement, (index, pop, building)) in query.iter_entities(&*world) {
+        let peak = chronicle.peaks.entry(settlement).or_insert(0.);
+
+        *peak = peak.max(pop.item);
+
+        if pop.item < abandon && *peak > abandon {
+            abandoned.push((settlement, index.item, *peak, building.item.clone()));
+        }
+    }
+
+    for (settlement, index, peak, buildings) in abandoned.into_iter() {
+        let name = site_name(world, settlement);
+        let ruin = spawn_ruin(world, settlement, buildings.clone());
+
+        if let Some(name) = world.get_component::<Name>(settlement).map(|name| name.item) {
+            world.add_component(ruin, Name { item: name }).unwrap();
+        }
+
+        world.add_component(ruin, Former { item: peak }).unwrap();
+        world.add_component(settlement, Building { item: vec![0.; buildings.len()] }).unwrap();
+        tags::remove_tag::<Settlement>(world, events.as_deref_mut(), settlement);
+        tags::remove_tag::<Colony>(world, events.as_deref_mut(), settlement);
+
+        chronicle.peaks.remove(&settlement);
+        chronicle.record(index, Symbol::new("Abandoned"), format!("{} was abandoned, having once held {} people", name, peak as usize));
+    }
+}
+
+// A settlement founded within `radius` of ruins salvages a share of their
+// buildings, and the ruins are gone for good.
+pub fn resettle(world: &mut World, chronicle: &mut Chronicle, defines: &Defines, settlement: Entity) {
+    let size = defines.size;
+    let center = match world.get_component::<Index>(settlement) {
+        Some(index) => index.item,
+        None => return,
+    };
+    let radius = defines.ruins.radius as f64;
+
+    let query = <(Read<Location>, Read<Building>)>::query().filter(tag::<Ruin>());
+    let near: Vec<(Entity, usize, Vec<f32>)> = query
+        .iter_entities(&*world)
+        .filter_map(|(ruin, (location, building))| {
+            let site = world.get_component::<Index>(location.item)?.item;
+            let (dx, dy) = offset_between(center, site, size, defines.wrap);
+
+            if dx.abs() <= radius && dy.abs() <= radius {
+                Some((ruin, site, building.item.clone()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    if near.is_empty() {
+        return;
+    }
+
+    let name = site_name(world, settlement);
+
+    for (ruin, site, buildings) in near.into_iter() {
+        let former = site_name(world, ruin);
+
+        if let Some(mut building) = world.get_component_mut::<Building>(settlement) {
+            for (level, ruined) in building.item.iter_mut().zip(buildings.iter()) {
+                *level += ruined * defines.ruins.salvage;
+            }
+        }
+
+        world.delete(ruin);
+        chronicle.record(site, Symbol::new("Resettled"), format!("{} was built from the ruins of {}", name, former));
+    }
+}
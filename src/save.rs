@@ -0,0 +1,560 @@
+use legion::prelude::*;
+
+use serde::Serialize;
+use serde::Deserialize;
+
+use ron::de::from_str;
+use ron::ser::to_string;
+
+use std::io;
+use std::io::Read as IoRead;
+use std::io::Write as IoWrite;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::Defines;
+use crate::Index;
+use crate::Pixel;
+use crate::registry::Registry;
+use crate::registry::EntityIds;
+use crate::ruins::Chronicle;
+use crate::ruins::ChronicleEntry;
+use crate::timeline::Timeline;
+use crate::timeline::Stat;
+use crate::export::ExportManager;
+use crate::writer::Writer;
+
+const MAGIC: &[u8; 4] = b"P4SV";
+const VERSION: u32 = 4;
+const REGION: usize = 64;
+
+pub type Record = (u64, Vec<(u16, String)>);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SaveMeta {
+    pub version: u32,
+    pub size: usize,
+    pub entities: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub name: String,
+    pub offset: u64,
+    pub len: u64,
+    pub entities: usize,
+}
+
+// Chunks that hold the world's history rather than entity records.
+const HISTORY: [&str; 2] = ["chronicle", "statistics"];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub tick: usize,
+    pub chronicle: Vec<ChronicleEntry>,
+    pub stats: Vec<Stat>,
+}
+
+impl History {
+    pub fn capture(chronicle: &Chronicle, timeline: &Timeline) -> Self {
+        History {
+            tick: chronicle.tick,
+            chronicle: chronicle.entries.clone(),
+            stats: timeline.stats.clone(),
+        }
+    }
+
+    // What the resources hold, without the chronicle when there is none.
+    pub fn of(resources: &Resources) -> Self {
+        match (resources.get::<Chronicle>(), resources.get::<Timeline>()) {
+            (Some(chronicle), Some(timeline)) => History::capture(&chronicle, &timeline),
+            (None, Some(timeline)) => History { stats: timeline.stats.clone(), ..History::default() },
+            _ => History::default(),
+        }
+    }
+
+    pub fn restore(self, chronicle: &mut Chronicle, timeline: &mut Timeline) {
+        chronicle.tick = self.tick;
+        chronicle.entries = self.chronicle;
+        timeline.stats = self.stats;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Loading;
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub fn region_name(index: usize, size: usize) -> String {
+    format!("region_{}_{}", (index % size) / REGION, (index / size) / REGION)
+}
+
+fn write_chunk<W: IoWrite + Seek>(out: &mut W, text: &str, level: i32) -> io::Result<(u64, u64)> {
+    let data = zstd::encode_all(text.as_bytes(), level)?;
+    let offset = out.seek(SeekFrom::Current(0))?;
+
+    out.write_all(&data)?;
+
+    Ok((offset, data.len() as u64))
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> io::Result<String> {
+    let mut data = vec![0; len as usize];
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+
+    String::from_utf8(zstd::decode_all(&data[..])?).map_err(invalid)
+}
+
+pub struct Baseline {
+    pub ids: EntityIds,
+    pub records: HashMap<u64, Vec<(u16, String)>>,
+    next_id: u64,
+}
+
+impl Baseline {
+    fn assign(&mut self, entity: Entity) -> u64 {
+        match self.ids.id(entity) {
+            Some(id) => id,
+            None => {
+                let id = self.next_id;
+
+                self.next_id += 1;
+                self.ids.insert(entity, id);
+
+                id
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Delta {
+    pub changed: Vec<Record>,
+    pub removed: Vec<u64>,
+    pub removed_components: Vec<(u64, Vec<u16>)>,
+}
+
+fn chunk_of(world: &World, entity: Entity, size: usize) -> String {
+    match world.get_component::<Index>(entity) {
+        Some(index) if world.get_tag::<Pixel>(entity).is_some() => region_name(index.item, size),
+        _ => "entities".to_string(),
+    }
+}
+
+// Layout: magic, version, the zstd chunks back to back, the zstd index of
+// those chunks, then the index offset as the last eight bytes of the file.
+fn write_container<W: IoWrite + Seek>(file: &mut W, meta: &SaveMeta, chunks: &Vec<(String, String, usize)>, level: i32) -> io::Result<()> {
+    let mut index = Vec::new();
+
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+
+    let (offset, len) = write_chunk(file, &to_string(meta).map_err(invalid)?, level)?;
+
+    index.push(ChunkInfo { name: "meta".to_string(), offset, len, entities: 0 });
+
+    for (name, text, entities) in chunks.iter() {
+        let (offset, len) = write_chunk(file, text, level)?;
+
+        index.push(ChunkInfo { name: name.clone(), offset, len, entities: *entities });
+    }
+
+    let (offset, _) = write_chunk(file, &to_string(&index).map_err(invalid)?, level)?;
+
+    file.write_all(&offset.to_le_bytes())?;
+
+    Ok(())
+}
+
+// The world serialized but not yet compressed or written, which is the part
+// that can be left to another thread.
+pub struct SaveData {
+    meta: SaveMeta,
+    chunks: Vec<(String, String, usize)>,
+}
+
+impl SaveData {
+    pub fn write<P: AsRef<Path>>(&self, level: i32, path: P) -> io::Result<()> {
+        write_container(&mut File::create(path)?, &self.meta, &self.chunks, level)
+    }
+
+    // The same container in memory, for sending rather than writing.
+    pub fn encode(&self, level: i32) -> io::Result<Vec<u8>> {
+        let mut out = io::Cursor::new(Vec::new());
+
+        write_container(&mut out, &self.meta, &self.chunks, level)?;
+
+        Ok(out.into_inner())
+    }
+}
+
+pub fn save<P: AsRef<Path>>(world: &World, registry: &Registry, history: &History, size: usize, level: i32, path: P) -> io::Result<Baseline> {
+    let (data, baseline) = snapshot(world, registry, history, size)?;
+
+    data.write(level, path)?;
+
+    Ok(baseline)
+}
+
+pub fn snapshot(world: &World, registry: &Registry, history: &History, size: usize) -> io::Result<(SaveData, Baseline)> {
+    let entities: Vec<Entity> = world.iter_entities().collect();
+    let mut baseline = Baseline { ids: EntityIds::new(), records: HashMap::new(), next_id: 0 };
+    let mut chunks: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+
+    for &entity in entities.iter() {
+        baseline.assign(entity);
+    }
+
+    for &entity in entities.iter() {
+        let id = baseline.ids.id(entity).unwrap();
+        let components = registry.serialize(world, entity, &baseline.ids);
+
+        chunks.entry(chunk_of(world, entity, size)).or_insert_with(Vec::new).push((id, components.clone()));
+        baseline.records.insert(id, components);
+    }
+
+    let meta = SaveMeta { version: VERSION, size, entities: entities.len() };
+    let mut out = Vec::new();
+
+    for (name, records) in chunks.into_iter() {
+        let len = records.len();
+
+        out.push((name, to_string(&records).map_err(invalid)?, len));
+    }
+
+    out.extend(history_chunks(history)?);
+
+    Ok((SaveData { meta, chunks: out }, baseline))
+}
+
+fn history_chunks(history: &History) -> io::Result<Vec<(String, String, usize)>> {
+    Ok(vec![
+        (HISTORY[0].to_string(), to_string(&(history.tick, &history.chronicle)).map_err(invalid)?, 0),
+        (HISTORY[1].to_string(), to_string(&history.stats).map_err(invalid)?, 0),
+    ])
+}
+
+// A delta holds everything that differs from the full snapshot the baseline
+// was taken from, so restoring only ever needs the snapshot and the newest
+// delta. Entities created since the snapshot get fresh ids in the baseline.
+pub fn save_delta<P: AsRef<Path>>(world: &World, registry: &Registry, baseline: &mut Baseline, history: &History, size: usize, level: i32, path: P) -> io::Result<()> {
+    delta_snapshot(world, registry, baseline, history, size)?.write(level, path)
+}
+
+// The history is small next to the world, so a delta carries all of it.
+pub fn delta_snapshot(world: &World, registry: &Registry, baseline: &mut Baseline, history: &History, size: usize) -> io::Result<SaveData> {
+    let entities: Vec<Entity> = world.iter_entities().collect();
+    let mut delta = Delta::default();
+    let mut alive = Vec::new();
+
+    for &entity in entities.iter() {
+        alive.push(baseline.assign(entity));
+    }
+
+    for (&entity, &id) in entities.iter().zip(alive.iter()) {
+        let components = registry.serialize(world, entity, &baseline.ids);
+
+        match baseline.records.get(&id) {
+            Some(base) => {
+                let changed: Vec<(u16, String)> = components
+                    .iter()
+                    .filter(|component| !base.contains(component))
+                    .cloned()
+                    .collect();
+                let removed: Vec<u16> = base
+                    .iter()
+                    .filter(|(cid, _)| !components.iter().any(|(c, _)| c == cid))
+                    .map(|&(cid, _)| cid)
+                    .collect();
+
+                if !changed.is_empty() {
+                    delta.changed.push((id, changed));
+                }
+                if !removed.is_empty() {
+                    delta.removed_components.push((id, removed));
+                }
+            },
+            None => delta.changed.push((id, components)),
+        }
+    }
+
+    let alive: HashSet<u64> = alive.into_iter().collect();
+
+    delta.removed = baseline.records.keys().filter(|id| !alive.contains(id)).cloned().collect();
+    delta.removed.sort();
+
+    let meta = SaveMeta { version: VERSION, size, entities: entities.len() };
+    let len = delta.changed.len();
+    let mut chunks = vec![("delta".to_string(), to_string(&delta).map_err(invalid)?, len)];
+
+    chunks.extend(history_chunks(history)?);
+
+    Ok(SaveData { meta, chunks })
+}
+
+pub fn read_index<P: AsRef<Path>>(path: P) -> io::Result<Vec<ChunkInfo>> {
+    let mut file = File::open(path)?;
+    let mut header = [0; 8];
+
+    file.read_exact(&mut header)?;
+
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a save file"));
+    }
+
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    if version != VERSION {
+        return Err(invalid(format!("save version {} does not match {}", version, VERSION)));
+    }
+
+    let end = file.seek(SeekFrom::End(-8))?;
+    let mut offset = [0; 8];
+
+    file.read_exact(&mut offset)?;
+
+    let offset = u64::from_le_bytes(offset);
+
+    from_str(&read_at(&mut file, offset, end - offset)?).map_err(invalid)
+}
+
+pub fn read_chunk<P: AsRef<Path>>(path: P, chunk: &ChunkInfo) -> io::Result<String> {
+    read_at(&mut File::open(path)?, chunk.offset, chunk.len)
+}
+
+pub fn read_meta<P: AsRef<Path>>(path: P) -> io::Result<SaveMeta> {
+    let path = path.as_ref();
+    let index = read_index(path)?;
+    let chunk = index.iter().find(|chunk| chunk.name == "meta").ok_or_else(|| invalid("missing meta chunk"))?;
+
+    from_str(&read_chunk(path, chunk)?).map_err(invalid)
+}
+
+// Reads only the history chunks, so a save's chronicle and statistics can be
+// pulled out without loading the world. Saves from before they were added
+// give an empty history.
+pub fn read_history<P: AsRef<Path>>(path: P) -> io::Result<History> {
+    let path = path.as_ref();
+    let index = read_index(path)?;
+    let mut history = History::default();
+
+    if let Some(chunk) = index.iter().find(|chunk| chunk.name == HISTORY[0]) {
+        let (tick, chronicle) = from_str(&read_chunk(path, chunk)?).map_err(invalid)?;
+
+        history.tick = tick;
+        history.chronicle = chronicle;
+    }
+    if let Some(chunk) = index.iter().find(|chunk| chunk.name == HISTORY[1]) {
+        history.stats = from_str(&read_chunk(path, chunk)?).map_err(invalid)?;
+    }
+
+    Ok(history)
+}
+
+pub fn read_records<P: AsRef<Path>>(path: P, chunk: &ChunkInfo) -> io::Result<Vec<Record>> {
+    from_str(&read_chunk(path, chunk)?).map_err(invalid)
+}
+
+// Entities are created first so that references between them resolve,
+// then each one is filled in through the registry.
+pub fn load_records(world: &mut World, registry: &Registry, records: &Vec<Record>, ids: &mut EntityIds) -> io::Result<()> {
+    let mut created = Vec::new();
+
+    for (id, _) in records.iter() {
+        if ids.entity(*id).is_none() {
+            let entity = world.insert((), vec![(Loading,)])[0];
+
+            ids.insert(entity, *id);
+            created.push(entity);
+        }
+    }
+
+    for (id, components) in records.iter() {
+        registry.deserialize(world, ids.entity(*id).unwrap(), components, ids).map_err(invalid)?;
+    }
+
+    for entity in created.into_iter() {
+        world.remove_component::<Loading>(entity).map_err(|_| invalid("entity vanished while loading"))?;
+    }
+
+    Ok(())
+}
+
+pub fn load<P: AsRef<Path>>(world: &mut World, registry: &Registry, path: P) -> io::Result<EntityIds> {
+    let path = path.as_ref();
+    let index = read_index(path)?;
+    let mut records = Vec::new();
+    let mut ids = EntityIds::new();
+
+    for chunk in index.iter().filter(|chunk| chunk.name != "meta" && !HISTORY.contains(&chunk.name.as_str())) {
+        records.extend(read_records(path, chunk)?);
+    }
+
+    load_records(world, registry, &records, &mut ids)?;
+
+    Ok(ids)
+}
+
+pub fn load_delta<P: AsRef<Path>, Q: AsRef<Path>>(world: &mut World, registry: &Registry, base: P, delta: Q) -> io::Result<EntityIds> {
+    let delta = delta.as_ref();
+    let mut ids = load(world, registry, base)?;
+    let index = read_index(delta)?;
+    let chunk = index.iter().find(|chunk| chunk.name == "delta").ok_or_else(|| invalid("missing delta chunk"))?;
+    let delta: Delta = from_str(&read_chunk(delta, chunk)?).map_err(invalid)?;
+
+    for id in delta.removed.iter() {
+        if let Some(entity) = ids.entity(*id) {
+            world.delete(entity);
+        }
+    }
+
+    for (id, components) in delta.removed_components.iter() {
+        if let Some(entity) = ids.entity(*id) {
+            for cid in components.iter() {
+                if let Some(entry) = registry.by_id(*cid) {
+                    (entry.remove)(world, entity);
+                }
+            }
+        }
+    }
+
+    load_records(world, registry, &delta.changed, &mut ids)?;
+
+    Ok(ids)
+}
+
+// Loads a save, and the delta on top of it if there is one, into a running
+// world. The chronicle and statistics come from the newest file, since a
+// delta carries the whole history, and replace those in the resources.
+pub fn load_game<P: AsRef<Path>, Q: AsRef<Path>>(world: &mut World, resources: &mut Resources, base: P, delta: Option<Q>) -> io::Result<EntityIds> {
+    let registry = resources.get::<Registry>().ok_or_else(|| invalid("missing registry"))?;
+    let (ids, history) = match delta {
+        Some(delta) => (load_delta(world, &registry, &base, &delta)?, read_history(&delta)?),
+        None => (load(world, &registry, &base)?, read_history(&base)?),
+    };
+
+    if let (Some(mut chronicle), Some(mut timeline)) = (resources.get_mut::<Chronicle>(), resources.get_mut::<Timeline>()) {
+        history.restore(&mut chronicle, &mut timeline);
+    }
+
+    Ok(ids)
+}
+
+// Every `period` ticks the world is saved, in full at first and then as
+// `deltas` deltas against that snapshot, written beside it, before the next
+// full one.
+#[derive(Clone, Deserialize)]
+pub struct AutosaveDefines {
+    pub period: usize,
+    pub path: String,
+    pub level: i32,
+    pub deltas: usize,
+}
+
+pub struct Autosave {
+    pub period: usize,
+    pub level: i32,
+    pub path: PathBuf,
+    pub delta: PathBuf,
+    pub stats: PathBuf,
+    pub deltas: usize,
+    baseline: Option<Baseline>,
+    since: usize,
+    tick: usize,
+}
+
+impl Autosave {
+    pub fn new(defines: &AutosaveDefines, exports: &mut ExportManager) -> Self {
+        Autosave {
+            period: defines.period,
+            level: defines.level,
+            path: exports.path("autosave", &defines.path),
+            delta: exports.path("autosave_delta", &format!("{}.delta", defines.path)),
+            stats: exports.path("statistics", "statistics.csv"),
+            deltas: defines.deltas,
+            baseline: None,
+            since: 0,
+            tick: 0,
+        }
+    }
+}
+
+fn stats_csv(stats: &Vec<Stat>) -> String {
+    let mut csv = String::from("tick,pop,settlements\n");
+
+    for stat in stats.iter() {
+        csv += &format!("{},{},{}\n", stat.tick, stat.pop, stat.settlements);
+    }
+
+    csv
+}
+
+// Serializing has to happen on the tick, since it reads the world, but the
+// compression and the writes go to the Writer.
+pub fn autosave(world: &mut World, resources: &mut Resources) {
+    let mut autosave = match resources.get_mut::<Autosave>() {
+        Some(autosave) => autosave,
+        None => return,
+    };
+
+    autosave.tick += 1;
+
+    if autosave.period == 0 || autosave.tick % autosave.period != 0 {
+        return;
+    }
+
+    let size = resources.get::<Defines>().unwrap().size;
+    let registry = resources.get::<Registry>().unwrap();
+    let history = History::of(resources);
+    let autosave = &mut *autosave;
+    let due = autosave.since < autosave.deltas;
+    let (data, path, stale) = match autosave.baseline.as_mut() {
+        Some(baseline) if due => {
+            autosave.since += 1;
+
+            (delta_snapshot(world, &registry, baseline, &history, size).unwrap(), autosave.delta.clone(), None)
+        }
+        _ => {
+            let (data, baseline) = snapshot(world, &registry, &history, size).unwrap();
+
+            autosave.baseline = Some(baseline);
+            autosave.since = 0;
+
+            (data, autosave.path.clone(), Some(autosave.delta.clone()))
+        }
+    };
+    let (level, stats) = (autosave.level, autosave.stats.clone());
+    let csv = stats_csv(&history.stats);
+
+    // A delta left from the last snapshot no longer applies to the new one.
+    let job = move || {
+        data.write(level, path)?;
+
+        match stale.map(fs::remove_file) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    };
+
+    match resources.get::<Writer>() {
+        Some(writer) => {
+            writer.submit(job);
+            writer.submit(move || fs::write(stats, csv));
+        }
+        None => {
+            job().unwrap();
+            fs::write(stats, csv).unwrap();
+        }
+    }
+}
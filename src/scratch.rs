@@ -0,0 +1,125 @@
+use legion::prelude::*;
+
+use std::hash::Hash;
+use std::cell::RefCell;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::LoopEvent;
+
+pub trait Reusable: Default {
+    fn clear(&mut self);
+}
+
+impl<T> Reusable for Vec<T> {
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+}
+
+impl<K: Hash + Eq> Reusable for HashSet<K> {
+    fn clear(&mut self) {
+        HashSet::clear(self);
+    }
+}
+
+impl<K: Hash + Eq, V> Reusable for HashMap<K, V> {
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+}
+
+pub struct Pool<T> {
+    free: Vec<T>,
+    lent: usize,
+    peak: usize,
+}
+
+impl<T: Reusable> Pool<T> {
+    pub fn new() -> Self {
+        Pool {
+            free: Vec::new(),
+            lent: 0,
+            peak: 0,
+        }
+    }
+
+    pub fn take(&mut self) -> T {
+        self.lent += 1;
+        self.peak = self.peak.max(self.lent);
+        self.free.pop().unwrap_or_default()
+    }
+
+    pub fn give(&mut self, mut item: T) {
+        item.clear();
+        self.lent = self.lent.saturating_sub(1);
+        self.free.push(item);
+    }
+
+    // Buffers that were never given back are simply gone; the free list is
+    // trimmed to what this tick needed at most so it can't grow unbounded.
+    fn reset(&mut self) {
+        self.free.truncate(self.peak);
+        self.lent = 0;
+        self.peak = 0;
+    }
+}
+
+pub struct Scratch {
+    pub floats: Pool<Vec<f32>>,
+    pub indices: Pool<Vec<usize>>,
+    pub entities: Pool<Vec<Entity>>,
+    pub index_sets: Pool<HashSet<usize>>,
+    pub entity_floats: Pool<HashMap<Entity, f32>>,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Scratch {
+            floats: Pool::new(),
+            indices: Pool::new(),
+            entities: Pool::new(),
+            index_sets: Pool::new(),
+            entity_floats: Pool::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.floats.reset();
+        self.indices.reset();
+        self.entities.reset();
+        self.index_sets.reset();
+        self.entity_floats.reset();
+    }
+}
+
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SCRATCH: RefCell<(usize, Scratch)> = RefCell::new((0, Scratch::new()));
+}
+
+// Every thread keeps its own pools, so systems borrowing buffers don't all
+// write one resource and can still run side by side. A thread resets its
+// pools the first time it borrows after a schedule run has ended.
+pub fn with_scratch<R, F: FnOnce(&mut Scratch) -> R>(f: F) -> R {
+    SCRATCH.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let (seen, scratch) = &mut *cell;
+        let generation = GENERATION.load(Ordering::Relaxed);
+
+        if *seen != generation {
+            scratch.reset();
+            *seen = generation;
+        }
+
+        f(scratch)
+    })
+}
+
+pub(crate) fn reset_scratch(_: &mut World, _: &mut Resources, _: &Receiver<LoopEvent>) {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,312 @@
+use legion::prelude::*;
+
+use std::io;
+use std::io::Read as IoRead;
+use std::io::Write as IoWrite;
+use std::mem::take;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use crate::Index;
+use crate::Settlement;
+use crate::census::Census;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+
+    msg.push(0x80);
+
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+
+        for i in 0..80 {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(w[i]);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut out = [0; 20];
+
+    for (i, word) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 512];
+
+    while !request.ends_with(b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "closed during handshake"));
+        }
+
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let key = request
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key") => Some(value.trim().to_string()),
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Sec-WebSocket-Key"))?;
+    let accept = base64(&sha1(format!("{}{}", key, GUID).as_bytes()));
+
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept)
+}
+
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x80 | opcode];
+
+    match payload.len() {
+        len if len < 126 => out.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+
+    out.extend_from_slice(payload);
+    out
+}
+
+// The text of the next frame a client sent, or None once it closes. Client
+// frames are masked; pings and other control frames are skipped.
+fn read_frame(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    loop {
+        let mut head = [0; 2];
+
+        stream.read_exact(&mut head)?;
+
+        let opcode = head[0] & 0x0F;
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as usize
+            },
+            127 => {
+                let mut len = [0; 8];
+
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len) as usize
+            },
+            len => len as usize,
+        };
+        let mut mask = [0; 4];
+
+        if head[1] & 0x80 != 0 {
+            stream.read_exact(&mut mask)?;
+        }
+
+        let mut payload = vec![0; len];
+
+        stream.read_exact(&mut payload)?;
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        match opcode {
+            0x1 => return Ok(Some(String::from_utf8_lossy(&payload).into_owned())),
+            0x8 => return Ok(None),
+            _ => continue,
+        }
+    }
+}
+
+// Each client is written to by a thread of its own, fed through a channel,
+// so a slow or stalled client only falls behind itself. What it sends comes
+// in on another thread and waits in `inbound` under its id.
+fn serve(mut stream: TcpStream, id: u32, clients: Arc<Mutex<Vec<Sender<Vec<u8>>>>>, inbound: Arc<Mutex<Vec<(u32, String)>>>, joined: Arc<AtomicUsize>) {
+    if handshake(&mut stream).is_err() {
+        return;
+    }
+
+    let (sender, frames) = channel::<Vec<u8>>();
+
+    if let Ok(mut reader) = stream.try_clone() {
+        thread::spawn(move || {
+            while let Ok(Some(text)) = read_frame(&mut reader) {
+                inbound.lock().unwrap().push((id, text));
+            }
+        });
+    }
+
+    clients.lock().unwrap().push(sender);
+    joined.fetch_add(1, Ordering::Relaxed);
+
+    for frame in frames.iter() {
+        if stream.write_all(&frame).is_err() {
+            break;
+        }
+    }
+}
+
+pub struct StateServer {
+    clients: Arc<Mutex<Vec<Sender<Vec<u8>>>>>,
+    inbound: Arc<Mutex<Vec<(u32, String)>>>,
+    joined: Arc<AtomicUsize>,
+    pub period: usize,
+    tick: usize,
+}
+
+impl StateServer {
+    // Connections are handshaken on their own threads, so one that never
+    // finishes does not hold up the ones after it.
+    pub fn bind(addr: &str, period: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let inbound = Arc::new(Mutex::new(Vec::new()));
+        let joined = Arc::new(AtomicUsize::new(0));
+        let (accepted, received, counted) = (clients.clone(), inbound.clone(), joined.clone());
+
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                if let Ok(stream) = stream {
+                    let (clients, inbound, joined) = (accepted.clone(), received.clone(), counted.clone());
+
+                    thread::spawn(move || serve(stream, id as u32, clients, inbound, joined));
+                }
+            }
+        });
+
+        Ok(StateServer {
+            clients,
+            inbound,
+            joined,
+            period,
+            tick: 0,
+        })
+    }
+
+    // A client whose writer has stopped is dropped here.
+    pub fn broadcast(&self, text: &str) {
+        self.send(frame(0x1, text.as_bytes()));
+    }
+
+    // Binary frames carry the world snapshots.
+    pub fn broadcast_bytes(&self, data: &[u8]) {
+        self.send(frame(0x2, data));
+    }
+
+    fn send(&self, frame: Vec<u8>) {
+        self.clients.lock().unwrap().retain(|client| client.send(frame.clone()).is_ok());
+    }
+
+    // How many clients have finished the handshake so far, counting those
+    // that have gone since.
+    pub fn joined(&self) -> usize {
+        self.joined.load(Ordering::Relaxed)
+    }
+
+    // Every text frame received since the last call, with the id of the
+    // client that sent it, in the order they came in.
+    pub fn receive(&self) -> Vec<(u32, String)> {
+        take(&mut *self.inbound.lock().unwrap())
+    }
+}
+
+pub fn publish_state() -> Box<dyn Schedulable> {
+    SystemBuilder::new("publish_state")
+        .read_resource::<Census>()
+        .write_resource::<StateServer>()
+        .with_query(<Read<Index>>::query().filter(tag::<Settlement>()))
+        .build(|_, world, (census, server), query| {
+            server.tick += 1;
+
+            if server.tick % server.period.max(1) != 0 {
+                return;
+            }
+
+            let settlements: Vec<String> = query
+                .iter_entities(&*world)
+                .map(|(settlement, index)| {
+                    let pop = census.get(settlement).map_or(0., |census| census.pop);
+
+                    format!("{{\"index\":{},\"pop\":{}}}", index.item, pop)
+                })
+                .collect();
+
+            server.broadcast(&format!("{{\"tick\":{},\"pop\":{},\"settlements\":[{}]}}", server.tick, census.pop, settlements.join(",")));
+        })
+}
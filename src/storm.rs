@@ -0,0 +1,121 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::f32::consts::PI;
+
+use crate::Defines;
+use crate::Index;
+use crate::Location;
+use crate::Rain;
+use crate::Sea;
+use crate::PixelGrid;
+use crate::caravan::Ship;
+use crate::caravan::Progress;
+use crate::random::WorldRng;
+
+#[derive(Clone, Deserialize)]
+pub struct StormDefines {
+    pub chance: f32,
+    pub radius: usize,
+    pub duration: usize,
+    pub sink: f32,
+    pub delay: f32,
+    pub year: usize,
+    pub seasonality: f32,
+}
+
+pub struct Storm {
+    pub center: usize,
+    pub radius: usize,
+    pub left: usize,
+}
+
+pub struct Storms {
+    pub active: Vec<Storm>,
+    pub sunk: usize,
+    tick: usize,
+}
+
+impl Storms {
+    pub fn new() -> Self {
+        Storms {
+            active: Vec::new(),
+            sunk: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn covers(&self, index: usize, size: usize) -> bool {
+        self.active.iter().any(|storm| {
+            let dx = (index % size) as isize - (storm.center % size) as isize;
+            let dy = (index / size) as isize - (storm.center / size) as isize;
+
+            dx.abs().max(dy.abs()) as usize <= storm.radius
+        })
+    }
+}
+
+// Each tick one random pixel is tried as the eye of a new storm, more likely
+// over rainy sea and in the stormy half of the year. Ships caught in a storm
+// either go down or lose `delay` of their progress.
+pub fn brew_storms(world: &mut World, resources: &mut Resources) {
+    let mut storms = match resources.get_mut::<Storms>() {
+        Some(storms) => storms,
+        None => return,
+    };
+    let defines = resources.get::<Defines>().unwrap();
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let s = &defines.storm;
+    let size = defines.size;
+    let mut rng = resources.get::<WorldRng>().unwrap().get("storms");
+
+    storms.tick += 1;
+
+    let phase = 2. * PI * (storms.tick % s.year.max(1)) as f32 / s.year.max(1) as f32;
+    let season = 1. + s.seasonality * phase.cos();
+    let eye = grid.get(rng.gen_range(0, grid.item.len()));
+
+    if world.get_tag::<Sea>(eye).is_some() {
+        let rain = world.get_component::<Rain>(eye).map_or(0., |rain| rain.item);
+
+        if rng.gen::<f32>() < s.chance * season * rain {
+            if let Some(index) = world.get_component::<Index>(eye) {
+                storms.active.push(Storm { center: index.item, radius: s.radius, left: s.duration });
+            }
+        }
+    }
+
+    for storm in storms.active.iter_mut() {
+        storm.left -= 1;
+    }
+
+    storms.active.retain(|storm| storm.left > 0);
+
+    if storms.active.is_empty() {
+        return;
+    }
+
+    let query = <Read<Location>>::query().filter(tag::<Ship>());
+    let caught: Vec<Entity> = query
+        .iter_entities(&*world)
+        .filter(|(_, location)| world.get_component::<Index>(location.item).map_or(false, |index| storms.covers(index.item, size)))
+        .map(|(ship, _)| ship)
+        .collect();
+    let mut sunk = Vec::new();
+
+    for ship in caught.into_iter() {
+        if rng.gen::<f32>() < s.sink {
+            sunk.push(ship);
+        } else if let Some(mut progress) = world.get_component_mut::<Progress>(ship) {
+            progress.item -= s.delay;
+        }
+    }
+
+    for ship in sunk.into_iter() {
+        world.delete(ship);
+        storms.sunk += 1;
+    }
+}
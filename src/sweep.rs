@@ -0,0 +1,208 @@
+use legion::prelude::*;
+use legion::systems::resource::Resources;
+use legion::systems::schedule::Schedule;
+
+use rayon::prelude::*;
+
+use serde::Deserialize;
+
+use ron::de::from_reader;
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::Defines;
+use crate::Index;
+use crate::Settlement;
+use crate::Sea;
+use crate::Veget;
+use crate::spawn_pixels;
+use crate::map::ProvBuilder;
+use crate::map::WorldGenConfig;
+use crate::climate;
+use crate::climate::Climate;
+use crate::census;
+use crate::census::Census;
+use crate::irrigation;
+use crate::guild;
+use crate::minimap;
+use crate::minimap::Minimap;
+use crate::names::Names;
+use crate::path::FlowFields;
+use crate::overlay;
+use crate::tags::TagEvents;
+use crate::symbol::Symbol;
+
+// Every combination of the listed values is run; an empty list keeps the
+// value from defines.ron, or from the worldgen config for the map values.
+#[derive(Clone, Deserialize)]
+pub struct SweepSpec {
+    #[serde(default)]
+    pub seeds: Vec<u64>,
+    #[serde(default)]
+    pub water_level: Vec<f64>,
+    #[serde(default)]
+    pub freq: Vec<f64>,
+    #[serde(default)]
+    pub temp_drift: Vec<f32>,
+    #[serde(default)]
+    pub sea_drift: Vec<f32>,
+    pub ticks: usize,
+    pub out: String,
+}
+
+#[derive(Clone, Copy)]
+struct Run {
+    id: usize,
+    seed: u64,
+    water_level: f64,
+    freq: f64,
+    temp_drift: f32,
+    sea_drift: f32,
+}
+
+struct RunStats {
+    run: Run,
+    pop: f32,
+    settlements: usize,
+    sea: usize,
+    veget: f64,
+}
+
+fn or_default<T: Copy>(values: &Vec<T>, default: T) -> Vec<T> {
+    if values.is_empty() { vec![default] } else { values.clone() }
+}
+
+impl SweepSpec {
+    fn runs(&self, defines: &Defines, config: &WorldGenConfig) -> Vec<Run> {
+        let mut runs = Vec::new();
+
+        for &seed in or_default(&self.seeds, config.seed).iter() {
+            for &water_level in or_default(&self.water_level, config.water_level).iter() {
+                for &freq in or_default(&self.freq, config.freq).iter() {
+                    for &temp_drift in or_default(&self.temp_drift, defines.temp_drift).iter() {
+                        for &sea_drift in or_default(&self.sea_drift, defines.sea_drift).iter() {
+                            runs.push(Run { id: runs.len(), seed, water_level, freq, temp_drift, sea_drift });
+                        }
+                    }
+                }
+            }
+        }
+
+        runs
+    }
+}
+
+// A run builds its own world with only the climate, irrigation, production
+// and census systems, plus the minimap for the thumbnail.
+fn simulate(run: Run, defines: &Defines, config: &WorldGenConfig, ticks: usize, out: &Path) -> Result<RunStats, String> {
+    let mut defines = defines.clone();
+
+    defines.temp_drift = run.temp_drift;
+    defines.sea_drift = run.sea_drift;
+
+    let mut config = config.clone();
+
+    config.seed = run.seed;
+    config.freq = run.freq;
+    config.water_level = run.water_level;
+
+    let mut map = ProvBuilder::with_config(&config)?;
+
+    map.terrain = defines.terrain.clone();
+    map.thermal = defines.thermal.clone();
+    map.set_noise(&defines.noise);
+    map.currents = defines.currents.clone();
+    map.glaciers = defines.glaciers.clone();
+    map.orography = defines.orography.clone();
+    map.basins = defines.basins.clone();
+    map.rivers = defines.rivers.clone();
+    map.provinces = defines.provinces.clone();
+    map.groundwater = defines.groundwater.clone();
+    map.generate();
+
+    let universe = Universe::new();
+    let mut world = universe.create_world();
+    let mut resources = Resources::default();
+    let mut names = Names::load("names.ron")?;
+
+    spawn_pixels(&mut world, &mut names, &map, &defines);
+
+    resources.insert(Climate::new(defines.temp_drift, defines.sea_drift, defines.sea_step));
+    resources.insert(Census::new(1));
+    resources.insert(FlowFields::new(defines.building_i[&Symbol::new("Road")]));
+    resources.insert(TagEvents::new());
+    resources.insert(Minimap::new(defines.size, defines.minimap_scale, 0, 0));
+    resources.insert(names);
+    resources.insert(defines.clone());
+
+    let mut schedule = Schedule::builder()
+        .add_system(irrigation::irrigation())
+        .add_system(climate::climate_drift())
+        .add_system(guild::produce())
+        .add_system(census::take_census())
+        .add_system(minimap::update_minimap())
+        .build();
+
+    for _ in 0..ticks {
+        schedule.execute(&mut world, &mut resources);
+    }
+
+    resources.get::<Minimap>().unwrap().image().save(out.join(format!("run_{}.png", run.id))).map_err(|e| e.to_string())?;
+
+    let veget = overlay::layer::<Veget>(&world, defines.size);
+
+    Ok(RunStats {
+        run,
+        pop: resources.get::<Census>().unwrap().pop,
+        settlements: Read::<Index>::query().filter(tag::<Settlement>()).iter(&world).count(),
+        sea: Read::<Index>::query().filter(tag::<Sea>()).iter(&world).count(),
+        veget: veget.iter().sum::<f64>() / veget.len().max(1) as f64,
+    })
+}
+
+// A run that fails is listed in errors.txt instead of stopping the sweep.
+pub fn sweep<P: AsRef<Path>>(spec: P, defines: &Defines) -> io::Result<()> {
+    let spec: SweepSpec = from_reader(File::open(spec)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let config = WorldGenConfig::load(&defines.worldgen).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let out = PathBuf::from(&spec.out);
+
+    fs::create_dir_all(&out)?;
+
+    let mut results: Vec<(Run, Result<RunStats, String>)> = spec
+        .runs(defines, &config)
+        .into_par_iter()
+        .map(|run| (run, simulate(run, defines, &config, spec.ticks, &out)))
+        .collect();
+
+    results.sort_by_key(|(run, _)| run.id);
+
+    let mut stats = Vec::new();
+    let mut errors = String::new();
+
+    for (run, result) in results.into_iter() {
+        match result {
+            Ok(s) => stats.push(s),
+            Err(e) => errors += &format!("run {}: {}\n", run.id, e),
+        }
+    }
+
+    if !errors.is_empty() {
+        fs::write(out.join("errors.txt"), errors)?;
+    }
+
+    let mut csv = String::from("run,seed,water_level,freq,temp_drift,sea_drift,pop,settlements,sea,veget\n");
+
+    for s in stats.iter() {
+        csv += &format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            s.run.id, s.run.seed, s.run.water_level, s.run.freq, s.run.temp_drift, s.run.sea_drift,
+            s.pop, s.settlements, s.sea, s.veget,
+        );
+    }
+
+    fs::write(out.join("stats.csv"), csv)
+}
@@ -0,0 +1,86 @@
+use serde::Serialize;
+use serde::Deserialize;
+
+use std::fmt;
+use std::sync::OnceLock;
+use std::sync::RwLock;
+use std::collections::HashMap;
+
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+
+fn interner() -> &'static RwLock<Interner> {
+    INTERNER.get_or_init(|| RwLock::new(Interner { ids: HashMap::new(), names: Vec::new() }))
+}
+
+// Interned strings are never freed, which is what lets as_str hand out
+// 'static references. Hashing and ordering go through the id, so lookups
+// never touch the interner; maps keyed by Symbol are looked up with a Symbol.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        if let Some(&id) = interner().read().unwrap().ids.get(name) {
+            return Symbol(id);
+        }
+
+        let mut interner = interner().write().unwrap();
+
+        if let Some(&id) = interner.ids.get(name) {
+            return Symbol(id);
+        }
+
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = interner.names.len() as u32;
+
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+
+        Symbol(id)
+    }
+
+    // The symbol already interned for `name`, without interning it.
+    pub fn lookup(name: &str) -> Option<Self> {
+        interner().read().unwrap().ids.get(name).map(|&id| Symbol(id))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        interner().read().unwrap().names[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::new(&name)
+    }
+}
+
+impl From<Symbol> for String {
+    fn from(symbol: Symbol) -> Self {
+        symbol.as_str().to_string()
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,103 @@
+use legion::prelude::*;
+
+use std::collections::VecDeque;
+
+use crate::Sea;
+use crate::Lake;
+use crate::Settlement;
+use crate::Colony;
+use crate::Navigable;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TagKind {
+    Sea,
+    Lake,
+    Settlement,
+    Colony,
+    Navigable,
+}
+
+pub trait Observed: Tag {
+    const KIND: TagKind;
+}
+
+impl Observed for Sea { const KIND: TagKind = TagKind::Sea; }
+impl Observed for Lake { const KIND: TagKind = TagKind::Lake; }
+impl Observed for Settlement { const KIND: TagKind = TagKind::Settlement; }
+impl Observed for Colony { const KIND: TagKind = TagKind::Colony; }
+impl Observed for Navigable { const KIND: TagKind = TagKind::Navigable; }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TagChange {
+    pub entity: Entity,
+    pub kind: TagKind,
+    pub added: bool,
+}
+
+// Each subscriber reads from its own cursor, so one that runs less often than
+// every tick still sees every change; changes every cursor has passed are
+// dropped.
+pub struct TagEvents {
+    events: VecDeque<TagChange>,
+    start: usize,
+    cursors: Vec<usize>,
+}
+
+impl TagEvents {
+    pub fn new() -> Self {
+        TagEvents {
+            events: VecDeque::new(),
+            start: 0,
+            cursors: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> usize {
+        self.cursors.push(self.start + self.events.len());
+        self.cursors.len() - 1
+    }
+
+    pub fn push(&mut self, entity: Entity, kind: TagKind, added: bool) {
+        if !self.cursors.is_empty() {
+            self.events.push_back(TagChange { entity, kind, added });
+        }
+    }
+
+    pub fn read(&mut self, subscriber: usize) -> Vec<TagChange> {
+        let from = self.cursors[subscriber] - self.start;
+        let changes = self.events.iter().skip(from).cloned().collect();
+
+        self.cursors[subscriber] = self.start + self.events.len();
+
+        let oldest = self.cursors.iter().min().cloned().unwrap_or(self.start);
+
+        self.events.drain(..oldest - self.start);
+        self.start = oldest;
+
+        changes
+    }
+}
+
+pub fn add_tag<T: Observed>(world: &mut World, events: Option<&mut TagEvents>, entity: Entity, tag: T) {
+    if world.get_tag::<T>(entity).is_some() {
+        return;
+    }
+
+    world.add_tag(entity, tag).unwrap();
+
+    if let Some(events) = events {
+        events.push(entity, T::KIND, true);
+    }
+}
+
+pub fn remove_tag<T: Observed>(world: &mut World, events: Option<&mut TagEvents>, entity: Entity) {
+    if world.get_tag::<T>(entity).is_none() {
+        return;
+    }
+
+    let _ = world.remove_tag::<T>(entity);
+
+    if let Some(events) = events {
+        events.push(entity, T::KIND, false);
+    }
+}
@@ -0,0 +1,133 @@
+use legion::prelude::*;
+
+use image::RgbImage;
+use image::Rgb;
+
+use std::io;
+use std::fs;
+use std::path::Path;
+
+use crate::Index;
+use crate::Height;
+use crate::Heat;
+use crate::Veget;
+use crate::River;
+use crate::Building;
+use crate::Sea;
+use crate::Lake;
+
+const TILE: u32 = 16;
+const RIVER: f32 = 0.1;
+const MOUNTAIN: f32 = 0.6;
+
+// Tile ids in tileset order; a gid in the map is the id plus one, zero
+// leaving the cell empty.
+const TILES: [(&str, [u8; 3]); 12] = [
+    ("Snow", [240, 240, 245]),
+    ("Tundra", [150, 160, 140]),
+    ("Desert", [225, 200, 140]),
+    ("Steppe", [190, 185, 110]),
+    ("Grassland", [120, 175, 80]),
+    ("Forest", [45, 115, 50]),
+    ("Jungle", [20, 80, 35]),
+    ("Mountain", [120, 105, 95]),
+    ("Sea", [30, 60, 140]),
+    ("Lake", [60, 110, 180]),
+    ("River", [80, 140, 210]),
+    ("Road", [140, 90, 50]),
+];
+
+fn tile(name: &str) -> u32 {
+    TILES.iter().position(|&(tile, _)| tile == name).unwrap() as u32 + 1
+}
+
+fn biome(height: f32, heat: f32, veget: f32) -> u32 {
+    let name = match () {
+        _ if height > MOUNTAIN => "Mountain",
+        _ if heat < 0.15 => "Snow",
+        _ if heat < 0.3 => "Tundra",
+        _ if veget < 0.15 => "Desert",
+        _ if veget < 0.35 => "Steppe",
+        _ if veget < 0.6 => "Grassland",
+        _ if heat < 0.75 || veget < 0.85 => "Forest",
+        _ => "Jungle",
+    };
+
+    tile(name)
+}
+
+fn layer_xml(name: &str, gids: &Vec<u32>, size: usize) -> String {
+    let rows: Vec<String> = gids
+        .chunks(size)
+        .map(|row| row.iter().map(|gid| gid.to_string()).collect::<Vec<String>>().join(","))
+        .collect();
+
+    format!(
+        " <layer name=\"{}\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n{}\n  </data>\n </layer>\n",
+        name, size, size, rows.join(",\n"),
+    )
+}
+
+fn export_tileset(dir: &Path) -> io::Result<()> {
+    let mut img = RgbImage::new(TILE * TILES.len() as u32, TILE);
+    let mut tiles = String::new();
+
+    for (i, &(name, color)) in TILES.iter().enumerate() {
+        for y in 0..TILE {
+            for x in 0..TILE {
+                img.put_pixel(i as u32 * TILE + x, y, Rgb(color));
+            }
+        }
+
+        tiles += &format!(" <tile id=\"{}\" type=\"{}\"/>\n", i, name);
+    }
+
+    img.save(dir.join("tileset.png")).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    fs::write(dir.join("tileset.tsx"), format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <tileset version=\"1.2\" name=\"prototype4\" tilewidth=\"{0}\" tileheight=\"{0}\" tilecount=\"{1}\" columns=\"{1}\">\n \
+         <image source=\"tileset.png\" width=\"{2}\" height=\"{0}\"/>\n{3}</tileset>\n",
+        TILE, TILES.len(), TILE * TILES.len() as u32, tiles,
+    ))
+}
+
+// Writes map.tmx with Terrain, Water and Roads layers over a generated
+// tileset.tsx, so each cell keeps its category instead of a blended colour.
+pub fn export_tmx<P: AsRef<Path>>(world: &World, size: usize, road: usize, dir: P) -> io::Result<()> {
+    let dir = dir.as_ref();
+
+    fs::create_dir_all(dir)?;
+    export_tileset(dir)?;
+
+    let mut terrain = vec![0; size * size];
+    let mut water = vec![0; size * size];
+    let mut roads = vec![0; size * size];
+
+    let query = <(Read<Index>, Read<Height>, Read<Heat>, Read<Veget>, Read<River>, Read<Building>)>::query();
+
+    for (index, height, heat, veget, river, building) in query.iter(world) {
+        terrain[index.item] = biome(height.item, heat.item, veget.item);
+
+        if river.item > RIVER {
+            water[index.item] = tile("River");
+        }
+        if building.item[road] > 0. {
+            roads[index.item] = tile("Road");
+        }
+    }
+
+    for index in Read::<Index>::query().filter(tag::<Lake>()).iter(world) {
+        water[index.item] = tile("Lake");
+    }
+    for index in Read::<Index>::query().filter(tag::<Sea>()).iter(world) {
+        water[index.item] = tile("Sea");
+    }
+
+    fs::write(dir.join("map.tmx"), format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <map version=\"1.2\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{0}\" height=\"{0}\" tilewidth=\"{1}\" tileheight=\"{1}\" infinite=\"0\">\n \
+         <tileset firstgid=\"1\" source=\"tileset.tsx\"/>\n{2}{3}{4}</map>\n",
+        size, TILE, layer_xml("Terrain", &terrain, size), layer_xml("Water", &water, size), layer_xml("Roads", &roads, size),
+    ))
+}
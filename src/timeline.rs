@@ -0,0 +1,129 @@
+use legion::prelude::*;
+
+use image::RgbaImage;
+use image::Rgba;
+use image::Frame;
+use image::gif::GifEncoder;
+
+use serde::Serialize;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::Index;
+use crate::Owned;
+use crate::Pixel;
+use crate::Settlement;
+use crate::census::Census;
+use crate::map::owner_color;
+
+pub struct Snapshot {
+    pub tick: usize,
+    pub pop: f32,
+    pub settlements: usize,
+    pub owners: Vec<Option<u32>>,
+}
+
+// The statistics of every snapshot ever taken, kept after the snapshot itself
+// has been dropped for capacity.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stat {
+    pub tick: usize,
+    pub pop: f32,
+    pub settlements: usize,
+}
+
+pub struct Timeline {
+    pub snapshots: VecDeque<Snapshot>,
+    pub stats: Vec<Stat>,
+    pub period: usize,
+    pub capacity: usize,
+    tick: usize,
+}
+
+impl Timeline {
+    pub fn new(period: usize, capacity: usize) -> Self {
+        Timeline {
+            snapshots: VecDeque::new(),
+            stats: Vec::new(),
+            period,
+            capacity,
+            tick: 0,
+        }
+    }
+
+    pub fn at(&self, tick: usize) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|snapshot| snapshot.tick <= tick)
+    }
+
+    pub fn render(&self, snapshot: &Snapshot, size: usize) -> RgbaImage {
+        let mut img = RgbaImage::new(size as u32, size as u32);
+
+        for (i, owner) in snapshot.owners.iter().enumerate() {
+            let color = match owner {
+                Some(owner) => {
+                    let color = owner_color(*owner as usize);
+
+                    Rgba([color.0[0], color.0[1], color.0[2], 255])
+                },
+                None => Rgba([0, 0, 0, 255]),
+            };
+
+            img.put_pixel((i % size) as u32, (i / size) as u32, color);
+        }
+
+        img
+    }
+
+    pub fn export_gif<T: Into<PathBuf>>(&self, size: usize, path: T) {
+        let mut encoder = GifEncoder::new(File::create(path.into()).unwrap());
+
+        encoder.encode_frames(self.snapshots.iter().map(|snapshot| Frame::new(self.render(snapshot, size)))).unwrap();
+    }
+}
+
+pub fn record_timeline() -> Box<dyn Schedulable> {
+    SystemBuilder::new("record_timeline")
+        .read_resource::<Census>()
+        .write_resource::<Timeline>()
+        .with_query(<Read<Index>>::query().filter(tag::<Settlement>()))
+        .with_query(<(Read<Index>, TryRead<Owned>)>::query().filter(tag::<Pixel>()))
+        .build(|_, world, (census, timeline), (settlement_query, pixel_query)| {
+            timeline.tick += 1;
+
+            if timeline.tick % timeline.period.max(1) != 0 {
+                return;
+            }
+
+            let indices: HashMap<Entity, u32> = settlement_query
+                .iter_entities(&*world)
+                .map(|(settlement, index)| (settlement, index.item as u32))
+                .collect();
+            let mut owners = Vec::new();
+
+            for (index, owned) in pixel_query.iter(&*world) {
+                if owners.len() <= index.item {
+                    owners.resize(index.item + 1, None);
+                }
+
+                owners[index.item] = owned.and_then(|owned| indices.get(&owned.item).cloned());
+            }
+
+            if timeline.snapshots.len() >= timeline.capacity {
+                timeline.snapshots.pop_front();
+            }
+
+            let tick = timeline.tick;
+
+            timeline.stats.push(Stat { tick, pop: census.pop, settlements: census.settlements.len() });
+            timeline.snapshots.push_back(Snapshot {
+                tick,
+                pop: census.pop,
+                settlements: census.settlements.len(),
+                owners,
+            });
+        })
+}
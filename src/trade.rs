@@ -0,0 +1,244 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::Defines;
+use crate::Index;
+use crate::PixelGrid;
+use crate::Sea;
+use crate::Navigable;
+use crate::Settlement;
+use crate::guild::Stock;
+use crate::market::Market;
+use crate::market::Price;
+use crate::market::Demand;
+use crate::caravan::Order;
+use crate::caravan::plan_route;
+use crate::caravan::pick_cargo;
+use crate::caravan::spawn_caravan;
+use crate::path::PathRequests;
+use crate::path::PathResult;
+
+#[derive(Clone, Deserialize)]
+pub struct TradeDefines {
+    pub weight: f32,
+    pub spoilage: f32,
+    pub value: f32,
+}
+
+// Per good, in good_i order; a good the data says nothing about weighs one,
+// is worth one and keeps forever.
+pub struct Trade {
+    pub weight: Vec<f32>,
+    pub spoilage: Vec<f32>,
+    pub value: Vec<f32>,
+}
+
+impl Trade {
+    pub fn new(defines: &Defines) -> Self {
+        let goods = defines.good_i.len();
+        let mut trade = Trade {
+            weight: vec![1.; goods],
+            spoilage: vec![0.; goods],
+            value: vec![1.; goods],
+        };
+
+        for (good, t) in defines.trade.iter() {
+            if let Some(&g) = defines.good_i.get(good) {
+                trade.weight[g] = t.weight.max(1e-3);
+                trade.spoilage[g] = t.spoilage.max(0.).min(1.);
+                trade.value[g] = t.value;
+            }
+        }
+
+        trade
+    }
+
+    pub fn density(&self, g: usize) -> f32 {
+        self.value[g] / self.weight[g]
+    }
+
+    // Share of a good still sound after `ticks` on the road.
+    pub fn kept(&self, g: usize, ticks: f32) -> f32 {
+        (1. - self.spoilage[g]).powf(ticks)
+    }
+
+    pub fn weight_of(&self, cargo: &Vec<f32>) -> f32 {
+        cargo.iter().zip(self.weight.iter()).map(|(amount, weight)| amount * weight).sum()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CaravanDefines {
+    pub period: usize,
+    pub speed: f32,
+    pub capacity: f32,
+    pub bandits: f32,
+    pub pirates: f32,
+    pub reach: f32,
+    pub loot: f32,
+}
+
+pub struct Dispatch {
+    pub period: usize,
+    pub sent: usize,
+    pub raided: usize,
+    tick: usize,
+}
+
+impl Dispatch {
+    pub fn new(period: usize) -> Self {
+        Dispatch {
+            period,
+            sent: 0,
+            raided: 0,
+            tick: 0,
+        }
+    }
+}
+
+// What a full load would earn at `to`, before spoilage, which is only worth
+// reckoning once the route is known.
+fn gain(stock: &Vec<f32>, from: &Vec<f32>, to: &Vec<f32>, capacity: f32) -> f32 {
+    (0..stock.len()).map(|g| stock[g].max(0.).min(capacity) * (to[g] - from[g]).max(0.)).sum()
+}
+
+// Every `period` ticks each settlement orders a load for whichever of its
+// trading partners pays the most for what it holds: members of a market zone
+// trade with the zone's hub, and hubs with the hubs of adjacent zones, and
+// anyone with settlements of its own zone whose guilds lack inputs. River
+// and sea ports ask for a route by water, anyone else for one by land; the
+// load sets out once depart_caravans has the route.
+pub fn dispatch_caravans(world: &mut World, resources: &mut Resources) {
+    let mut dispatch = match resources.get_mut::<Dispatch>() {
+        Some(dispatch) => dispatch,
+        None => return,
+    };
+
+    dispatch.tick += 1;
+
+    if dispatch.period == 0 || dispatch.tick % dispatch.period != 0 {
+        return;
+    }
+
+    let (market, grid, mut requests) = match (resources.get::<Market>(), resources.get::<PixelGrid>(), resources.get_mut::<PathRequests>()) {
+        (Some(market), Some(grid), Some(requests)) => (market, grid, requests),
+        _ => return,
+    };
+    let defines = resources.get::<Defines>().unwrap();
+    let c = &defines.caravans;
+    let hubs: HashMap<Entity, usize> = market.hubs.iter().enumerate().map(|(z, &hub)| (hub, z)).collect();
+
+    let wanting: Vec<(Entity, usize)> = <Read<Demand>>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(&*world)
+        .filter(|(_, demand)| demand.item.iter().any(|&need| need > 0.))
+        .filter_map(|(buyer, _)| market.zone.get(&buyer).map(|&z| (buyer, z)))
+        .collect();
+    let sellers: Vec<(Entity, Vec<f32>, Vec<f32>)> = <(Read<Stock>, Read<Price>)>::query()
+        .filter(tag::<Settlement>())
+        .iter_entities(&*world)
+        .map(|(seller, (stock, price))| (seller, stock.item.clone(), price.item.clone()))
+        .collect();
+
+    for (seller, stock, from) in sellers.into_iter() {
+        let z = match market.zone.get(&seller) {
+            Some(&z) => z,
+            None => continue,
+        };
+        let mut partners: Vec<Entity> = match hubs.contains_key(&seller) {
+            true => market.adjacent[z].iter().map(|&a| market.hubs[a]).collect(),
+            false => vec![market.hubs[z]],
+        };
+
+        partners.extend(wanting.iter().filter(|&&(buyer, w)| w == z && buyer != seller && buyer != market.hubs[z]).map(|&(buyer, _)| buyer));
+        let best = partners
+            .into_iter()
+            .filter_map(|buyer| world.get_component::<Price>(buyer).map(|price| (buyer, price.item.clone())))
+            .map(|(buyer, to)| (gain(&stock, &from, &to, c.capacity), buyer, to))
+            .filter(|&(gain, _, _)| gain > 0.)
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let buyer = match best {
+            Some((_, buyer, _)) => buyer,
+            None => continue,
+        };
+
+        let coast = world.get_component::<Index>(seller).map_or(false, |index| {
+            grid.neighb(world, index.item).iter().any(|next| world.get_tag::<Sea>(grid.get(next)).is_some())
+        });
+        let port = coast || world.get_tag::<Navigable>(seller).is_some();
+        let order = world.insert((), vec![(Order { from: seller, to: buyer, by_sea: port },)])[0];
+
+        if !plan_route(world, &mut requests, order, seller, buyer, port) {
+            world.delete(order);
+        }
+    }
+}
+
+// An order whose route has come back sets out with whatever pays best over
+// a route that long. A ship with no way by water asks again by land, and an
+// order with no route at all is dropped.
+pub fn depart_caravans(world: &mut World, resources: &mut Resources) {
+    let answered: Vec<(Entity, Entity, Entity, bool, Option<Vec<Entity>>)> = <(Read<Order>, Read<PathResult>)>::query()
+        .iter_entities(&*world)
+        .map(|(order, (o, result))| (order, o.from, o.to, o.by_sea, result.item.clone()))
+        .collect();
+
+    if answered.is_empty() {
+        return;
+    }
+
+    let defines = resources.get::<Defines>().unwrap();
+    let c = &defines.caravans;
+    let trade = Trade::new(&defines);
+    let mut dispatch = resources.get_mut::<Dispatch>();
+    let mut requests = resources.get_mut::<PathRequests>();
+
+    for (order, seller, buyer, by_sea, route) in answered.into_iter() {
+        let route = match (route, requests.as_deref_mut()) {
+            (Some(route), _) => route,
+            (None, Some(requests)) if by_sea => {
+                world.remove_component::<PathResult>(order).ok();
+
+                if let Some(mut order) = world.get_component_mut::<Order>(order) {
+                    order.by_sea = false;
+                }
+                if !plan_route(world, requests, order, seller, buyer, false) {
+                    world.delete(order);
+                }
+
+                continue;
+            }
+            _ => {
+                world.delete(order);
+                continue;
+            }
+        };
+
+        world.delete(order);
+
+        let (stock, from, to) = match (world.get_component::<Stock>(seller), world.get_component::<Price>(seller), world.get_component::<Price>(buyer)) {
+            (Some(stock), Some(from), Some(to)) => (stock.item.clone(), from.item.clone(), to.item.clone()),
+            _ => continue,
+        };
+        let cargo = pick_cargo(&trade, &stock, &from, &to, c.capacity, route.len() as f32 / c.speed.max(1e-3));
+
+        if cargo.iter().all(|&amount| amount <= 0.) {
+            continue;
+        }
+
+        if let Some(mut stock) = world.get_component_mut::<Stock>(seller) {
+            for (g, amount) in cargo.iter().enumerate() {
+                stock.item[g] -= amount;
+            }
+        }
+
+        spawn_caravan(world, seller, route, by_sea, c.speed, c.capacity, cargo);
+
+        if let Some(dispatch) = dispatch.as_deref_mut() {
+            dispatch.sent += 1;
+        }
+    }
+}
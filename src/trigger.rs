@@ -0,0 +1,139 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use rand::Rng;
+
+use std::collections::HashMap;
+
+use crate::Index;
+use crate::Settlement;
+use crate::Defines;
+use crate::PixelGrid;
+use crate::symbol::Symbol;
+use crate::condition::Condition;
+use crate::modifier::Modifier;
+use crate::modifier::Modifiers;
+use crate::modifier::add_modifier;
+use crate::ruins::Chronicle;
+use crate::ruins::site_name;
+use crate::random::WorldRng;
+
+fn certain() -> f32 {
+    1.
+}
+
+// Fires when a chronicle entry of kind `on` is written, for each settlement
+// within `range` of its site that meets `requires` and does not already carry
+// the event's modifiers. `text` is written back under the event's own name,
+// with "{site}" standing for the settlement, so events can follow on events.
+#[derive(Clone, Deserialize)]
+pub struct EventDefines {
+    pub name: Symbol,
+    pub on: Symbol,
+    pub text: String,
+    #[serde(default = "certain")]
+    pub chance: f32,
+    #[serde(default)]
+    pub requires: Option<Condition>,
+    #[serde(default)]
+    pub range: usize,
+    #[serde(default)]
+    pub modifiers: HashMap<Symbol, f32>,
+    #[serde(default)]
+    pub duration: Option<u32>,
+}
+
+pub struct Triggers {
+    pub fired: usize,
+    on: HashMap<Symbol, Vec<usize>>,
+    read: usize,
+}
+
+impl Triggers {
+    pub fn new(events: &Vec<EventDefines>) -> Self {
+        let mut on = HashMap::new();
+
+        for (e, event) in events.iter().enumerate() {
+            on.entry(event.on).or_insert_with(Vec::new).push(e);
+        }
+
+        Triggers {
+            fired: 0,
+            on,
+            read: 0,
+        }
+    }
+}
+
+// Entries written while events fire are left for the next tick, so a chain
+// moves one link a tick however long it is.
+pub fn fire_events(world: &mut World, resources: &mut Resources) {
+    let (mut triggers, mut chronicle) = match (resources.get_mut::<Triggers>(), resources.get_mut::<Chronicle>()) {
+        (Some(triggers), Some(chronicle)) => (triggers, chronicle),
+        _ => return,
+    };
+    let defines = resources.get::<Defines>().unwrap();
+    let grid = resources.get::<PixelGrid>().unwrap();
+    let size = defines.size as isize;
+    let mut rng = resources.get::<WorldRng>().unwrap().get("events");
+
+    let read = triggers.read.min(chronicle.entries.len());
+    let written: Vec<(Symbol, usize)> = chronicle.entries[read..].iter().map(|entry| (entry.kind, entry.site)).collect();
+
+    triggers.read = chronicle.entries.len();
+
+    let mut fired = Vec::new();
+
+    for (kind, site) in written.into_iter() {
+        let events = match triggers.on.get(&kind) {
+            Some(events) => events,
+            None => continue,
+        };
+
+        for &e in events.iter() {
+            let event = &defines.events[e];
+
+            if rng.gen::<f32>() >= event.chance {
+                continue;
+            }
+
+            let range = event.range as isize;
+            let (x, y) = (site as isize % size, site as isize / size);
+
+            for yy in (y - range).max(0)..(y + range + 1).min(size) {
+                for xx in (x - range).max(0)..(x + range + 1).min(size) {
+                    let target = grid.item[(yy * size + xx) as usize];
+
+                    if world.get_tag::<Settlement>(target).is_none() {
+                        continue;
+                    }
+                    if world.get_component::<Modifiers>(target).map_or(false, |modifiers| modifiers.from(event.name)) {
+                        continue;
+                    }
+                    if fired.contains(&(target, e)) {
+                        continue;
+                    }
+                    if event.requires.as_ref().map_or(true, |requires| requires.eval(&*world, &defines, target)) {
+                        fired.push((target, e));
+                    }
+                }
+            }
+        }
+    }
+
+    for (target, e) in fired.into_iter() {
+        let event = &defines.events[e];
+        let index = match world.get_component::<Index>(target) {
+            Some(index) => index.item,
+            None => continue,
+        };
+
+        for (&stat, &factor) in event.modifiers.iter() {
+            add_modifier(world, target, Modifier { stat, factor, left: event.duration, source: event.name });
+        }
+
+        chronicle.record(index, event.name, event.text.replace("{site}", &site_name(world, target)));
+        triggers.fired += 1;
+    }
+}
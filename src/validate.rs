@@ -0,0 +1,174 @@
+use legion::prelude::*;
+
+use serde::Deserialize;
+
+use std::fs;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use crate::export::ExportManager;
+use crate::tags::TagEvents;
+use crate::tags::TagChange;
+use crate::writer::Writer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Strictness {
+    Off,
+    Report,
+    Panic,
+}
+
+// Unless `release` is set the checks only run in debug builds.
+#[derive(Clone, Deserialize)]
+pub struct ValidateDefines {
+    pub period: usize,
+    pub strictness: Strictness,
+    pub release: bool,
+    pub epsilon: f32,
+    pub history: usize,
+    pub path: String,
+}
+
+pub struct Breach {
+    pub entity: Option<Entity>,
+    pub message: String,
+}
+
+pub struct Invariant {
+    pub name: &'static str,
+    pub check: fn(&World, &Resources, f32) -> Vec<Breach>,
+}
+
+pub struct Report {
+    pub tick: u64,
+    pub invariant: &'static str,
+    pub entity: Option<Entity>,
+    pub message: String,
+    pub recent: Vec<(u64, TagChange)>,
+}
+
+// Every `period` ticks each registered invariant is checked. A breach is
+// reported with the recent tag changes of its entity, or all of them when it
+// names none; under Panic the first breach stops the simulation instead.
+pub struct Validator {
+    pub period: usize,
+    pub strictness: Strictness,
+    pub epsilon: f32,
+    pub history: usize,
+    pub path: PathBuf,
+    pub invariants: Vec<Invariant>,
+    pub reports: Vec<Report>,
+    recent: VecDeque<(u64, TagChange)>,
+    subscriber: Option<usize>,
+    tick: u64,
+}
+
+impl Validator {
+    pub fn new(defines: &ValidateDefines, exports: &mut ExportManager) -> Self {
+        let strictness = match cfg!(debug_assertions) || defines.release {
+            true => defines.strictness,
+            false => Strictness::Off,
+        };
+
+        Validator {
+            period: defines.period.max(1),
+            strictness,
+            epsilon: defines.epsilon,
+            history: defines.history,
+            path: exports.path("validation", &defines.path),
+            invariants: Vec::new(),
+            reports: Vec::new(),
+            recent: VecDeque::new(),
+            subscriber: None,
+            tick: 0,
+        }
+    }
+
+    pub fn register(mut self, name: &'static str, check: fn(&World, &Resources, f32) -> Vec<Breach>) -> Self {
+        self.invariants.push(Invariant { name, check });
+        self
+    }
+
+    fn recent_for(&self, entity: Option<Entity>) -> Vec<(u64, TagChange)> {
+        self.recent
+            .iter()
+            .filter(|(_, change)| entity.map_or(true, |entity| change.entity == entity))
+            .cloned()
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+
+        for report in self.reports.iter() {
+            out += &format!("tick {} {} {:?}: {}\n", report.tick, report.invariant, report.entity, report.message);
+
+            for (tick, change) in report.recent.iter() {
+                out += &format!("    tick {} {:?} {:?} {}\n", tick, change.entity, change.kind, if change.added { "added" } else { "removed" });
+            }
+        }
+
+        out
+    }
+}
+
+pub fn validate(world: &mut World, resources: &mut Resources) {
+    let mut validator = match resources.get_mut::<Validator>() {
+        Some(validator) => validator,
+        None => return,
+    };
+
+    if validator.strictness == Strictness::Off {
+        return;
+    }
+
+    validator.tick += 1;
+
+    if let Some(mut events) = resources.get_mut::<TagEvents>() {
+        let subscriber = *validator.subscriber.get_or_insert_with(|| events.subscribe());
+        let tick = validator.tick;
+
+        for change in events.read(subscriber) {
+            validator.recent.push_back((tick, change));
+        }
+        while validator.recent.len() > validator.history {
+            validator.recent.pop_front();
+        }
+    }
+
+    if validator.tick % validator.period as u64 != 0 {
+        return;
+    }
+
+    let mut reports = Vec::new();
+
+    for invariant in validator.invariants.iter() {
+        for breach in (invariant.check)(world, resources, validator.epsilon) {
+            reports.push(Report {
+                tick: validator.tick,
+                invariant: invariant.name,
+                entity: breach.entity,
+                message: breach.message,
+                recent: validator.recent_for(breach.entity),
+            });
+        }
+    }
+
+    if reports.is_empty() {
+        return;
+    }
+
+    validator.reports.extend(reports);
+
+    if validator.strictness == Strictness::Panic {
+        panic!("invariant broken:\n{}", validator.text());
+    }
+
+    let text = validator.text();
+    let path = validator.path.clone();
+
+    match resources.get::<Writer>() {
+        Some(writer) => writer.submit(move || fs::write(path, text)),
+        None => fs::write(path, text).unwrap(),
+    }
+}
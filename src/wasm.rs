@@ -0,0 +1,72 @@
+use wasm_bindgen::prelude::*;
+
+use crate::map::ProvBuilder;
+use crate::map::WrapMode;
+use crate::map::NoiseKind;
+use crate::colormap::Colormap;
+
+#[wasm_bindgen]
+pub struct MapPreview {
+    builder: ProvBuilder,
+}
+
+#[wasm_bindgen]
+impl MapPreview {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        size: usize, freq: f64, pers: f64, lac: f64, min: f64, max: f64, water_level: f64, water_taper: f64,
+        lat_start: f64, lat_end: f64, seed: u64, wrap: &str, noise: &str,
+    ) -> Self {
+        let wrap = match wrap {
+            "WrapX" => WrapMode::WrapX,
+            "Torus" => WrapMode::Torus,
+            _ => WrapMode::None,
+        };
+        let kind = match noise {
+            "OpenSimplex" => NoiseKind::OpenSimplex,
+            "Ridged" => NoiseKind::Ridged,
+            "Billow" => NoiseKind::Billow,
+            "Warped" => NoiseKind::Warped,
+            _ => NoiseKind::Perlin,
+        };
+
+        MapPreview {
+            builder: ProvBuilder::new(size, freq, pers, lac, min, max, water_level, water_taper, lat_start, lat_end, seed, wrap, kind),
+        }
+    }
+
+    pub fn generate(&mut self) {
+        self.builder.generate();
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.builder.seed
+    }
+
+    pub fn size(&self) -> usize {
+        self.builder.size
+    }
+
+    // Returns size * size * 4 bytes, ready for an ImageData, or an empty
+    // buffer for an unknown layer name.
+    pub fn render(&self, layer: &str, colormap: &str) -> Vec<u8> {
+        let colormap = match colormap {
+            "Viridis" => Colormap::Viridis,
+            "Diverging" => Colormap::Diverging,
+            "Terrain" => Colormap::Terrain,
+            _ => Colormap::Grayscale,
+        };
+        let (values, min, max) = match layer {
+            "Height" => (&self.builder.heightmap, 0., 1.),
+            "Insolation" => (&self.builder.insolation, 0., 1.),
+            "Cloud" => (&self.builder.cloudmap, 0., 1.),
+            "Temp" => (&self.builder.tempmap, 0., 1.),
+            "River" => (&self.builder.rivermap, 0., 1.),
+            "Water" => (&self.builder.watermap, 0., 1.),
+            "Veget" => (&self.builder.vegetmap, 0., 1.),
+            _ => return Vec::new(),
+        };
+
+        self.builder.render_rgba(values, min, max, colormap)
+    }
+}
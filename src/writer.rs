@@ -0,0 +1,67 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::channel;
+
+type Job = Box<dyn FnOnce() -> io::Result<()> + Send>;
+
+// Disk writes queued from the simulation run in order on one thread of their
+// own, so a tick only pays for getting the data ready to write.
+pub struct Writer {
+    jobs: Mutex<Sender<Job>>,
+    pending: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel::<Job>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+
+        {
+            let pending = pending.clone();
+            let failed = failed.clone();
+
+            thread::spawn(move || {
+                for job in receiver {
+                    if job().is_err() {
+                        failed.fetch_add(1, Ordering::SeqCst);
+                    }
+
+                    pending.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+
+        Writer {
+            jobs: Mutex::new(sender),
+            pending,
+            failed,
+        }
+    }
+
+    pub fn submit<F: FnOnce() -> io::Result<()> + Send + 'static>(&self, job: F) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.jobs.lock().unwrap().send(Box::new(job)).unwrap();
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    pub fn wait(&self) {
+        while self.pending() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
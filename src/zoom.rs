@@ -0,0 +1,68 @@
+use legion::prelude::*;
+
+use prototype4::pyramid::Pyramid;
+use prototype4::pyramid::Reduce;
+
+use crate::Index;
+use crate::Height;
+use crate::Owned;
+use crate::Pop;
+use crate::Pixel;
+use crate::map::owner_color;
+
+// Political cells hold the owner's pixel index plus one, zero being unowned;
+// density is the population living on each pixel.
+pub struct Pyramids {
+    pub height: Pyramid,
+    pub political: Pyramid,
+    pub density: Pyramid,
+}
+
+impl Pyramids {
+    pub fn new(size: usize) -> Self {
+        Pyramids {
+            height: Pyramid::new(size, Reduce::Mean),
+            political: Pyramid::new(size, Reduce::Mode),
+            density: Pyramid::new(size, Reduce::Mean),
+        }
+    }
+
+    pub fn political_rgba(&self, level: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.political.levels[level].len() * 4);
+
+        for &owner in self.political.levels[level].iter() {
+            match owner as usize {
+                0 => out.extend_from_slice(&[0, 0, 0, 0]),
+                owner => {
+                    let color = owner_color(owner - 1);
+
+                    out.extend_from_slice(&[color.0[0], color.0[1], color.0[2], 255]);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+pub fn update_pyramids() -> Box<dyn Schedulable> {
+    SystemBuilder::new("update_pyramids")
+        .write_resource::<Pyramids>()
+        .read_component::<Index>()
+        .with_query(<(Read<Index>, Read<Height>, TryRead<Owned>, TryRead<Pop>)>::query().filter(tag::<Pixel>()))
+        .build(|_, world, pyramids, query| {
+            for (index, height, owned, pop) in query.iter(&*world) {
+                let owner = owned
+                    .and_then(|owned| world.get_component::<Index>(owned.item))
+                    .map_or(0., |owner| (owner.item + 1) as f32);
+
+                pyramids.height.set(index.item, height.item);
+                pyramids.political.set(index.item, owner);
+                pyramids.density.set(index.item, pop.map_or(0., |pop| pop.item));
+            }
+
+            pyramids.height.update();
+            pyramids.political.update();
+            pyramids.density.update();
+        })
+}